@@ -1,7 +1,11 @@
 pub mod app;
+pub mod complexity;
 pub mod crypt;
 pub mod db;
 pub mod env;
+pub mod exclude;
+pub mod git;
+pub mod loc;
 pub mod safe;
 
 pub const API_VERSION: i8 = 0;
@@ -1,4 +1,7 @@
 pub mod admin;
+pub mod analysis;
+pub mod audit;
 pub mod models;
+pub mod outbox;
 pub mod schema;
 pub mod state;
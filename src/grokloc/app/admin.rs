@@ -1,2 +1,13 @@
+pub mod archive;
+pub mod invitation;
+pub mod language;
 pub mod org;
+pub mod personal;
+pub mod provision;
+pub mod repository;
+pub mod role;
+pub mod settings;
+pub mod subproject;
+pub mod team;
+pub mod token;
 pub mod user;
@@ -0,0 +1,890 @@
+//! archive exports an org with everything it owns into a portable document,
+//! and imports such a document into another environment
+use crate::grokloc::app::admin::org;
+use crate::grokloc::app::admin::org::Org;
+use crate::grokloc::app::admin::repository;
+use crate::grokloc::app::admin::repository::Repository;
+use crate::grokloc::app::admin::role;
+use crate::grokloc::app::admin::settings;
+use crate::grokloc::app::admin::user;
+use crate::grokloc::app::admin::user::User;
+use crate::grokloc::app::audit;
+use crate::grokloc::app::models;
+use crate::grokloc::app::schema;
+use crate::grokloc::crypt;
+use crate::grokloc::db;
+use crate::grokloc::safe;
+use anyhow;
+use serde::{Deserialize, Serialize};
+use sqlx;
+use std::collections::HashMap;
+use thiserror::Error;
+use uuid::Uuid;
+
+/// ARCHIVE_VERSION is the version of the archive layout
+pub const ARCHIVE_VERSION: i8 = 0;
+
+pub const SELECT_ACTOR_ORG_QUERY: &str = r#"
+select org from users where id = ?
+"#;
+
+pub const SELECT_ORG_QUERY: &str = r#"
+select name, owner, status from orgs where id = ?
+"#;
+
+pub const SELECT_SETTINGS_QUERY: &str = r#"
+select name, kind, value from org_settings where org = ? order by name
+"#;
+
+pub const SELECT_USERS_QUERY: &str = r#"
+select
+ id,
+ api_secret,
+ api_secret_digest,
+ display_name,
+ display_name_digest,
+ email,
+ email_digest,
+ password,
+ role,
+ status
+from users
+where org = ?
+order by ctime, id
+"#;
+
+pub const SELECT_REPOSITORIES_QUERY: &str = r#"
+select id, name, upstream, status from repositories where org = ? order by ctime, id
+"#;
+
+pub const SELECT_CASCADE_QUERY: &str = r#"
+select source, source_id, prior_status from status_cascade
+where org = ?
+order by source, source_id
+"#;
+
+pub const SELECT_AUDIT_QUERY: &str = r#"
+select id, code, source, source_id, ctime from audit
+where (source = ? and source_id = ?)
+or (source = ? and source_id in (select id from users where org = ?))
+or (source = ? and source_id in (select id from repositories where org = ?))
+order by ctime, id
+"#;
+
+pub const INSERT_SETTING_QUERY: &str = r#"
+insert into org_settings
+(id,
+ org,
+ name,
+ kind,
+ value)
+values
+(?,?,?,?,?)
+"#;
+
+pub const INSERT_CASCADE_QUERY: &str = r#"
+insert into status_cascade
+(id,
+ org,
+ source,
+ source_id,
+ prior_status)
+values
+(?,?,?,?,?)
+"#;
+
+pub const INSERT_AUDIT_QUERY: &str = r#"
+insert into audit
+(id,
+ code,
+ source,
+ source_id)
+values
+(?,?,?,?)
+"#;
+
+pub const UPDATE_AUDIT_CTIME_QUERY: &str = r#"
+update audit set ctime = ? where id = ?
+"#;
+
+/// Err covers archive errors
+#[derive(Debug, Error, PartialEq)]
+pub enum Err {
+    #[error("unsupported archive version")]
+    Version,
+    #[error("archive references unknown id")]
+    UnknownId,
+}
+
+/// Archive is the portable representation of an org; user PII is encrypted
+/// under the export key, and statuses and roles are in db representation
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Archive {
+    pub version: i8,
+    pub org: ArchiveOrg,
+    pub settings: Vec<ArchiveSetting>,
+    pub users: Vec<ArchiveUser>,
+    pub repositories: Vec<ArchiveRepository>,
+    /// the statuses to restore when an Inactive org is reactivated
+    #[serde(default)]
+    pub cascade: Vec<ArchiveCascade>,
+    pub audit: Vec<ArchiveAudit>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ArchiveOrg {
+    pub id: String,
+    pub name: String,
+    pub owner: String,
+    pub status: i64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ArchiveSetting {
+    pub name: String,
+    pub kind: i64,
+    pub value: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, sqlx::FromRow)]
+pub struct ArchiveUser {
+    pub id: String,
+    pub api_secret: String,
+    pub api_secret_digest: String,
+    pub display_name: String,
+    pub display_name_digest: String,
+    pub email: String,
+    pub email_digest: String,
+    pub password: String,
+    pub role: i64,
+    pub status: i64,
+}
+
+/// ArchiveRepository omits the local clone path, which is environment specific
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ArchiveRepository {
+    pub id: String,
+    pub name: String,
+    pub upstream: String,
+    pub status: i64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ArchiveCascade {
+    pub source: String,
+    pub source_id: String,
+    pub prior_status: i64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ArchiveAudit {
+    pub id: String,
+    pub code: i64,
+    pub source: String,
+    pub source_id: String,
+    pub ctime: i64,
+}
+
+/// rekey decrypts c under from_key and encrypts it under to_key, with the same iv
+fn rekey(from_key: &str, to_key: &str, iv: &str, c: &str) -> Result<String, crypt::Err> {
+    crypt::encrypt(to_key, iv, &crypt::decrypt(from_key, iv, c)?)
+}
+
+impl ArchiveUser {
+    /// rekeyed re-encrypts the user PII from from_key to to_key, and
+    /// replaces the api secret with a new random one, so that a leaked
+    /// archive holds no usable credential; erased users carry no PII and
+    /// are copied as-is
+    fn rekeyed(&self, from_key: &str, to_key: &str) -> Result<Self, anyhow::Error> {
+        if models::Status::from_int(self.status)? == models::Status::Deleted {
+            return Ok(self.clone());
+        }
+        let iv = crypt::iv(&self.email_digest);
+        let api_secret = Uuid::new_v4().to_string();
+        Ok(ArchiveUser {
+            api_secret: crypt::encrypt(to_key, &iv, &api_secret)?,
+            api_secret_digest: crypt::sha256_hex(&api_secret),
+            display_name: rekey(from_key, to_key, &iv, &self.display_name)?,
+            email: rekey(from_key, to_key, &iv, &self.email)?,
+            ..self.clone()
+        })
+    }
+}
+
+impl Archive {
+    /// export reads the org id and everything it owns into an Archive, with
+    /// user PII re-encrypted from key to export_key
+    ///
+    /// actor must be permitted to update the org, which may be Inactive
+    #[allow(dead_code)]
+    pub async fn export(
+        pool: &sqlx::SqlitePool,
+        actor: &Uuid,
+        id: &Uuid,
+        key: &str,
+        export_key: &str,
+    ) -> Result<Self, anyhow::Error> {
+        role::check_update_org(pool, actor, id).await?;
+
+        // read in one transaction for a consistent snapshot
+        let mut txn = pool.begin().await?;
+
+        let (name, owner, status): (String, String, i64) = sqlx::query_as(SELECT_ORG_QUERY)
+            .bind(id.to_string())
+            .fetch_one(&mut txn)
+            .await?;
+
+        let settings_rows: Vec<(String, i64, String)> = sqlx::query_as(SELECT_SETTINGS_QUERY)
+            .bind(id.to_string())
+            .fetch_all(&mut txn)
+            .await?;
+
+        let user_rows: Vec<ArchiveUser> = sqlx::query_as(SELECT_USERS_QUERY)
+            .bind(id.to_string())
+            .fetch_all(&mut txn)
+            .await?;
+
+        let repository_rows: Vec<(String, String, String, i64)> =
+            sqlx::query_as(SELECT_REPOSITORIES_QUERY)
+                .bind(id.to_string())
+                .fetch_all(&mut txn)
+                .await?;
+
+        let cascade_rows: Vec<(String, String, i64)> = sqlx::query_as(SELECT_CASCADE_QUERY)
+            .bind(id.to_string())
+            .fetch_all(&mut txn)
+            .await?;
+
+        let audit_rows: Vec<(String, i64, String, String, i64)> =
+            sqlx::query_as(SELECT_AUDIT_QUERY)
+                .bind(schema::ORGS_TABLENAME)
+                .bind(id.to_string())
+                .bind(schema::USERS_TABLENAME)
+                .bind(id.to_string())
+                .bind(schema::REPOSITORIES_TABLENAME)
+                .bind(id.to_string())
+                .fetch_all(&mut txn)
+                .await?;
+
+        txn.commit().await?;
+
+        let mut users = Vec::with_capacity(user_rows.len());
+        for user in user_rows {
+            users.push(user.rekeyed(key, export_key)?);
+        }
+
+        Ok(Archive {
+            version: ARCHIVE_VERSION,
+            org: ArchiveOrg {
+                id: id.to_string(),
+                name,
+                owner,
+                status,
+            },
+            settings: settings_rows
+                .into_iter()
+                .map(|(name, kind, value)| ArchiveSetting { name, kind, value })
+                .collect(),
+            users,
+            repositories: repository_rows
+                .into_iter()
+                .map(|(id, name, upstream, status)| ArchiveRepository {
+                    id,
+                    name,
+                    upstream,
+                    status,
+                })
+                .collect(),
+            cascade: cascade_rows
+                .into_iter()
+                .map(|(source, source_id, prior_status)| ArchiveCascade {
+                    source,
+                    source_id,
+                    prior_status,
+                })
+                .collect(),
+            audit: audit_rows
+                .into_iter()
+                .map(|(id, code, source, source_id, ctime)| ArchiveAudit {
+                    id,
+                    code,
+                    source,
+                    source_id,
+                    ctime,
+                })
+                .collect(),
+        })
+    }
+
+    /// import inserts the archived org and everything it owns, with user PII
+    /// re-encrypted from export_key to key, returning the new Org
+    ///
+    /// if preserve_ids is false, every row is given a new id and references
+    /// are remapped; repository clone paths are formed under repo_base;
+    /// an org name already in use is reported as db::Err::OrgViolation;
+    /// every imported user is given a new api secret
+    ///
+    /// actor must be permitted to update its own org in the importing
+    /// environment, as the imported org does not exist yet
+    #[allow(dead_code)]
+    pub async fn import(
+        &self,
+        pool: &sqlx::SqlitePool,
+        actor: &Uuid,
+        export_key: &str,
+        key: &str,
+        preserve_ids: bool,
+        repo_base: &str,
+    ) -> Result<Org, anyhow::Error> {
+        let actor_org: String = match sqlx::query_scalar(SELECT_ACTOR_ORG_QUERY)
+            .bind(actor.to_string())
+            .fetch_one(pool)
+            .await
+        {
+            Err(e) if db::sqlx_row_not_found(&e) => return Err(role::Err::Forbidden.into()),
+            Err(e) => return Err(e.into()),
+            Ok(v) => v,
+        };
+        role::check(
+            pool,
+            actor,
+            &Uuid::try_parse(&actor_org)?,
+            role::Permission::UpdateOrg,
+        )
+        .await?;
+
+        if self.version != ARCHIVE_VERSION {
+            return Err(Err::Version.into());
+        }
+
+        // map every archived id to its imported id
+        let mut ids: HashMap<String, Uuid> = HashMap::new();
+        let mut map_id = |id: &str| -> Result<Uuid, anyhow::Error> {
+            let archived = Uuid::try_parse(id)?;
+            let imported = match preserve_ids {
+                true => archived,
+                false => Uuid::new_v4(),
+            };
+            ids.insert(id.to_string(), imported);
+            Ok(imported)
+        };
+        let org_id = map_id(&self.org.id)?;
+        let mut users = Vec::with_capacity(self.users.len());
+        for user in self.users.iter() {
+            users.push((map_id(&user.id)?, user.rekeyed(export_key, key)?));
+        }
+        let mut repositories = Vec::with_capacity(self.repositories.len());
+        for repository in self.repositories.iter() {
+            repositories.push((map_id(&repository.id)?, repository));
+        }
+        let lookup = |id: &str| -> Result<Uuid, Err> { ids.get(id).copied().ok_or(Err::UnknownId) };
+
+        let org = Org {
+            id: org_id,
+            name: safe::VarChar::new(&self.org.name)?,
+            owner: lookup(&self.org.owner)?,
+            meta: models::Meta {
+                status: models::Status::from_int(self.org.status)?,
+                schema_version: org::SCHEMA_VERSION,
+                ..Default::default()
+            },
+        };
+
+        let mut txn = pool.begin().await?;
+
+        if let Err(insert_error) = org.insert(&mut txn).await {
+            if db::anyhow_sqlx_duplicate(&insert_error) {
+                return Err(db::Err::OrgViolation.into());
+            }
+            return Err(insert_error);
+        }
+
+        for setting in self.settings.iter() {
+            settings::Key::from_name(&setting.name)?
+                .validate(&settings::Value::from_db(setting.kind, &setting.value)?)?;
+            sqlx::query(INSERT_SETTING_QUERY)
+                .bind(Uuid::new_v4().to_string())
+                .bind(org.id.to_string())
+                .bind(&setting.name)
+                .bind(setting.kind)
+                .bind(&setting.value)
+                .execute(&mut txn)
+                .await?;
+        }
+
+        for (id, user) in users {
+            let user = User {
+                id,
+                api_secret: safe::VarChar::new(&user.api_secret)?,
+                api_secret_digest: safe::VarChar::new(&user.api_secret_digest)?,
+                display_name: safe::VarChar::new(&user.display_name)?,
+                display_name_digest: safe::VarChar::new(&user.display_name_digest)?,
+                email: safe::VarChar::new(&user.email)?,
+                email_digest: safe::VarChar::new(&user.email_digest)?,
+                org: org.id,
+                password: safe::VarChar::new(&user.password)?,
+                role: role::Role::from_int(user.role)?,
+                meta: models::Meta {
+                    status: models::Status::from_int(user.status)?,
+                    schema_version: user::SCHEMA_VERSION,
+                    ..Default::default()
+                },
+            };
+            if let Err(insert_error) = user.insert(&mut txn).await {
+                if db::anyhow_sqlx_duplicate(&insert_error) {
+                    return Err(db::Err::UserViolation.into());
+                }
+                return Err(insert_error);
+            }
+        }
+
+        for (id, repository) in repositories {
+            let repository = Repository {
+                id,
+                name: safe::VarChar::new(&repository.name)?,
+                org: org.id,
+                path: safe::VarChar::new(&format!("{}/{}/{}", repo_base, org.id, id))?,
+                upstream: safe::VarChar::new(&repository.upstream)?,
+                meta: models::Meta {
+                    status: models::Status::from_int(repository.status)?,
+                    schema_version: repository::SCHEMA_VERSION,
+                    ..Default::default()
+                },
+            };
+            if let Err(insert_error) = repository.insert(&mut txn).await {
+                if db::anyhow_sqlx_duplicate(&insert_error) {
+                    return Err(db::Err::RepositoryViolation.into());
+                }
+                return Err(insert_error);
+            }
+        }
+
+        for entry in self.cascade.iter() {
+            if entry.source != schema::USERS_TABLENAME
+                && entry.source != schema::REPOSITORIES_TABLENAME
+            {
+                return Err(db::Err::BadRowValues.into());
+            }
+            models::Status::from_int(entry.prior_status)?;
+            sqlx::query(INSERT_CASCADE_QUERY)
+                .bind(Uuid::new_v4().to_string())
+                .bind(org.id.to_string())
+                .bind(&entry.source)
+                .bind(lookup(&entry.source_id)?.to_string())
+                .bind(entry.prior_status)
+                .execute(&mut txn)
+                .await?;
+        }
+
+        for entry in self.audit.iter() {
+            let id = match preserve_ids {
+                true => Uuid::try_parse(&entry.id)?,
+                false => Uuid::new_v4(),
+            };
+            sqlx::query(INSERT_AUDIT_QUERY)
+                .bind(id.to_string())
+                .bind(entry.code)
+                .bind(&entry.source)
+                .bind(lookup(&entry.source_id)?.to_string())
+                .execute(&mut txn)
+                .await?;
+            // keep the original time of the audited change
+            sqlx::query(UPDATE_AUDIT_CTIME_QUERY)
+                .bind(entry.ctime)
+                .bind(id.to_string())
+                .execute(&mut txn)
+                .await?;
+        }
+
+        audit::insert(&mut txn, audit::ORG_IMPORT, schema::ORGS_TABLENAME, &org.id).await?;
+
+        txn.commit().await?;
+
+        Org::read(pool, &org.id).await
+    }
+
+    /// to_json renders the archive as JSON
+    #[allow(dead_code)]
+    pub fn to_json(&self) -> Result<String, anyhow::Error> {
+        Ok(serde_json::to_string(self)?)
+    }
+
+    /// from_json parses an archive rendered by to_json
+    #[allow(dead_code)]
+    pub fn from_json(s: &str) -> Result<Self, anyhow::Error> {
+        Ok(serde_json::from_str(s)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grokloc::app::state;
+
+    #[tokio::test]
+    async fn archive_export_import_test() -> Result<(), anyhow::Error> {
+        // the source environment
+        let source = state::unit().await?;
+        let password = safe::VarChar::new(&crypt::kdf(&crypt::rand_hex(), crypt::MIN_KDF_ROUNDS))?;
+        let member = User::create(
+            &source.master_pool,
+            &safe::VarChar::rand(),
+            &safe::VarChar::rand(),
+            &source.root_org.id,
+            &password,
+            &source.key,
+        )
+        .await?;
+        let member = User::read(&source.master_pool, &member.id, &source.key).await?;
+        let repository = Repository::create(
+            &source.master_pool,
+            &source.root_user.id,
+            &safe::VarChar::rand(),
+            &source.root_org.id,
+            &safe::VarChar::rand(),
+            &source.repo_base,
+        )
+        .await?;
+        settings::set(
+            &source.master_pool,
+            &source.root_user.id,
+            &source.root_org.id,
+            settings::Key::DataRetention,
+            &settings::Value::Int(90),
+        )
+        .await?;
+
+        let export_key = crypt::rand_key();
+        let archive = Archive::export(
+            &source.master_pool,
+            &source.root_user.id,
+            &source.root_org.id,
+            &source.key,
+            &export_key,
+        )
+        .await?;
+        assert_eq!(2, archive.users.len());
+        assert_eq!(1, archive.repositories.len());
+        assert_eq!(1, archive.settings.len());
+
+        // PII is under the export key
+        let archived_member = archive
+            .users
+            .iter()
+            .find(|v| v.id == member.id.to_string())
+            .unwrap();
+        assert_eq!(
+            member.email.to_string(),
+            crypt::decrypt(
+                &export_key,
+                &crypt::iv(&archived_member.email_digest),
+                &archived_member.email
+            )?
+        );
+
+        // the target environment, with its own key
+        let target = state::unit().await?;
+        let archive = Archive::from_json(&archive.to_json()?)?;
+        let org = archive
+            .import(
+                &target.master_pool,
+                &target.root_user.id,
+                &export_key,
+                &target.key,
+                false,
+                &target.repo_base,
+            )
+            .await?;
+        assert_ne!(source.root_org.id, org.id);
+        assert_eq!(source.root_org.name, org.name);
+
+        // the owner is remapped and decrypts under the target key
+        let owner = User::read(&target.master_pool, &org.owner, &target.key).await?;
+        let source_owner =
+            User::read(&source.master_pool, &source.root_user.id, &source.key).await?;
+        assert_eq!(source_owner.email, owner.email);
+        assert_eq!(source_owner.display_name, owner.display_name);
+        // ...but is given a new api secret
+        assert_ne!(source_owner.api_secret, owner.api_secret);
+        assert_ne!(source_owner.api_secret_digest, owner.api_secret_digest);
+        assert_eq!(
+            crypt::sha256_hex(&owner.api_secret.to_string()),
+            owner.api_secret_digest.to_string()
+        );
+        assert_eq!(role::Role::Owner, owner.role);
+        assert_eq!(org.id, owner.org);
+
+        let (profiles, _) =
+            User::list_by_org(&target.master_pool, &org.id, None, None, 10, &target.key).await?;
+        assert_eq!(2, profiles.len());
+        assert!(profiles.iter().any(|v| v.email == member.email));
+
+        let repository_id: String =
+            sqlx::query_scalar("select id from repositories where org = ? and name = ?")
+                .bind(org.id.to_string())
+                .bind(repository.name.to_string())
+                .fetch_one(&target.master_pool)
+                .await?;
+        let imported_repository =
+            Repository::read(&target.master_pool, &Uuid::try_parse(&repository_id)?).await?;
+        assert_eq!(repository.upstream, imported_repository.upstream);
+        assert!(imported_repository
+            .path
+            .to_string()
+            .starts_with(&target.repo_base));
+
+        assert_eq!(
+            settings::Value::Int(90),
+            settings::get(&target.master_pool, &org.id, settings::Key::DataRetention).await?
+        );
+
+        // every archived audit entry, remapped, plus the import itself
+        let audit_count: i64 = sqlx::query_scalar(
+            "select count(*) from audit where source_id in (select id from users where org = ?)",
+        )
+        .bind(org.id.to_string())
+        .fetch_one(&target.master_pool)
+        .await?;
+        assert_eq!(
+            archive
+                .audit
+                .iter()
+                .filter(|v| v.source == schema::USERS_TABLENAME)
+                .count() as i64,
+            audit_count
+        );
+
+        // a second import conflicts on the org name
+        match archive
+            .import(
+                &target.master_pool,
+                &target.root_user.id,
+                &export_key,
+                &target.key,
+                false,
+                &target.repo_base,
+            )
+            .await
+        {
+            Ok(_) => unreachable!(),
+            Err(e) => assert!(matches!(
+                e.downcast_ref::<db::Err>(),
+                Some(db::Err::OrgViolation)
+            )),
+        };
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn archive_preserve_ids_test() -> Result<(), anyhow::Error> {
+        let source = state::unit().await?;
+        let export_key = crypt::rand_key();
+        let archive = Archive::export(
+            &source.master_pool,
+            &source.root_user.id,
+            &source.root_org.id,
+            &source.key,
+            &export_key,
+        )
+        .await?;
+
+        let target = state::unit().await?;
+        let org = archive
+            .import(
+                &target.master_pool,
+                &target.root_user.id,
+                &export_key,
+                &target.key,
+                true,
+                &target.repo_base,
+            )
+            .await?;
+        assert_eq!(source.root_org.id, org.id);
+        assert_eq!(source.root_user.id, org.owner);
+
+        // only permitted actors may export
+        match Archive::export(
+            &source.master_pool,
+            &Uuid::new_v4(),
+            &source.root_org.id,
+            &source.key,
+            &export_key,
+        )
+        .await
+        {
+            Ok(_) => unreachable!(),
+            Err(e) => assert_eq!(Some(&role::Err::Forbidden), e.downcast_ref::<role::Err>()),
+        };
+
+        // ...or import, whether unknown or without authority over their org
+        let password = safe::VarChar::new(&crypt::kdf(&crypt::rand_hex(), crypt::MIN_KDF_ROUNDS))?;
+        let mut member = User::create(
+            &target.master_pool,
+            &safe::VarChar::rand(),
+            &safe::VarChar::rand(),
+            &target.root_org.id,
+            &password,
+            &target.key,
+        )
+        .await?;
+        member
+            .update_status(
+                &target.master_pool,
+                &target.root_user.id,
+                models::Status::Active,
+            )
+            .await?;
+        for actor in [Uuid::new_v4(), member.id] {
+            match archive
+                .import(
+                    &target.master_pool,
+                    &actor,
+                    &export_key,
+                    &target.key,
+                    false,
+                    &target.repo_base,
+                )
+                .await
+            {
+                Ok(_) => unreachable!(),
+                Err(e) => {
+                    assert_eq!(Some(&role::Err::Forbidden), e.downcast_ref::<role::Err>())
+                }
+            };
+        }
+
+        // unsupported versions are refused
+        let mut archive = archive;
+        archive.version = ARCHIVE_VERSION + 1;
+        match archive
+            .import(
+                &target.master_pool,
+                &target.root_user.id,
+                &export_key,
+                &target.key,
+                false,
+                &target.repo_base,
+            )
+            .await
+        {
+            Ok(_) => unreachable!(),
+            Err(e) => assert_eq!(Some(&Err::Version), e.downcast_ref::<Err>()),
+        };
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn archive_cascade_test() -> Result<(), anyhow::Error> {
+        // an Inactive source org whose children were deactivated with it
+        let source = state::unit().await?;
+        let password = safe::VarChar::new(&crypt::kdf(&crypt::rand_hex(), crypt::MIN_KDF_ROUNDS))?;
+        let mut member = User::create(
+            &source.master_pool,
+            &safe::VarChar::rand(),
+            &safe::VarChar::rand(),
+            &source.root_org.id,
+            &password,
+            &source.key,
+        )
+        .await?;
+        member
+            .update_status(
+                &source.master_pool,
+                &source.root_user.id,
+                models::Status::Active,
+            )
+            .await?;
+        let repository = Repository::create(
+            &source.master_pool,
+            &source.root_user.id,
+            &safe::VarChar::rand(),
+            &source.root_org.id,
+            &safe::VarChar::rand(),
+            &source.repo_base,
+        )
+        .await?;
+        let mut source_org = source.root_org.clone();
+        source_org
+            .update_status(
+                &source.master_pool,
+                &source.root_user.id,
+                models::Status::Inactive,
+            )
+            .await?;
+
+        // the owner may still export
+        let export_key = crypt::rand_key();
+        let archive = Archive::export(
+            &source.master_pool,
+            &source.root_user.id,
+            &source.root_org.id,
+            &source.key,
+            &export_key,
+        )
+        .await?;
+        assert_eq!(models::Status::Inactive.to_int(), archive.org.status);
+        let mut archived: Vec<(String, String, i64)> = archive
+            .cascade
+            .iter()
+            .map(|v| (v.source.clone(), v.source_id.clone(), v.prior_status))
+            .collect();
+        archived.sort();
+        assert_eq!(
+            vec![
+                (
+                    schema::REPOSITORIES_TABLENAME.to_string(),
+                    repository.id.to_string(),
+                    models::Status::Active.to_int()
+                ),
+                (
+                    schema::USERS_TABLENAME.to_string(),
+                    member.id.to_string(),
+                    models::Status::Active.to_int()
+                ),
+            ],
+            archived
+        );
+
+        // the cascade is remapped on import, so reactivation restores
+        // the imported children
+        let target = state::unit().await?;
+        let mut org = archive
+            .import(
+                &target.master_pool,
+                &target.root_user.id,
+                &export_key,
+                &target.key,
+                false,
+                &target.repo_base,
+            )
+            .await?;
+        assert_eq!(models::Status::Inactive, org.meta.status);
+        let owner = org.owner;
+        org.update_status(&target.master_pool, &owner, models::Status::Active)
+            .await?;
+
+        let (profiles, _) =
+            User::list_by_org(&target.master_pool, &org.id, None, None, 10, &target.key).await?;
+        assert_eq!(2, profiles.len());
+        for profile in profiles {
+            let user = User::read(&target.master_pool, &profile.id, &target.key).await?;
+            assert_eq!(models::Status::Active, user.meta.status);
+        }
+        let repository_status: i64 =
+            sqlx::query_scalar("select status from repositories where org = ?")
+                .bind(org.id.to_string())
+                .fetch_one(&target.master_pool)
+                .await?;
+        assert_eq!(models::Status::Active.to_int(), repository_status);
+        let cascade_count: i64 =
+            sqlx::query_scalar("select count(*) from status_cascade where org = ?")
+                .bind(org.id.to_string())
+                .fetch_one(&target.master_pool)
+                .await?;
+        assert_eq!(0, cascade_count);
+
+        Ok(())
+    }
+}
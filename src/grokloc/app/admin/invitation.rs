@@ -0,0 +1,668 @@
+//! invitation models an invitations row, an expiring offer of org membership
+//! to an email address, and related db functionality
+use crate::grokloc::app::admin::role;
+use crate::grokloc::app::admin::user::{self, User};
+use crate::grokloc::app::audit;
+use crate::grokloc::app::models;
+use crate::grokloc::app::schema;
+use crate::grokloc::crypt;
+use crate::grokloc::db;
+use crate::grokloc::safe;
+use anyhow;
+use sqlx;
+use sqlx::sqlite::SqliteRow;
+use sqlx::Row;
+use std::fmt;
+use thiserror::Error;
+use uuid::Uuid;
+
+pub const SCHEMA_VERSION: i8 = 0;
+
+pub const INSERT_QUERY: &str = r#"
+insert into invitations
+(id,
+ org,
+ email,
+ email_digest,
+ role,
+ inviter,
+ token_digest,
+ expires,
+ state,
+ schema_version)
+values
+(?,?,?,?,?,?,?,?,?,?)
+"#;
+
+pub const SELECT_BY_TOKEN_QUERY: &str = r#"
+select
+ id,
+ org,
+ email,
+ email_digest,
+ role,
+ inviter,
+ expires,
+ state,
+ ctime,
+ schema_version
+from invitations
+where token_digest = ?
+"#;
+
+pub const LIST_BY_ORG_QUERY: &str = r#"
+select
+ id,
+ org,
+ email,
+ email_digest,
+ role,
+ inviter,
+ expires,
+ state,
+ ctime,
+ schema_version
+from invitations
+where org = ?
+and (? is null or state = ?)
+and (ctime > ? or (ctime = ? and id > ?))
+order by ctime, id
+limit ?
+"#;
+
+pub const SELECT_USER_EXISTS_QUERY: &str = r#"
+select count(*) from users where email_digest = ? and org = ?
+"#;
+
+pub const UPDATE_STATE_QUERY: &str = r#"
+update invitations set state = ? where id = ? and state = ?
+"#;
+
+pub const SUPERSEDE_QUERY: &str = r#"
+update invitations set state = ? where org = ? and email_digest = ? and state = ?
+"#;
+
+/// Err covers invitation errors
+#[derive(Debug, Error, PartialEq)]
+pub enum Err {
+    #[error("unknown invitation state")]
+    UnknownState,
+    #[error("invalid email")]
+    Email,
+    #[error("invalid or expired invitation")]
+    Invalid,
+}
+
+/// State describes the progress of an invitation
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum State {
+    Pending,
+    Accepted,
+    Revoked,
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl State {
+    /// translate a State to its database representation
+    pub fn to_int(self) -> i64 {
+        match self {
+            State::Pending => 1,
+            State::Accepted => 2,
+            State::Revoked => 3,
+        }
+    }
+
+    /// translate a State from its database representation
+    pub fn from_int(i: i64) -> Result<Self, Err> {
+        match i {
+            1 => Ok(State::Pending),
+            2 => Ok(State::Accepted),
+            3 => Ok(State::Revoked),
+            _ => Err(Err::UnknownState),
+        }
+    }
+}
+
+/// Invitation is the decrypted data representation of an invitations row
+///
+/// the token itself is only known to the creator; the row holds its digest
+#[derive(Clone, Debug)]
+#[allow(dead_code)]
+pub struct Invitation {
+    pub id: Uuid,
+    pub org: Uuid,
+    pub email: safe::VarChar,
+    pub role: role::Role,
+    pub inviter: Uuid,
+    pub expires: chrono::DateTime<chrono::Utc>,
+    pub state: State,
+    pub ctime: chrono::DateTime<chrono::Utc>,
+    pub schema_version: i8,
+}
+
+impl Invitation {
+    /// create invites email to join org with role, valid for ttl, returning
+    /// the invitation and the token to deliver to email
+    ///
+    /// actor must be permitted to manage users; the org must be Active, else
+    /// db::Err::OrgViolation; an email already in the org is reported as
+    /// db::Err::UserViolation; a Pending invitation to the same email is
+    /// superseded (revoked) by the new one; the email is normalised first
+    /// (see safe::email_normal)
+    #[allow(dead_code)]
+    pub async fn create(
+        pool: &sqlx::SqlitePool,
+        actor: &Uuid,
+        org: &Uuid,
+        email: &safe::VarChar,
+        role_: role::Role,
+        ttl: chrono::Duration,
+        key: &str,
+    ) -> Result<(Self, String), anyhow::Error> {
+        if !safe::email_ok(&email.to_string()) {
+            return Err(Err::Email.into());
+        }
+        let email = &safe::VarChar::new(&safe::email_normal(&email.to_string()))?;
+        // the single owner is only changed by ownership transfer
+        if role_ == role::Role::Owner {
+            return Err(role::Err::OwnerInvariant.into());
+        }
+
+        let token = crypt::rand_hex();
+        let email_digest = crypt::sha256_hex(&email.to_string());
+        let invitation = Self {
+            id: Uuid::new_v4(),
+            org: *org,
+            email: email.clone(),
+            role: role_,
+            inviter: *actor,
+            expires: chrono::Utc::now() + ttl,
+            state: State::Pending,
+            ctime: chrono::Utc::now(),
+            schema_version: SCHEMA_VERSION,
+        };
+
+        let mut txn = pool.begin().await?;
+
+        role::check(&mut txn, actor, org, role::Permission::ManageUsers).await?;
+
+        let org_status: i64 = sqlx::query_scalar(user::SELECT_ORG_STATUS_QUERY)
+            .bind(org.to_string())
+            .fetch_one(&mut txn)
+            .await?;
+        if models::Status::from_int(org_status)? != models::Status::Active {
+            return Err(db::Err::OrgViolation.into());
+        }
+
+        let existing: i64 = sqlx::query_scalar(SELECT_USER_EXISTS_QUERY)
+            .bind(&email_digest)
+            .bind(org.to_string())
+            .fetch_one(&mut txn)
+            .await?;
+        if existing != 0 {
+            return Err(db::Err::UserViolation.into());
+        }
+
+        sqlx::query(SUPERSEDE_QUERY)
+            .bind(State::Revoked.to_int())
+            .bind(org.to_string())
+            .bind(&email_digest)
+            .bind(State::Pending.to_int())
+            .execute(&mut txn)
+            .await?;
+
+        sqlx::query(INSERT_QUERY)
+            .bind(invitation.id.to_string())
+            .bind(org.to_string())
+            .bind(crypt::encrypt(
+                key,
+                &crypt::iv(&email_digest),
+                &email.to_string(),
+            )?)
+            .bind(&email_digest)
+            .bind(invitation.role.to_int())
+            .bind(actor.to_string())
+            .bind(crypt::sha256_hex(&token))
+            .bind(invitation.expires.timestamp())
+            .bind(invitation.state.to_int())
+            .bind(SCHEMA_VERSION)
+            .execute(&mut txn)
+            .await?;
+
+        audit::insert(
+            &mut txn,
+            audit::INVITATION_INSERT,
+            schema::INVITATIONS_TABLENAME,
+            &invitation.id,
+        )
+        .await?;
+
+        txn.commit().await?;
+
+        Ok((invitation, token))
+    }
+
+    /// list selects and decrypts a page of up to limit invitations of org,
+    /// optionally in state, ordered by ctime and starting after cursor
+    ///
+    /// actor must be permitted to manage users; the returned cursor is
+    /// Some iff there may be more invitations to list
+    #[allow(dead_code)]
+    pub async fn list(
+        pool: &sqlx::SqlitePool,
+        actor: &Uuid,
+        org: &Uuid,
+        state: Option<State>,
+        cursor: Option<&models::Cursor>,
+        limit: i64,
+        key: &str,
+    ) -> Result<(Vec<Self>, Option<models::Cursor>), anyhow::Error> {
+        role::check(pool, actor, org, role::Permission::ManageUsers).await?;
+
+        let limit = models::limit_ok(limit);
+        let state = state.map(|v| v.to_int());
+        let (after_ctime, after_id) = models::Cursor::bind_values(cursor);
+        let rows = sqlx::query(LIST_BY_ORG_QUERY)
+            .bind(org.to_string())
+            .bind(state)
+            .bind(state)
+            .bind(after_ctime)
+            .bind(after_ctime)
+            .bind(after_id)
+            .bind(limit + 1)
+            .fetch_all(pool)
+            .await?;
+
+        let mut invitations = Vec::with_capacity(rows.len());
+        for row in rows.iter().take(limit as usize) {
+            invitations.push(Self::from_row(row, key)?);
+        }
+
+        let next = match rows.len() as i64 > limit {
+            true => invitations.last().map(|v| models::Cursor {
+                ctime: v.ctime.timestamp(),
+                id: v.id,
+            }),
+            false => None,
+        };
+        Ok((invitations, next))
+    }
+
+    /// revoke withdraws a Pending invitation
+    ///
+    /// actor must be permitted to manage users; an invitation that is no
+    /// longer Pending is reported as not found
+    #[allow(dead_code)]
+    pub async fn revoke(
+        &mut self,
+        pool: &sqlx::SqlitePool,
+        actor: &Uuid,
+    ) -> Result<(), anyhow::Error> {
+        let mut txn = pool.begin().await?;
+
+        role::check(&mut txn, actor, &self.org, role::Permission::ManageUsers).await?;
+
+        let update_result = sqlx::query(UPDATE_STATE_QUERY)
+            .bind(State::Revoked.to_int())
+            .bind(self.id.to_string())
+            .bind(State::Pending.to_int())
+            .execute(&mut txn)
+            .await?;
+        if update_result.rows_affected() != 1 {
+            return Err(sqlx::Error::RowNotFound.into());
+        }
+
+        audit::insert(
+            &mut txn,
+            audit::INVITATION_REVOKE,
+            schema::INVITATIONS_TABLENAME,
+            &self.id,
+        )
+        .await?;
+
+        txn.commit().await?;
+
+        // the update to the db was a success, set the internal field
+        self.state = State::Revoked;
+
+        Ok(())
+    }
+
+    /// accept redeems the invitation token, creating an Active user with
+    /// display_name and a password derived from password with kdf_rounds
+    ///
+    /// the user is created through the same org integrity checks as
+    /// User::create; no email confirmation is needed, as receipt of the
+    /// token proves ownership of the email; an unknown, expired or no longer
+    /// Pending invitation is reported as Err::Invalid
+    #[allow(dead_code)]
+    pub async fn accept(
+        pool: &sqlx::SqlitePool,
+        token: &str,
+        display_name: &safe::VarChar,
+        password: &str,
+        kdf_rounds: u32,
+        key: &str,
+    ) -> Result<User, anyhow::Error> {
+        let mut txn = pool.begin().await?;
+
+        let row = sqlx::query(SELECT_BY_TOKEN_QUERY)
+            .bind(crypt::sha256_hex(token))
+            .fetch_optional(&mut txn)
+            .await?;
+        let invitation = match row {
+            Some(row) => Self::from_row(&row, key)?,
+            None => return Err(Err::Invalid.into()),
+        };
+        if invitation.state != State::Pending || invitation.expires <= chrono::Utc::now() {
+            return Err(Err::Invalid.into());
+        }
+
+        let password_ = safe::VarChar::new(&crypt::kdf(password, kdf_rounds))?;
+        let mut user = User::encrypted(
+            display_name,
+            &invitation.email,
+            &invitation.org,
+            &password_,
+            key,
+        )?;
+        user.role = invitation.role;
+        user.meta.status = models::Status::Active;
+        user.insert_checked(&mut txn).await?;
+
+        let update_result = sqlx::query(UPDATE_STATE_QUERY)
+            .bind(State::Accepted.to_int())
+            .bind(invitation.id.to_string())
+            .bind(State::Pending.to_int())
+            .execute(&mut txn)
+            .await?;
+        if update_result.rows_affected() != 1 {
+            return Err(Err::Invalid.into());
+        }
+
+        audit::insert(
+            &mut txn,
+            audit::INVITATION_ACCEPT,
+            schema::INVITATIONS_TABLENAME,
+            &invitation.id,
+        )
+        .await?;
+
+        txn.commit().await?;
+
+        Ok(user)
+    }
+
+    /// from_row decrypts a row selecting invitations columns to construct an Invitation
+    fn from_row(row: &SqliteRow, key: &str) -> Result<Self, anyhow::Error> {
+        let iv = crypt::iv(&row.try_get::<String, _>("email_digest")?);
+        let email_ = crypt::decrypt(key, &iv, &row.try_get::<String, _>("email")?)?;
+        Ok(Self {
+            id: Uuid::try_parse(&row.try_get::<String, _>("id")?)?,
+            org: Uuid::try_parse(&row.try_get::<String, _>("org")?)?,
+            email: safe::VarChar::trusted(&email_),
+            role: role::Role::from_int(row.try_get::<i64, _>("role")?)?,
+            inviter: Uuid::try_parse(&row.try_get::<String, _>("inviter")?)?,
+            expires: chrono::DateTime::from_timestamp(row.try_get::<i64, _>("expires")?, 0)
+                .unwrap_or_default(),
+            state: State::from_int(row.try_get::<i64, _>("state")?)?,
+            ctime: chrono::DateTime::from_timestamp(row.try_get::<i64, _>("ctime")?, 0)
+                .unwrap_or_default(),
+            schema_version: row.try_get::<i8, _>("schema_version")?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grokloc::app::state;
+
+    fn rand_email() -> safe::VarChar {
+        safe::VarChar::trusted(&format!("{}@example.com", crypt::rand_hex()))
+    }
+
+    #[test]
+    fn invitation_state_int_test() -> Result<(), Err> {
+        for state in [State::Pending, State::Accepted, State::Revoked] {
+            assert_eq!(state, State::from_int(state.to_int())?);
+        }
+        assert_eq!(Err::UnknownState, State::from_int(0).unwrap_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn invitation_create_accept_test() -> Result<(), anyhow::Error> {
+        let app = state::unit().await?;
+        let org = app.root_org.id;
+        let email = rand_email();
+
+        let (invitation, token) = Invitation::create(
+            &app.master_pool,
+            &app.root_user.id,
+            &org,
+            &email,
+            role::Role::Admin,
+            chrono::Duration::days(1),
+            &app.key,
+        )
+        .await?;
+
+        let (pending, next) = Invitation::list(
+            &app.master_pool,
+            &app.root_user.id,
+            &org,
+            Some(State::Pending),
+            None,
+            10,
+            &app.key,
+        )
+        .await?;
+        assert!(next.is_none());
+        assert_eq!(1, pending.len());
+        assert_eq!(invitation.id, pending[0].id);
+        assert_eq!(email, pending[0].email);
+        assert_eq!(role::Role::Admin, pending[0].role);
+        assert_eq!(app.root_user.id, pending[0].inviter);
+
+        let display_name = safe::VarChar::rand();
+        let password = crypt::rand_hex();
+        let user = Invitation::accept(
+            &app.master_pool,
+            &token,
+            &display_name,
+            &password,
+            app.kdf_iterations,
+            &app.key,
+        )
+        .await?;
+        let user_read = User::read(&app.master_pool, &user.id, &app.key).await?;
+        assert_eq!(email, user_read.email);
+        assert_eq!(display_name, user_read.display_name);
+        assert_eq!(role::Role::Admin, user_read.role);
+        assert_eq!(models::Status::Active, user_read.meta.status);
+        assert!(crypt::kdf_verify(
+            &password,
+            &user_read.password.to_string()
+        ));
+
+        // single use
+        match Invitation::accept(
+            &app.master_pool,
+            &token,
+            &display_name,
+            &password,
+            app.kdf_iterations,
+            &app.key,
+        )
+        .await
+        {
+            Ok(_) => unreachable!(),
+            Err(e) => assert_eq!(Some(&Err::Invalid), e.downcast_ref::<Err>()),
+        };
+
+        // the email is now a member, in any case
+        for member_email in [
+            email.clone(),
+            safe::VarChar::new(&email.to_string().to_uppercase())?,
+        ] {
+            match Invitation::create(
+                &app.master_pool,
+                &app.root_user.id,
+                &org,
+                &member_email,
+                role::Role::Member,
+                chrono::Duration::days(1),
+                &app.key,
+            )
+            .await
+            {
+                Ok(_) => unreachable!(),
+                Err(e) => assert!(matches!(
+                    e.downcast_ref::<db::Err>(),
+                    Some(db::Err::UserViolation)
+                )),
+            };
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn invitation_revoke_expire_test() -> Result<(), anyhow::Error> {
+        let app = state::unit().await?;
+        let org = app.root_org.id;
+        let email = rand_email();
+
+        let (mut invitation, token) = Invitation::create(
+            &app.master_pool,
+            &app.root_user.id,
+            &org,
+            &email,
+            role::Role::Member,
+            chrono::Duration::days(1),
+            &app.key,
+        )
+        .await?;
+        invitation
+            .revoke(&app.master_pool, &app.root_user.id)
+            .await?;
+        assert_eq!(State::Revoked, invitation.state);
+        match invitation.revoke(&app.master_pool, &app.root_user.id).await {
+            Ok(_) => unreachable!(),
+            Err(e) => assert!(db::anyhow_sqlx_row_not_found(&e)),
+        };
+
+        // a new invitation supersedes a pending one
+        let (_, superseded_token) = Invitation::create(
+            &app.master_pool,
+            &app.root_user.id,
+            &org,
+            &email,
+            role::Role::Member,
+            chrono::Duration::days(1),
+            &app.key,
+        )
+        .await?;
+        let (_, expired_token) = Invitation::create(
+            &app.master_pool,
+            &app.root_user.id,
+            &org,
+            &email,
+            role::Role::Member,
+            chrono::Duration::seconds(-1),
+            &app.key,
+        )
+        .await?;
+        let (revoked, _) = Invitation::list(
+            &app.master_pool,
+            &app.root_user.id,
+            &org,
+            Some(State::Revoked),
+            None,
+            10,
+            &app.key,
+        )
+        .await?;
+        assert_eq!(2, revoked.len());
+
+        for t in [token, superseded_token, expired_token] {
+            match Invitation::accept(
+                &app.master_pool,
+                &t,
+                &safe::VarChar::rand(),
+                &crypt::rand_hex(),
+                app.kdf_iterations,
+                &app.key,
+            )
+            .await
+            {
+                Ok(_) => unreachable!(),
+                Err(e) => assert_eq!(Some(&Err::Invalid), e.downcast_ref::<Err>()),
+            };
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn invitation_create_rejected_test() -> Result<(), anyhow::Error> {
+        let app = state::unit().await?;
+        let org = app.root_org.id;
+
+        match Invitation::create(
+            &app.master_pool,
+            &app.root_user.id,
+            &org,
+            &safe::VarChar::rand(),
+            role::Role::Member,
+            chrono::Duration::days(1),
+            &app.key,
+        )
+        .await
+        {
+            Ok(_) => unreachable!(),
+            Err(e) => assert_eq!(Some(&Err::Email), e.downcast_ref::<Err>()),
+        };
+
+        match Invitation::create(
+            &app.master_pool,
+            &app.root_user.id,
+            &org,
+            &rand_email(),
+            role::Role::Owner,
+            chrono::Duration::days(1),
+            &app.key,
+        )
+        .await
+        {
+            Ok(_) => unreachable!(),
+            Err(e) => assert_eq!(
+                Some(&role::Err::OwnerInvariant),
+                e.downcast_ref::<role::Err>()
+            ),
+        };
+
+        match Invitation::create(
+            &app.master_pool,
+            &Uuid::new_v4(),
+            &org,
+            &rand_email(),
+            role::Role::Member,
+            chrono::Duration::days(1),
+            &app.key,
+        )
+        .await
+        {
+            Ok(_) => unreachable!(),
+            Err(e) => assert_eq!(Some(&role::Err::Forbidden), e.downcast_ref::<role::Err>()),
+        };
+
+        Ok(())
+    }
+}
@@ -0,0 +1,325 @@
+//! language models a languages row, a custom language definition of an org
+//! that is recognised beside the built-in languages when analysing the
+//! org repositories
+use crate::grokloc::app::admin::role;
+use crate::grokloc::app::audit;
+use crate::grokloc::app::schema;
+use crate::grokloc::db;
+use crate::grokloc::loc;
+use anyhow;
+use sqlx;
+use sqlx::Row;
+use uuid::Uuid;
+
+pub const SCHEMA_VERSION: i8 = 0;
+
+pub const INSERT_QUERY: &str = r#"
+insert into languages
+(id,
+ org,
+ name,
+ definition,
+ schema_version)
+values
+(?,?,?,?,?)
+"#;
+
+pub const SELECT_QUERY: &str = r#"
+select
+ id,
+ org,
+ definition,
+ schema_version
+from languages
+where id = ?
+"#;
+
+pub const LIST_QUERY: &str = r#"
+select
+ id,
+ org,
+ definition,
+ schema_version
+from languages
+where org = ?
+order by name
+"#;
+
+pub const UPDATE_QUERY: &str = r#"
+update languages set name = ?, definition = ? where id = ?
+"#;
+
+pub const DELETE_QUERY: &str = r#"
+delete from languages where id = ?
+"#;
+
+/// CustomLanguage is the data representation of a languages row
+#[derive(Clone, Debug, PartialEq)]
+pub struct CustomLanguage {
+    pub id: Uuid,
+    pub org: Uuid,
+    pub language: loc::Language,
+    pub schema_version: i8,
+}
+
+/// validate checks a custom definition, which may not take a built-in name
+pub fn validate(language: &loc::Language) -> Result<(), loc::Err> {
+    language.validate()?;
+    if loc::is_builtin(&language.name) {
+        return Err(loc::Err::BuiltinName);
+    }
+    Ok(())
+}
+
+/// from_row constructs a CustomLanguage from a languages row
+fn from_row(row: &sqlx::sqlite::SqliteRow) -> Result<CustomLanguage, anyhow::Error> {
+    Ok(CustomLanguage {
+        id: Uuid::try_parse(&row.try_get::<String, _>("id")?)?,
+        org: Uuid::try_parse(&row.try_get::<String, _>("org")?)?,
+        language: serde_json::from_str(&row.try_get::<String, _>("definition")?)?,
+        schema_version: row.try_get::<i8, _>("schema_version")?,
+    })
+}
+
+impl CustomLanguage {
+    /// create validates and stores a custom language definition of org
+    ///
+    /// actor must be permitted to update the org; a duplicate name within
+    /// the org is reported as db::Err::LanguageViolation
+    #[allow(dead_code)]
+    pub async fn create(
+        pool: &sqlx::SqlitePool,
+        actor: &Uuid,
+        org: &Uuid,
+        language: &loc::Language,
+    ) -> Result<Self, anyhow::Error> {
+        validate(language)?;
+        let custom = Self {
+            id: Uuid::new_v4(),
+            org: *org,
+            language: language.clone(),
+            schema_version: SCHEMA_VERSION,
+        };
+
+        let mut txn = pool.begin().await?;
+
+        role::check(&mut txn, actor, org, role::Permission::UpdateOrg).await?;
+
+        if let Err(e) = sqlx::query(INSERT_QUERY)
+            .bind(custom.id.to_string())
+            .bind(custom.org.to_string())
+            .bind(&custom.language.name)
+            .bind(serde_json::to_string(&custom.language)?)
+            .bind(custom.schema_version)
+            .execute(&mut txn)
+            .await
+        {
+            if db::sqlx_duplicate(&e) {
+                return Err(db::Err::LanguageViolation.into());
+            }
+            return Err(e.into());
+        }
+
+        audit::insert(
+            &mut txn,
+            audit::LANGUAGE_INSERT,
+            schema::LANGUAGES_TABLENAME,
+            &custom.id,
+        )
+        .await?;
+
+        txn.commit().await?;
+
+        Ok(custom)
+    }
+
+    /// read selects a languages row to construct a CustomLanguage instance
+    #[allow(dead_code)]
+    pub async fn read(pool: &sqlx::SqlitePool, id: &Uuid) -> Result<Self, anyhow::Error> {
+        let row = sqlx::query(SELECT_QUERY)
+            .bind(id.to_string())
+            .fetch_one(pool)
+            .await?;
+        from_row(&row)
+    }
+
+    /// update validates and replaces the definition, which may be renamed
+    ///
+    /// actor must be permitted to update the org; a duplicate name within
+    /// the org is reported as db::Err::LanguageViolation
+    #[allow(dead_code)]
+    pub async fn update(
+        &mut self,
+        pool: &sqlx::SqlitePool,
+        actor: &Uuid,
+        language: &loc::Language,
+    ) -> Result<(), anyhow::Error> {
+        validate(language)?;
+
+        let mut txn = pool.begin().await?;
+
+        role::check(&mut txn, actor, &self.org, role::Permission::UpdateOrg).await?;
+
+        let update_result = match sqlx::query(UPDATE_QUERY)
+            .bind(&language.name)
+            .bind(serde_json::to_string(language)?)
+            .bind(self.id.to_string())
+            .execute(&mut txn)
+            .await
+        {
+            Err(e) if db::sqlx_duplicate(&e) => return Err(db::Err::LanguageViolation.into()),
+            Err(e) => return Err(e.into()),
+            Ok(v) => v,
+        };
+
+        if update_result.rows_affected() != 1 {
+            return Err(sqlx::Error::RowNotFound.into());
+        }
+
+        audit::insert(
+            &mut txn,
+            audit::LANGUAGE_UPDATE,
+            schema::LANGUAGES_TABLENAME,
+            &self.id,
+        )
+        .await?;
+
+        txn.commit().await?;
+
+        // the update to the db was a success, set the internal field
+        self.language = language.clone();
+
+        Ok(())
+    }
+
+    /// delete removes the definition; counts made with it remain cached
+    ///
+    /// actor must be permitted to update the org
+    #[allow(dead_code)]
+    pub async fn delete(self, pool: &sqlx::SqlitePool, actor: &Uuid) -> Result<(), anyhow::Error> {
+        let mut txn = pool.begin().await?;
+
+        role::check(&mut txn, actor, &self.org, role::Permission::UpdateOrg).await?;
+
+        let delete_result = sqlx::query(DELETE_QUERY)
+            .bind(self.id.to_string())
+            .execute(&mut txn)
+            .await?;
+        if delete_result.rows_affected() != 1 {
+            return Err(sqlx::Error::RowNotFound.into());
+        }
+
+        audit::insert(
+            &mut txn,
+            audit::LANGUAGE_DELETE,
+            schema::LANGUAGES_TABLENAME,
+            &self.id,
+        )
+        .await?;
+
+        txn.commit().await?;
+
+        Ok(())
+    }
+}
+
+/// list selects the custom language definitions of org, ordered by name
+pub async fn list(
+    pool: &sqlx::SqlitePool,
+    org: &Uuid,
+) -> Result<Vec<CustomLanguage>, anyhow::Error> {
+    let rows = sqlx::query(LIST_QUERY)
+        .bind(org.to_string())
+        .fetch_all(pool)
+        .await?;
+    rows.iter().map(from_row).collect()
+}
+
+/// languages is the set of languages recognised in org
+pub async fn languages(
+    pool: &sqlx::SqlitePool,
+    org: &Uuid,
+) -> Result<loc::Languages, anyhow::Error> {
+    let custom = list(pool, org).await?;
+    Ok(loc::Languages::with_custom(
+        custom.into_iter().map(|v| v.language).collect(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grokloc::app::state;
+
+    #[tokio::test]
+    async fn language_crud_test() -> Result<(), anyhow::Error> {
+        let app = state::unit().await?;
+        let org = app.root_org.id;
+        let dsl = loc::Language {
+            name: "Dsl".to_string(),
+            extensions: vec!["dsl".to_string()],
+            line_comments: vec![";".to_string()],
+            ..Default::default()
+        };
+
+        let mut custom =
+            CustomLanguage::create(&app.master_pool, &app.root_user.id, &org, &dsl).await?;
+        assert_eq!(
+            custom,
+            CustomLanguage::read(&app.master_pool, &custom.id).await?
+        );
+        let languages = languages(&app.master_pool, &org).await?;
+        assert_eq!(Some(&dsl), languages.detect("x.dsl"));
+        assert!(languages.detect("x.rs").is_some());
+
+        // duplicate names, built-in names and invalid definitions are refused
+        match CustomLanguage::create(&app.master_pool, &app.root_user.id, &org, &dsl).await {
+            Ok(_) => unreachable!(),
+            Err(e) => assert!(matches!(
+                e.downcast_ref::<db::Err>(),
+                Some(db::Err::LanguageViolation)
+            )),
+        };
+        let rust = loc::Language {
+            name: "Rust".to_string(),
+            ..dsl.clone()
+        };
+        match CustomLanguage::create(&app.master_pool, &app.root_user.id, &org, &rust).await {
+            Ok(_) => unreachable!(),
+            Err(e) => assert_eq!(Some(&loc::Err::BuiltinName), e.downcast_ref::<loc::Err>()),
+        };
+        let invalid = loc::Language {
+            extensions: vec![],
+            ..dsl.clone()
+        };
+        match custom
+            .update(&app.master_pool, &app.root_user.id, &invalid)
+            .await
+        {
+            Ok(_) => unreachable!(),
+            Err(e) => assert_eq!(Some(&loc::Err::NoMatch), e.downcast_ref::<loc::Err>()),
+        };
+
+        // only permitted actors may save
+        match CustomLanguage::create(&app.master_pool, &Uuid::new_v4(), &org, &dsl).await {
+            Ok(_) => unreachable!(),
+            Err(e) => assert_eq!(Some(&role::Err::Forbidden), e.downcast_ref::<role::Err>()),
+        };
+
+        // update, then delete
+        let renamed = loc::Language {
+            name: "Dsl2".to_string(),
+            nested_comments: true,
+            ..dsl.clone()
+        };
+        custom
+            .update(&app.master_pool, &app.root_user.id, &renamed)
+            .await?;
+        assert_eq!(renamed, custom.language);
+        assert_eq!(custom, list(&app.master_pool, &org).await?[0]);
+        custom.delete(&app.master_pool, &app.root_user.id).await?;
+        assert!(list(&app.master_pool, &org).await?.is_empty());
+
+        Ok(())
+    }
+}
@@ -1,9 +1,16 @@
 //! org models an orgs row and related db functionality
+use crate::grokloc::app::admin::repository;
+use crate::grokloc::app::admin::role;
+use crate::grokloc::app::admin::user;
 use crate::grokloc::app::admin::user::User;
+use crate::grokloc::app::audit;
 use crate::grokloc::app::models;
+use crate::grokloc::app::schema;
+use crate::grokloc::db;
 use crate::grokloc::safe;
 use anyhow;
 use sqlx;
+use sqlx::sqlite::SqliteRow;
 use sqlx::Row;
 use uuid::Uuid;
 
@@ -22,6 +29,7 @@ insert into orgs
 
 pub const SELECT_QUERY: &str = r#"
 select
+ id,
  name,
  owner,
  ctime,
@@ -32,10 +40,73 @@ from orgs
 where id = ?
 "#;
 
+pub const LIST_QUERY: &str = r#"
+select
+ id,
+ name,
+ owner,
+ ctime,
+ mtime,
+ schema_version,
+ status
+from orgs
+where (? is null or status = ?)
+and (? is null or substr(name, 1, length(?)) = ?)
+and (ctime > ? or (ctime = ? and id > ?))
+order by ctime, id
+limit ?
+"#;
+
 pub const UPDATE_STATUS_QUERY: &str = r#"
 update orgs set status = ? where id = ?;
 "#;
 
+pub const SELECT_CASCADE_USERS_QUERY: &str = r#"
+select id, status from users where org = ? and status not in (?, ?) and id != ?;
+"#;
+
+pub const SELECT_CASCADE_REPOSITORIES_QUERY: &str = r#"
+select id, status from repositories where org = ? and status != ?;
+"#;
+
+pub const INSERT_CASCADE_QUERY: &str = r#"
+insert into status_cascade
+(id,
+ org,
+ source,
+ source_id,
+ prior_status)
+values
+(?,?,?,?,?)
+"#;
+
+pub const SELECT_CASCADE_QUERY: &str = r#"
+select source, source_id, prior_status from status_cascade where org = ?;
+"#;
+
+pub const DELETE_CASCADE_QUERY: &str = r#"
+delete from status_cascade where org = ?;
+"#;
+
+pub const UPDATE_NAME_QUERY: &str = r#"
+update orgs set name = ? where id = ?;
+"#;
+
+pub const UPDATE_OWNER_QUERY: &str = r#"
+update orgs set owner = ? where id = ?;
+"#;
+
+pub const SELECT_USER_ORG_STATUS_QUERY: &str = r#"
+select org, status from users where id = ?;
+"#;
+
+/// ListFilter narrows an org listing; None fields match all orgs
+#[derive(Clone, Debug, Default)]
+pub struct ListFilter {
+    pub status: Option<models::Status>,
+    pub name_prefix: Option<safe::VarChar>,
+}
+
 /// Org is the data representation of an orgs row
 #[derive(Clone, Debug)]
 pub struct Org {
@@ -81,6 +152,7 @@ impl Org {
         // build and insert org owner
         let mut owner = User::encrypted(owner_display_name, owner_email, &id, owner_password, key)?;
         owner.meta.status = models::Status::Active;
+        owner.role = role::Role::Owner;
         let mut txn = pool.begin().await?;
         owner.insert(&mut txn).await?;
 
@@ -106,12 +178,62 @@ impl Org {
     #[allow(dead_code)]
     pub async fn read(pool: &sqlx::SqlitePool, id: &Uuid) -> Result<Self, anyhow::Error> {
         let row = sqlx::query(SELECT_QUERY)
-            .bind(&id.to_string())
+            .bind(id.to_string())
             .fetch_one(pool)
             .await?;
 
+        Self::from_row(&row)
+    }
+
+    /// list selects a page of up to limit orgs matching filter, ordered by
+    /// ctime and starting after cursor
+    ///
+    /// the returned cursor is Some iff there may be more orgs to list
+    #[allow(dead_code)]
+    pub async fn list(
+        pool: &sqlx::SqlitePool,
+        filter: &ListFilter,
+        cursor: Option<&models::Cursor>,
+        limit: i64,
+    ) -> Result<(Vec<Self>, Option<models::Cursor>), anyhow::Error> {
+        let limit = models::limit_ok(limit);
+        let status = filter.status.map(|v| v.to_int());
+        let name_prefix = filter.name_prefix.as_ref().map(|v| v.to_string());
+        let (after_ctime, after_id) = models::Cursor::bind_values(cursor);
+
+        let rows = sqlx::query(LIST_QUERY)
+            .bind(status)
+            .bind(status)
+            .bind(&name_prefix)
+            .bind(&name_prefix)
+            .bind(&name_prefix)
+            .bind(after_ctime)
+            .bind(after_ctime)
+            .bind(after_id)
+            .bind(limit + 1)
+            .fetch_all(pool)
+            .await?;
+
+        let mut orgs = Vec::with_capacity(rows.len());
+        for row in rows.iter().take(limit as usize) {
+            orgs.push(Self::from_row(row)?);
+        }
+
+        let next = match rows.len() as i64 > limit {
+            true => orgs.last().map(|v| models::Cursor {
+                ctime: v.meta.ctime.timestamp(),
+                id: v.id,
+            }),
+            false => None,
+        };
+
+        Ok((orgs, next))
+    }
+
+    /// from_row constructs an Org from a row selecting all orgs columns
+    fn from_row(row: &SqliteRow) -> Result<Self, anyhow::Error> {
         Ok(Self {
-            id: *id,
+            id: Uuid::try_parse(&row.try_get::<String, _>("id")?)?,
             name: safe::VarChar::trusted(&row.try_get::<String, _>("name")?),
             owner: Uuid::try_parse(&row.try_get::<String, _>("owner")?)?,
             meta: models::Meta::from_db(
@@ -123,17 +245,34 @@ impl Org {
         })
     }
 
-    /// update_status updates the org status
+    /// update_status updates the org status, cascading to its users and repositories
+    ///
+    /// setting Inactive deactivates every user (but the owner) and repository of
+    /// the org, remembering their prior status; any other status restores
+    /// the children deactivated by the cascade, leaving those that were
+    /// already Inactive as they were; orgs are never Deleted, which is
+    /// reported as models::Err::Deleted
+    ///
+    /// actor must be permitted to update the org
     #[allow(dead_code)]
     pub async fn update_status(
         &mut self,
         pool: &sqlx::SqlitePool,
+        actor: &Uuid,
         new_status: models::Status,
     ) -> Result<(), anyhow::Error> {
+        if new_status == models::Status::Deleted {
+            return Err(models::Err::Deleted.into());
+        }
+
+        let mut txn = pool.begin().await?;
+
+        role::check_update_org(&mut txn, actor, &self.id).await?;
+
         let update_result = match sqlx::query(UPDATE_STATUS_QUERY)
             .bind(new_status.to_int())
             .bind(self.id.to_string())
-            .execute(pool)
+            .execute(&mut txn)
             .await
         {
             Err(e) => return Err(e.into()),
@@ -144,20 +283,224 @@ impl Org {
             return Err(sqlx::Error::RowNotFound.into());
         }
 
+        match new_status {
+            models::Status::Inactive => self.cascade_deactivate(&mut txn).await?,
+            _ => self.cascade_restore(&mut txn).await?,
+        }
+
+        audit::insert(
+            &mut txn,
+            audit::ORG_STATUS,
+            schema::ORGS_TABLENAME,
+            &self.id,
+        )
+        .await?;
+
+        txn.commit().await?;
+
         // the update to the db was a success, set the internal field
         self.meta.status = new_status;
 
         Ok(())
     }
+
+    /// cascade_deactivate sets the org users (but the owner and erased users)
+    /// and repositories Inactive, recording the prior status of each
+    async fn cascade_deactivate(
+        &self,
+        txn: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    ) -> Result<(), anyhow::Error> {
+        let inactive = models::Status::Inactive.to_int();
+        let users: Vec<(String, i64)> = sqlx::query_as(SELECT_CASCADE_USERS_QUERY)
+            .bind(self.id.to_string())
+            .bind(inactive)
+            .bind(models::Status::Deleted.to_int())
+            .bind(self.owner.to_string())
+            .fetch_all(&mut *txn)
+            .await?;
+        let repositories: Vec<(String, i64)> = sqlx::query_as(SELECT_CASCADE_REPOSITORIES_QUERY)
+            .bind(self.id.to_string())
+            .bind(inactive)
+            .fetch_all(&mut *txn)
+            .await?;
+
+        for (source, update_query, children) in [
+            (schema::USERS_TABLENAME, user::UPDATE_STATUS_QUERY, users),
+            (
+                schema::REPOSITORIES_TABLENAME,
+                repository::UPDATE_STATUS_QUERY,
+                repositories,
+            ),
+        ] {
+            for (id, prior_status) in children {
+                sqlx::query(INSERT_CASCADE_QUERY)
+                    .bind(Uuid::new_v4().to_string())
+                    .bind(self.id.to_string())
+                    .bind(source)
+                    .bind(&id)
+                    .bind(prior_status)
+                    .execute(&mut *txn)
+                    .await?;
+                sqlx::query(update_query)
+                    .bind(inactive)
+                    .bind(&id)
+                    .execute(&mut *txn)
+                    .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// cascade_restore returns children deactivated by cascade_deactivate
+    /// to their prior status
+    async fn cascade_restore(
+        &self,
+        txn: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    ) -> Result<(), anyhow::Error> {
+        let children: Vec<(String, String, i64)> = sqlx::query_as(SELECT_CASCADE_QUERY)
+            .bind(self.id.to_string())
+            .fetch_all(&mut *txn)
+            .await?;
+
+        for (source, id, prior_status) in children {
+            let update_query = match source.as_str() {
+                schema::USERS_TABLENAME => user::UPDATE_STATUS_QUERY,
+                schema::REPOSITORIES_TABLENAME => repository::UPDATE_STATUS_QUERY,
+                _ => return Err(db::Err::BadRowValues.into()),
+            };
+            sqlx::query(update_query)
+                .bind(prior_status)
+                .bind(&id)
+                .execute(&mut *txn)
+                .await?;
+        }
+
+        sqlx::query(DELETE_CASCADE_QUERY)
+            .bind(self.id.to_string())
+            .execute(&mut *txn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// update_name updates the org name
+    ///
+    /// actor must be permitted to update the org; a name already in use is
+    /// reported as db::Err::OrgViolation
+    #[allow(dead_code)]
+    pub async fn update_name(
+        &mut self,
+        pool: &sqlx::SqlitePool,
+        actor: &Uuid,
+        new_name: &safe::VarChar,
+    ) -> Result<(), anyhow::Error> {
+        let mut txn = pool.begin().await?;
+
+        role::check(&mut txn, actor, &self.id, role::Permission::UpdateOrg).await?;
+
+        let update_result = match sqlx::query(UPDATE_NAME_QUERY)
+            .bind(new_name.to_string())
+            .bind(self.id.to_string())
+            .execute(&mut txn)
+            .await
+        {
+            Err(e) if db::sqlx_duplicate(&e) => return Err(db::Err::OrgViolation.into()),
+            Err(e) => return Err(e.into()),
+            Ok(v) => v,
+        };
+
+        if update_result.rows_affected() != 1 {
+            return Err(sqlx::Error::RowNotFound.into());
+        }
+
+        audit::insert(&mut txn, audit::ORG_NAME, schema::ORGS_TABLENAME, &self.id).await?;
+
+        txn.commit().await?;
+
+        // the update to the db was a success, set the internal field
+        self.name = new_name.clone();
+
+        Ok(())
+    }
+
+    /// transfer_ownership makes new_owner the org owner, demoting the
+    /// previous owner to Admin
+    ///
+    /// actor must be permitted to update the org; new_owner must be an Active
+    /// user of the org, otherwise db::Err::UserViolation is returned
+    #[allow(dead_code)]
+    pub async fn transfer_ownership(
+        &mut self,
+        pool: &sqlx::SqlitePool,
+        actor: &Uuid,
+        new_owner: &Uuid,
+    ) -> Result<(), anyhow::Error> {
+        let mut txn = pool.begin().await?;
+
+        role::check(&mut txn, actor, &self.id, role::Permission::UpdateOrg).await?;
+
+        if *new_owner == self.owner {
+            return Err(db::Err::UserViolation.into());
+        }
+        let (new_owner_org, new_owner_status): (String, i64) =
+            match sqlx::query_as(SELECT_USER_ORG_STATUS_QUERY)
+                .bind(new_owner.to_string())
+                .fetch_one(&mut txn)
+                .await
+            {
+                Err(e) if db::sqlx_row_not_found(&e) => return Err(db::Err::UserViolation.into()),
+                Err(e) => return Err(e.into()),
+                Ok(v) => v,
+            };
+        if Uuid::try_parse(&new_owner_org)? != self.id
+            || models::Status::from_int(new_owner_status)? != models::Status::Active
+        {
+            return Err(db::Err::UserViolation.into());
+        }
+
+        // demote first; there is at most one owner per org
+        for (id, new_role) in [
+            (self.owner, role::Role::Admin),
+            (*new_owner, role::Role::Owner),
+        ] {
+            let update_result = sqlx::query(user::UPDATE_ROLE_QUERY)
+                .bind(new_role.to_int())
+                .bind(id.to_string())
+                .bind(models::Status::Deleted.to_int())
+                .execute(&mut txn)
+                .await?;
+            if update_result.rows_affected() != 1 {
+                return Err(sqlx::Error::RowNotFound.into());
+            }
+            audit::insert(&mut txn, audit::USER_ROLE, schema::USERS_TABLENAME, &id).await?;
+        }
+
+        let update_result = sqlx::query(UPDATE_OWNER_QUERY)
+            .bind(new_owner.to_string())
+            .bind(self.id.to_string())
+            .execute(&mut txn)
+            .await?;
+        if update_result.rows_affected() != 1 {
+            return Err(sqlx::Error::RowNotFound.into());
+        }
+        audit::insert(&mut txn, audit::ORG_OWNER, schema::ORGS_TABLENAME, &self.id).await?;
+
+        txn.commit().await?;
+
+        // the update to the db was a success, set the internal field
+        self.owner = *new_owner;
+
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::grokloc::app::admin::user::User;
-    use crate::grokloc::app::schema;
+    use crate::grokloc::app::admin::repository::Repository;
+    use crate::grokloc::app::state;
     use crate::grokloc::crypt;
-    use crate::grokloc::db;
     use anyhow;
 
     #[tokio::test]
@@ -294,6 +637,7 @@ mod tests {
 
         assert_eq!(owner.id, user_read.id);
         assert_eq!(models::Status::Active, user_read.meta.status);
+        assert_eq!(role::Role::Owner, user_read.role);
         assert!(owner.meta.ctime < user_read.meta.ctime);
         assert!(owner.meta.mtime < user_read.meta.mtime);
 
@@ -317,7 +661,7 @@ mod tests {
         let owner_password =
             safe::VarChar::new(&crypt::kdf(&crypt::rand_hex(), crypt::MIN_KDF_ROUNDS))?;
 
-        let (mut org, _) = Org::create(
+        let (mut org, owner) = Org::create(
             &pool,
             &name,
             &owner_display_name,
@@ -328,7 +672,8 @@ mod tests {
         .await?;
 
         // update the status of the org
-        org.update_status(&pool, models::Status::Inactive).await?;
+        org.update_status(&pool, &owner.id, models::Status::Inactive)
+            .await?;
 
         // read the org
         let org_read = match Org::read(&pool, &org.id).await {
@@ -341,6 +686,18 @@ mod tests {
         assert!(org.meta.ctime < org_read.meta.ctime);
         assert!(org.meta.mtime < org_read.meta.mtime);
 
+        // orgs are not deleted
+        match org
+            .update_status(&pool, &owner.id, models::Status::Deleted)
+            .await
+        {
+            Ok(_) => unreachable!(),
+            Err(e) => assert_eq!(Some(&models::Err::Deleted), e.downcast_ref::<models::Err>()),
+        };
+        assert_eq!(models::Status::Inactive, org.meta.status);
+        let org_read = Org::read(&pool, &org.id).await?;
+        assert_eq!(models::Status::Inactive, org_read.meta.status);
+
         Ok(())
     }
 
@@ -366,11 +723,367 @@ mod tests {
             },
         };
 
-        match org.update_status(&pool, models::Status::Active).await {
+        // ...but its owner is
+        let key = crypt::rand_key();
+        let password = safe::VarChar::new(&crypt::kdf(&crypt::rand_hex(), crypt::MIN_KDF_ROUNDS))?;
+        let mut owner = User::encrypted(
+            &safe::VarChar::rand(),
+            &safe::VarChar::rand(),
+            &org.id,
+            &password,
+            &key,
+        )?;
+        owner.role = role::Role::Owner;
+        owner.meta.status = models::Status::Active;
+        org.owner = owner.id;
+        let mut txn = pool.begin().await?;
+        owner.insert(&mut txn).await?;
+        txn.commit().await?;
+
+        match org
+            .update_status(&pool, &owner.id, models::Status::Active)
+            .await
+        {
             Ok(_) => unreachable!(),
             Err(e) => assert!(db::anyhow_sqlx_row_not_found(&e)),
         };
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn org_transfer_ownership_test() -> Result<(), anyhow::Error> {
+        let app = state::unit().await?;
+        let password = safe::VarChar::new(&crypt::kdf(&crypt::rand_hex(), crypt::MIN_KDF_ROUNDS))?;
+        let mut new_owner = User::create(
+            &app.master_pool,
+            &safe::VarChar::rand(),
+            &safe::VarChar::rand(),
+            &app.root_org.id,
+            &password,
+            &app.key,
+        )
+        .await?;
+        let mut org = app.root_org.clone();
+
+        // new owner must be Active
+        match org
+            .transfer_ownership(&app.master_pool, &app.root_user.id, &new_owner.id)
+            .await
+        {
+            Ok(_) => unreachable!(),
+            Err(e) => assert!(matches!(
+                e.downcast_ref::<db::Err>(),
+                Some(db::Err::UserViolation)
+            )),
+        };
+
+        new_owner
+            .update_status(&app.master_pool, &app.root_user.id, models::Status::Active)
+            .await?;
+
+        // only the owner may transfer
+        match org
+            .transfer_ownership(&app.master_pool, &new_owner.id, &new_owner.id)
+            .await
+        {
+            Ok(_) => unreachable!(),
+            Err(e) => assert_eq!(Some(&role::Err::Forbidden), e.downcast_ref::<role::Err>()),
+        };
+
+        org.transfer_ownership(&app.master_pool, &app.root_user.id, &new_owner.id)
+            .await?;
+        assert_eq!(new_owner.id, org.owner);
+
+        let org_read = Org::read(&app.master_pool, &org.id).await?;
+        assert_eq!(new_owner.id, org_read.owner);
+        let new_owner_read = User::read(&app.master_pool, &new_owner.id, &app.key).await?;
+        assert_eq!(role::Role::Owner, new_owner_read.role);
+        let old_owner_read = User::read(&app.master_pool, &app.root_user.id, &app.key).await?;
+        assert_eq!(role::Role::Admin, old_owner_read.role);
+
+        let audit_count: i64 = sqlx::query_scalar(
+            "select count(*) from audit where (source_id = ? or source_id = ?) and code = ?",
+        )
+        .bind(new_owner.id.to_string())
+        .bind(app.root_user.id.to_string())
+        .bind(audit::USER_ROLE)
+        .fetch_one(&app.master_pool)
+        .await?;
+        assert_eq!(2, audit_count);
+
+        // a user of another org cannot become owner
+        let (other_org, other_owner) = Org::create(
+            &app.master_pool,
+            &safe::VarChar::rand(),
+            &safe::VarChar::rand(),
+            &safe::VarChar::rand(),
+            &password,
+            &app.key,
+        )
+        .await?;
+        match org
+            .transfer_ownership(&app.master_pool, &new_owner.id, &other_owner.id)
+            .await
+        {
+            Ok(_) => unreachable!(),
+            Err(e) => assert!(matches!(
+                e.downcast_ref::<db::Err>(),
+                Some(db::Err::UserViolation)
+            )),
+        };
+        assert_eq!(
+            other_owner.id,
+            Org::read(&app.master_pool, &other_org.id).await?.owner
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn org_list_test() -> Result<(), anyhow::Error> {
+        let app = state::unit().await?;
+        let password = safe::VarChar::new(&crypt::kdf(&crypt::rand_hex(), crypt::MIN_KDF_ROUNDS))?;
+
+        // five orgs named with a common prefix, plus the root org
+        let prefix = "listed-";
+        let mut ids = Vec::new();
+        for _ in 0..5 {
+            let (org, _) = Org::create(
+                &app.master_pool,
+                &safe::VarChar::new(&format!("{}{}", prefix, crypt::rand_hex()))?,
+                &safe::VarChar::rand(),
+                &safe::VarChar::rand(),
+                &password,
+                &app.key,
+            )
+            .await?;
+            ids.push(org.id);
+        }
+
+        let filter = ListFilter {
+            name_prefix: Some(safe::VarChar::new(prefix)?),
+            ..Default::default()
+        };
+
+        // page through two at a time; a matching org inserted between pages
+        // does not disturb paging
+        let mut listed = Vec::new();
+        let mut inserted = None;
+        let mut cursor: Option<models::Cursor> = None;
+        loop {
+            let (page, next) = Org::list(&app.master_pool, &filter, cursor.as_ref(), 2).await?;
+            assert!(page.len() <= 2);
+            listed.extend(page.iter().map(|v| v.id));
+            if inserted.is_none() {
+                let (org, _) = Org::create(
+                    &app.master_pool,
+                    &safe::VarChar::new(&format!("{}{}", prefix, crypt::rand_hex()))?,
+                    &safe::VarChar::rand(),
+                    &safe::VarChar::rand(),
+                    &password,
+                    &app.key,
+                )
+                .await?;
+                inserted = Some(org.id);
+            }
+            match next {
+                // round trip the cursor through its string form
+                Some(v) => cursor = Some(v.to_string().parse::<models::Cursor>()?),
+                None => break,
+            }
+        }
+        // every pre-existing prefixed org exactly once; the inserted org is
+        // listed at most once, depending on where it sorts against the cursor
+        let inserted = inserted.unwrap_or_default();
+        listed.sort();
+        let mut deduplicated = listed.clone();
+        deduplicated.dedup();
+        assert_eq!(listed, deduplicated);
+        listed.retain(|v| *v != inserted);
+        ids.sort();
+        assert_eq!(ids, listed);
+
+        // status filter
+        let filter = ListFilter {
+            status: Some(models::Status::Inactive),
+            ..Default::default()
+        };
+        let (page, next) = Org::list(&app.master_pool, &filter, None, 10).await?;
+        assert!(page.is_empty());
+        assert!(next.is_none());
+
+        let mut root_org = app.root_org.clone();
+        root_org
+            .update_status(
+                &app.master_pool,
+                &app.root_user.id,
+                models::Status::Inactive,
+            )
+            .await?;
+        let (page, _) = Org::list(&app.master_pool, &filter, None, 10).await?;
+        assert_eq!(1, page.len());
+        assert_eq!(root_org.id, page[0].id);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn org_update_name_test() -> Result<(), anyhow::Error> {
+        let app = state::unit().await?;
+        let password = safe::VarChar::new(&crypt::kdf(&crypt::rand_hex(), crypt::MIN_KDF_ROUNDS))?;
+        let mut org = app.root_org.clone();
+
+        let new_name = safe::VarChar::rand();
+        org.update_name(&app.master_pool, &app.root_user.id, &new_name)
+            .await?;
+        assert_eq!(new_name, org.name);
+        assert_eq!(new_name, Org::read(&app.master_pool, &org.id).await?.name);
+
+        // name in use by another org
+        let (other_org, _) = Org::create(
+            &app.master_pool,
+            &safe::VarChar::rand(),
+            &safe::VarChar::rand(),
+            &safe::VarChar::rand(),
+            &password,
+            &app.key,
+        )
+        .await?;
+        match org
+            .update_name(&app.master_pool, &app.root_user.id, &other_org.name)
+            .await
+        {
+            Ok(_) => unreachable!(),
+            Err(e) => assert!(matches!(
+                e.downcast_ref::<db::Err>(),
+                Some(db::Err::OrgViolation)
+            )),
+        };
+        assert_eq!(new_name, org.name);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn org_update_status_cascade_test() -> Result<(), anyhow::Error> {
+        let app = state::unit().await?;
+        let password = safe::VarChar::new(&crypt::kdf(&crypt::rand_hex(), crypt::MIN_KDF_ROUNDS))?;
+        let mut org = app.root_org.clone();
+        let owner = User::read(&app.master_pool, &app.root_user.id, &app.key).await?;
+
+        // an Active user, an Inactive user and an Unconfirmed user
+        let mut active = User::create(
+            &app.master_pool,
+            &safe::VarChar::rand(),
+            &safe::VarChar::rand(),
+            &org.id,
+            &password,
+            &app.key,
+        )
+        .await?;
+        active
+            .update_status(&app.master_pool, &owner.id, models::Status::Active)
+            .await?;
+        let mut inactive = User::create(
+            &app.master_pool,
+            &safe::VarChar::rand(),
+            &safe::VarChar::rand(),
+            &org.id,
+            &password,
+            &app.key,
+        )
+        .await?;
+        inactive
+            .update_status(&app.master_pool, &owner.id, models::Status::Inactive)
+            .await?;
+        let unconfirmed = User::create(
+            &app.master_pool,
+            &safe::VarChar::rand(),
+            &safe::VarChar::rand(),
+            &org.id,
+            &password,
+            &app.key,
+        )
+        .await?;
+
+        // an Active and an Inactive repository
+        let repository = Repository::create(
+            &app.master_pool,
+            &owner.id,
+            &safe::VarChar::rand(),
+            &org.id,
+            &safe::VarChar::rand(),
+            &app.repo_base,
+        )
+        .await?;
+        let mut inactive_repository = Repository::create(
+            &app.master_pool,
+            &owner.id,
+            &safe::VarChar::rand(),
+            &org.id,
+            &safe::VarChar::rand(),
+            &app.repo_base,
+        )
+        .await?;
+        inactive_repository
+            .update_status(&app.master_pool, &owner.id, models::Status::Inactive)
+            .await?;
+
+        assert_eq!(
+            owner.id,
+            User::authenticate_api_secret(&app.master_pool, &owner.api_secret.to_string()).await?
+        );
+
+        org.update_status(&app.master_pool, &owner.id, models::Status::Inactive)
+            .await?;
+
+        for id in [active.id, inactive.id, unconfirmed.id] {
+            let user_read = User::read(&app.master_pool, &id, &app.key).await?;
+            assert_eq!(models::Status::Inactive, user_read.meta.status);
+        }
+        for id in [repository.id, inactive_repository.id] {
+            let repository_read = Repository::read(&app.master_pool, &id).await?;
+            assert_eq!(models::Status::Inactive, repository_read.meta.status);
+        }
+
+        // the owner remains Active so the org can be reactivated,
+        // but cannot authenticate while the org is Inactive
+        let owner_read = User::read(&app.master_pool, &owner.id, &app.key).await?;
+        assert_eq!(models::Status::Active, owner_read.meta.status);
+        match User::authenticate_api_secret(&app.master_pool, &owner.api_secret.to_string()).await {
+            Ok(_) => unreachable!(),
+            Err(e) => assert!(db::anyhow_sqlx_row_not_found(&e)),
+        };
+
+        org.update_status(&app.master_pool, &owner.id, models::Status::Active)
+            .await?;
+
+        for (id, status) in [
+            (active.id, models::Status::Active),
+            (inactive.id, models::Status::Inactive),
+            (unconfirmed.id, models::Status::Unconfirmed),
+        ] {
+            let user_read = User::read(&app.master_pool, &id, &app.key).await?;
+            assert_eq!(status, user_read.meta.status);
+        }
+        for (id, status) in [
+            (repository.id, models::Status::Active),
+            (inactive_repository.id, models::Status::Inactive),
+        ] {
+            let repository_read = Repository::read(&app.master_pool, &id).await?;
+            assert_eq!(status, repository_read.meta.status);
+        }
+        assert_eq!(
+            owner.id,
+            User::authenticate_api_secret(&app.master_pool, &owner.api_secret.to_string()).await?
+        );
+
+        let cascade_count: i64 = sqlx::query_scalar("select count(*) from status_cascade")
+            .fetch_one(&app.master_pool)
+            .await?;
+        assert_eq!(0, cascade_count);
+
+        Ok(())
+    }
 }
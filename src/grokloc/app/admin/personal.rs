@@ -0,0 +1,184 @@
+//! personal gathers the data held about a user for data subject access requests
+use crate::grokloc::app::admin::org::Org;
+use crate::grokloc::app::admin::role;
+use crate::grokloc::app::admin::user::User;
+use crate::grokloc::app::schema;
+use crate::grokloc::db;
+use anyhow;
+use serde::Serialize;
+use sqlx;
+use uuid::Uuid;
+
+/// SCHEMA_VERSION is the version of the exported document layout;
+/// it changes only when fields are removed or change meaning
+pub const SCHEMA_VERSION: i8 = 0;
+
+pub const SELECT_AUDIT_QUERY: &str = r#"
+select id, code, ctime from audit where source = ? and source_id = ? order by ctime, id
+"#;
+
+/// PersonalData is the document describing everything held about a user
+///
+/// credentials (api secret and password derivation) are deliberately not exported
+#[derive(Clone, Debug, Serialize)]
+pub struct PersonalData {
+    pub schema_version: i8,
+    pub generated: i64,
+    pub profile: Profile,
+    pub org: Membership,
+    pub audit: Vec<AuditEntry>,
+}
+
+/// Profile is the decrypted user record
+#[derive(Clone, Debug, Serialize)]
+pub struct Profile {
+    pub id: String,
+    pub display_name: String,
+    pub email: String,
+    pub status: String,
+    pub ctime: i64,
+    pub mtime: i64,
+}
+
+/// Membership describes the org of the user and the user role within it
+#[derive(Clone, Debug, Serialize)]
+pub struct Membership {
+    pub id: String,
+    pub name: String,
+    pub role: String,
+}
+
+/// AuditEntry is an audit row with the user as source
+#[derive(Clone, Debug, Serialize)]
+pub struct AuditEntry {
+    pub id: String,
+    pub code: i64,
+    pub ctime: i64,
+}
+
+impl PersonalData {
+    /// gather reads and decrypts everything held about the user id
+    ///
+    /// actor must be the user, or be permitted to manage users in its org;
+    /// a missing user is reported as forbidden to other actors, so that
+    /// gather does not reveal which ids exist
+    #[allow(dead_code)]
+    pub async fn gather(
+        pool: &sqlx::SqlitePool,
+        actor: &Uuid,
+        id: &Uuid,
+        key: &str,
+    ) -> Result<Self, anyhow::Error> {
+        let user = match User::read(pool, id, key).await {
+            Err(e) if actor != id && db::anyhow_sqlx_row_not_found(&e) => {
+                return Err(role::Err::Forbidden.into())
+            }
+            Err(e) => return Err(e),
+            Ok(v) => v,
+        };
+        if actor != id {
+            role::check(pool, actor, &user.org, role::Permission::ManageUsers).await?;
+        }
+        let org = Org::read(pool, &user.org).await?;
+
+        let audit_rows: Vec<(String, i64, i64)> = sqlx::query_as(SELECT_AUDIT_QUERY)
+            .bind(schema::USERS_TABLENAME)
+            .bind(id.to_string())
+            .fetch_all(pool)
+            .await?;
+
+        Ok(PersonalData {
+            schema_version: SCHEMA_VERSION,
+            generated: chrono::Utc::now().timestamp(),
+            profile: Profile {
+                id: user.id.to_string(),
+                display_name: user.display_name.to_string(),
+                email: user.email.to_string(),
+                status: user.meta.status.to_string(),
+                ctime: user.meta.ctime.timestamp(),
+                mtime: user.meta.mtime.timestamp(),
+            },
+            org: Membership {
+                id: org.id.to_string(),
+                name: org.name.to_string(),
+                role: user.role.to_string(),
+            },
+            audit: audit_rows
+                .into_iter()
+                .map(|(id, code, ctime)| AuditEntry { id, code, ctime })
+                .collect(),
+        })
+    }
+
+    /// to_json renders the document as JSON
+    #[allow(dead_code)]
+    pub fn to_json(&self) -> Result<String, anyhow::Error> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grokloc::app::audit;
+    use crate::grokloc::app::state;
+    use crate::grokloc::crypt;
+    use crate::grokloc::safe;
+
+    #[tokio::test]
+    async fn personal_data_test() -> Result<(), anyhow::Error> {
+        let app = state::unit().await?;
+        let password = safe::VarChar::new(&crypt::kdf(&crypt::rand_hex(), crypt::MIN_KDF_ROUNDS))?;
+        let display_name = safe::VarChar::rand();
+        let email = safe::VarChar::rand();
+        let user = User::create(
+            &app.master_pool,
+            &display_name,
+            &email,
+            &app.root_org.id,
+            &password,
+            &app.key,
+        )
+        .await?;
+
+        let data = PersonalData::gather(&app.master_pool, &user.id, &user.id, &app.key).await?;
+        let doc: serde_json::Value = serde_json::from_str(&data.to_json()?)?;
+
+        assert_eq!(SCHEMA_VERSION as i64, doc["schema_version"]);
+        assert_eq!(user.id.to_string(), doc["profile"]["id"]);
+        assert_eq!(display_name.to_string(), doc["profile"]["display_name"]);
+        assert_eq!(email.to_string(), doc["profile"]["email"]);
+        assert_eq!("Unconfirmed", doc["profile"]["status"]);
+        assert_eq!(app.root_org.id.to_string(), doc["org"]["id"]);
+        assert_eq!(app.root_org.name.to_string(), doc["org"]["name"]);
+        assert_eq!("Member", doc["org"]["role"]);
+        assert_eq!(1, doc["audit"].as_array().map(|v| v.len()).unwrap_or(0));
+        assert_eq!(audit::USER_INSERT, doc["audit"][0]["code"]);
+
+        // no credentials
+        let json = data.to_json()?;
+        let user_read = User::read(&app.master_pool, &user.id, &app.key).await?;
+        assert!(!json.contains(&user_read.api_secret.to_string()));
+        assert!(!json.contains(&user_read.password.to_string()));
+
+        // the org owner may export, other members may not
+        PersonalData::gather(&app.master_pool, &app.root_user.id, &user.id, &app.key).await?;
+        match PersonalData::gather(&app.master_pool, &user.id, &app.root_user.id, &app.key).await {
+            Ok(_) => unreachable!(),
+            Err(e) => assert_eq!(Some(&role::Err::Forbidden), e.downcast_ref::<role::Err>()),
+        };
+
+        // a missing user is not distinguished from a forbidden one
+        let missing = Uuid::new_v4();
+        match PersonalData::gather(&app.master_pool, &user.id, &missing, &app.key).await {
+            Ok(_) => unreachable!(),
+            Err(e) => assert_eq!(Some(&role::Err::Forbidden), e.downcast_ref::<role::Err>()),
+        };
+        match PersonalData::gather(&app.master_pool, &missing, &missing, &app.key).await {
+            Ok(_) => unreachable!(),
+            Err(e) => assert!(db::anyhow_sqlx_row_not_found(&e)),
+        };
+
+        Ok(())
+    }
+}
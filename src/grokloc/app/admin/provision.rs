@@ -0,0 +1,528 @@
+//! provision creates users in bulk from CSV or JSON, issuing each an invite token
+use crate::grokloc::app::admin::role;
+use crate::grokloc::app::admin::token;
+use crate::grokloc::app::admin::user::User;
+use crate::grokloc::app::audit;
+use crate::grokloc::app::models;
+use crate::grokloc::app::schema;
+use crate::grokloc::crypt;
+use crate::grokloc::safe;
+use anyhow;
+use serde::Deserialize;
+use sqlx;
+use std::collections::HashSet;
+use thiserror::Error;
+use uuid::Uuid;
+
+/// DEFAULT_BATCH_SIZE is the number of users inserted per transaction
+pub const DEFAULT_BATCH_SIZE: usize = 100;
+
+/// DEFAULT_TOKEN_TTL_DAYS is the lifetime of an invite token
+pub const DEFAULT_TOKEN_TTL_DAYS: i64 = 7;
+
+pub const SELECT_EMAIL_EXISTS_QUERY: &str = r#"
+select count(*) from users where email_digest = ? and org = ?
+"#;
+
+pub const ACTIVATE_QUERY: &str = r#"
+update users set
+ password = ?,
+ status = ?
+where id = ?
+and status = ?
+and org in (select id from orgs where status = ?)
+"#;
+
+/// Err describes why a row cannot be provisioned
+#[derive(Debug, Error, PartialEq)]
+pub enum Err {
+    #[error("malformed row")]
+    Malformed,
+    #[error("unsafe display name")]
+    DisplayName,
+    #[error("invalid email")]
+    Email,
+    #[error("unknown or disallowed role")]
+    Role,
+    #[error("email repeated in input")]
+    DuplicateEmail,
+    #[error("email already in org")]
+    ExistingEmail,
+    #[error("batch insert failed")]
+    Batch,
+}
+
+/// Format is the encoding of the provisioning input
+///
+/// CSV input has a header row naming display_name, email and (optionally)
+/// role columns; JSON input is an array of objects with the same fields
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[allow(dead_code)]
+pub enum Format {
+    Csv,
+    Json,
+}
+
+/// Options control a provisioning run
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// validate only, inserting nothing
+    pub dry_run: bool,
+    pub batch_size: usize,
+    pub token_ttl: chrono::Duration,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            dry_run: false,
+            batch_size: DEFAULT_BATCH_SIZE,
+            token_ttl: chrono::Duration::days(DEFAULT_TOKEN_TTL_DAYS),
+        }
+    }
+}
+
+/// Record is a row of input before validation; role defaults to Member
+#[derive(Clone, Debug, Deserialize)]
+struct Record {
+    display_name: String,
+    email: String,
+    role: Option<String>,
+}
+
+/// Entry is a validated row of input
+#[derive(Clone, Debug)]
+pub struct Entry {
+    pub row: usize,
+    pub display_name: safe::VarChar,
+    pub email: safe::VarChar,
+    pub role: role::Role,
+}
+
+/// RowError reports the problem with a row; rows are numbered from 1,
+/// not counting a CSV header
+#[derive(Debug, PartialEq)]
+pub struct RowError {
+    pub row: usize,
+    pub err: Err,
+}
+
+/// Invite is a provisioned user and the token to deliver to it
+#[derive(Clone, Debug)]
+#[allow(dead_code)]
+pub struct Invite {
+    pub row: usize,
+    pub user: Uuid,
+    pub email: safe::VarChar,
+    pub token: String,
+}
+
+/// Report is the outcome of a provisioning run
+#[derive(Debug, Default)]
+pub struct Report {
+    pub errors: Vec<RowError>,
+    pub invites: Vec<Invite>,
+    /// why the batch reported as Err::Batch failed, if one did
+    pub batch_error: Option<anyhow::Error>,
+}
+
+/// parse reads and validates every row of input, without reference to the db
+///
+/// a document that cannot be read at all is an error; problems with
+/// individual rows are returned alongside the valid entries
+pub fn parse(input: &str, format: Format) -> Result<(Vec<Entry>, Vec<RowError>), anyhow::Error> {
+    let records: Vec<Result<Record, Err>> = match format {
+        Format::Csv => csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(input.as_bytes())
+            .deserialize()
+            .map(|v| v.map_err(|_| Err::Malformed))
+            .collect(),
+        Format::Json => serde_json::from_str::<Vec<serde_json::Value>>(input)?
+            .into_iter()
+            .map(|v| serde_json::from_value(v).map_err(|_| Err::Malformed))
+            .collect(),
+    };
+
+    let mut entries = Vec::new();
+    let mut errors = Vec::new();
+    let mut seen = HashSet::new();
+    for (i, record) in records.into_iter().enumerate() {
+        let row = i + 1;
+        match validate(record) {
+            Ok(entry) if !seen.insert(entry.email.to_string()) => errors.push(RowError {
+                row,
+                err: Err::DuplicateEmail,
+            }),
+            Ok(entry) => entries.push(Entry { row, ..entry }),
+            Err(err) => errors.push(RowError { row, err }),
+        }
+    }
+    Ok((entries, errors))
+}
+
+/// validate checks a single record; the Entry row is set by the caller
+///
+/// the email is normalised (see safe::email_normal), so that repeats in
+/// input are found as they are against existing users
+fn validate(record: Result<Record, Err>) -> Result<Entry, Err> {
+    let record = record?;
+    let display_name = safe::VarChar::new(&record.display_name).map_err(|_| Err::DisplayName)?;
+    if !safe::email_ok(&record.email) {
+        return Err(Err::Email);
+    }
+    let email = safe::VarChar::trusted(&safe::email_normal(&record.email));
+    // the single owner is only changed by ownership transfer
+    let role = match record.role.as_deref().map(str::trim) {
+        None | Some("") => role::Role::default(),
+        Some(v) => match v.parse() {
+            Ok(role::Role::Owner) | Err(_) => return Err(Err::Role),
+            Ok(v) => v,
+        },
+    };
+    Ok(Entry {
+        row: 0,
+        display_name,
+        email,
+        role,
+    })
+}
+
+/// provision creates an Unconfirmed user in org for each row of input and
+/// issues each an invite token (see activate)
+///
+/// every row is validated, including against existing org users, before
+/// anything is inserted; if any row is invalid, or options.dry_run is set,
+/// only the errors are reported; users are then inserted in transactions
+/// of options.batch_size; a batch that fails is rolled back and reported as
+/// Err::Batch for each of its rows with the cause in Report::batch_error,
+/// ending the run, but earlier batches stay committed and their invites are
+/// reported; actor must be permitted to manage users
+#[allow(dead_code)]
+pub async fn provision(
+    pool: &sqlx::SqlitePool,
+    actor: &Uuid,
+    org: &Uuid,
+    input: &str,
+    format: Format,
+    options: &Options,
+    key: &str,
+) -> Result<Report, anyhow::Error> {
+    role::check(pool, actor, org, role::Permission::ManageUsers).await?;
+
+    let (entries, mut errors) = parse(input, format)?;
+    for entry in &entries {
+        let count: i64 = sqlx::query_scalar(SELECT_EMAIL_EXISTS_QUERY)
+            .bind(crypt::sha256_hex(&entry.email.to_string()))
+            .bind(org.to_string())
+            .fetch_one(pool)
+            .await?;
+        if count != 0 {
+            errors.push(RowError {
+                row: entry.row,
+                err: Err::ExistingEmail,
+            });
+        }
+    }
+    errors.sort_by_key(|v| v.row);
+
+    let mut report = Report {
+        errors,
+        ..Default::default()
+    };
+    if options.dry_run || !report.errors.is_empty() {
+        return Ok(report);
+    }
+
+    for batch in entries.chunks(options.batch_size.max(1)) {
+        match insert_batch(pool, org, batch, options.token_ttl, key).await {
+            Ok(invites) => report.invites.extend(invites),
+            Err(e) => {
+                report.batch_error = Some(e);
+                report.errors = batch
+                    .iter()
+                    .map(|v| RowError {
+                        row: v.row,
+                        err: Err::Batch,
+                    })
+                    .collect();
+                break;
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+/// insert_batch inserts and issues tokens for entries in one transaction
+async fn insert_batch(
+    pool: &sqlx::SqlitePool,
+    org: &Uuid,
+    entries: &[Entry],
+    token_ttl: chrono::Duration,
+    key: &str,
+) -> Result<Vec<Invite>, anyhow::Error> {
+    let mut invites = Vec::with_capacity(entries.len());
+    let mut txn = pool.begin().await?;
+    for entry in entries {
+        // unusable until replaced on activation
+        let password =
+            safe::VarChar::trusted(&crypt::kdf(&crypt::rand_hex(), crypt::MIN_KDF_ROUNDS));
+        let mut user = User::encrypted(&entry.display_name, &entry.email, org, &password, key)?;
+        user.role = entry.role;
+        user.insert_checked(&mut txn).await?;
+        invites.push(Invite {
+            row: entry.row,
+            user: user.id,
+            email: entry.email.clone(),
+            token: token::issue(&mut txn, &user.id, token_ttl).await?,
+        });
+    }
+    txn.commit().await?;
+    Ok(invites)
+}
+
+/// activate redeems an invite token, setting the password of the
+/// Unconfirmed user it was issued for and making the user Active
+///
+/// an unusable token is reported as token::Err::Invalid; a user that is no
+/// longer Unconfirmed, or whose org is not Active, is reported as not found
+#[allow(dead_code)]
+pub async fn activate(
+    pool: &sqlx::SqlitePool,
+    invite_token: &str,
+    password: &safe::VarChar, // assumed already derived
+) -> Result<Uuid, anyhow::Error> {
+    let mut txn = pool.begin().await?;
+    let user = token::redeem(&mut txn, invite_token).await?;
+
+    let update_result = sqlx::query(ACTIVATE_QUERY)
+        .bind(password.to_string())
+        .bind(models::Status::Active.to_int())
+        .bind(user.to_string())
+        .bind(models::Status::Unconfirmed.to_int())
+        .bind(models::Status::Active.to_int())
+        .execute(&mut txn)
+        .await?;
+    if update_result.rows_affected() != 1 {
+        return Err(sqlx::Error::RowNotFound.into());
+    }
+
+    audit::insert(
+        &mut txn,
+        audit::USER_ACTIVATE,
+        schema::USERS_TABLENAME,
+        &user,
+    )
+    .await?;
+
+    txn.commit().await?;
+
+    Ok(user)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grokloc::app::state;
+
+    #[test]
+    fn provision_parse_test() -> Result<(), anyhow::Error> {
+        let csv_input = "display_name,email,role\n\
+                         Ann,ann@example.com,admin\n\
+                         Bob,bob@example.com,\n\
+                         Cy,not-an-email,member\n\
+                         Di,di@example.com,owner\n\
+                         Ed,ANN@example.com,member\n\
+                         select x,fy@example.com,readonly\n\
+                         Gil,gil@example.com,member,extra\n";
+        let (entries, errors) = parse(csv_input, Format::Csv)?;
+        assert_eq!(2, entries.len());
+        assert_eq!(role::Role::Admin, entries[0].role);
+        assert_eq!(role::Role::Member, entries[1].role);
+        assert_eq!(
+            vec![
+                RowError {
+                    row: 3,
+                    err: Err::Email
+                },
+                RowError {
+                    row: 4,
+                    err: Err::Role
+                },
+                RowError {
+                    row: 5,
+                    err: Err::DuplicateEmail
+                },
+                RowError {
+                    row: 6,
+                    err: Err::DisplayName
+                },
+                RowError {
+                    row: 7,
+                    err: Err::Malformed
+                },
+            ],
+            errors
+        );
+
+        let json_input = r#"[
+            {"display_name": "Ann", "email": "ann@example.com", "role": "ReadOnly"},
+            {"display_name": "Bob"}
+        ]"#;
+        let (entries, errors) = parse(json_input, Format::Json)?;
+        assert_eq!(1, entries.len());
+        assert_eq!(role::Role::ReadOnly, entries[0].role);
+        assert_eq!(
+            vec![RowError {
+                row: 2,
+                err: Err::Malformed
+            }],
+            errors
+        );
+
+        assert!(parse("{", Format::Json).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn provision_test() -> Result<(), anyhow::Error> {
+        let app = state::unit().await?;
+        let org = app.root_org.id;
+        let input = (0..5)
+            .map(|i| format!("user{},user{}@example.com,member", i, i))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let input = format!("display_name,email,role\n{}", input);
+
+        // dry run inserts nothing
+        let options = Options {
+            dry_run: true,
+            batch_size: 2,
+            ..Default::default()
+        };
+        let report = provision(
+            &app.master_pool,
+            &app.root_user.id,
+            &org,
+            &input,
+            Format::Csv,
+            &options,
+            &app.key,
+        )
+        .await?;
+        assert!(report.errors.is_empty());
+        assert!(report.invites.is_empty());
+
+        let options = Options {
+            dry_run: false,
+            ..options
+        };
+        let report = provision(
+            &app.master_pool,
+            &app.root_user.id,
+            &org,
+            &input,
+            Format::Csv,
+            &options,
+            &app.key,
+        )
+        .await?;
+        assert!(report.errors.is_empty());
+        assert_eq!(5, report.invites.len());
+        for invite in &report.invites {
+            let user = User::read(&app.master_pool, &invite.user, &app.key).await?;
+            assert_eq!(invite.email, user.email);
+            assert_eq!(models::Status::Unconfirmed, user.meta.status);
+        }
+
+        // a second run finds every email already in the org
+        let report = provision(
+            &app.master_pool,
+            &app.root_user.id,
+            &org,
+            &input,
+            Format::Csv,
+            &options,
+            &app.key,
+        )
+        .await?;
+        assert_eq!(5, report.errors.len());
+        assert!(report.errors.iter().all(|v| v.err == Err::ExistingEmail));
+        assert!(report.invites.is_empty());
+
+        // emails are compared to existing users as they are to each other
+        let report = provision(
+            &app.master_pool,
+            &app.root_user.id,
+            &org,
+            "display_name,email\nUser,USER0@example.com\n",
+            Format::Csv,
+            &options,
+            &app.key,
+        )
+        .await?;
+        assert_eq!(
+            vec![RowError {
+                row: 1,
+                err: Err::ExistingEmail
+            }],
+            report.errors
+        );
+        assert!(report.batch_error.is_none());
+
+        // activation sets the password and status, once
+        let invite = provision_one(&app, &org).await?;
+        let password = safe::VarChar::new(&crypt::kdf(&crypt::rand_hex(), crypt::MIN_KDF_ROUNDS))?;
+        assert_eq!(
+            invite.user,
+            activate(&app.master_pool, &invite.token, &password).await?
+        );
+        let user = User::read(&app.master_pool, &invite.user, &app.key).await?;
+        assert_eq!(models::Status::Active, user.meta.status);
+        assert_eq!(password, user.password);
+        match activate(&app.master_pool, &invite.token, &password).await {
+            Ok(_) => unreachable!(),
+            Err(e) => assert_eq!(Some(&token::Err::Invalid), e.downcast_ref::<token::Err>()),
+        };
+
+        // only permitted actors may provision
+        match provision(
+            &app.master_pool,
+            &user.id,
+            &org,
+            &input,
+            Format::Csv,
+            &options,
+            &app.key,
+        )
+        .await
+        {
+            Ok(_) => unreachable!(),
+            Err(e) => assert_eq!(Some(&role::Err::Forbidden), e.downcast_ref::<role::Err>()),
+        };
+
+        Ok(())
+    }
+
+    /// provision_one provisions a single user from JSON
+    async fn provision_one(app: &state::App, org: &Uuid) -> Result<Invite, anyhow::Error> {
+        let input = format!(
+            r#"[{{"display_name": "x", "email": "{}@example.com"}}]"#,
+            crypt::rand_hex()
+        );
+        let mut report = provision(
+            &app.master_pool,
+            &app.root_user.id,
+            org,
+            &input,
+            Format::Json,
+            &Options::default(),
+            &app.key,
+        )
+        .await?;
+        assert!(report.errors.is_empty());
+        assert_eq!(1, report.invites.len());
+        Ok(report.invites.remove(0))
+    }
+}
@@ -0,0 +1,534 @@
+//! repository models a repositories row and related db functionality
+use crate::grokloc::app::admin::role;
+use crate::grokloc::app::admin::team;
+use crate::grokloc::app::audit;
+use crate::grokloc::app::models;
+use crate::grokloc::app::schema;
+use crate::grokloc::db;
+use crate::grokloc::safe;
+use anyhow;
+use sqlx;
+use sqlx::sqlite::SqliteRow;
+use sqlx::Row;
+use uuid::Uuid;
+
+pub const SCHEMA_VERSION: i8 = 0;
+
+pub const INSERT_QUERY: &str = r#"
+insert into repositories
+(id,
+ name,
+ org,
+ path,
+ upstream,
+ schema_version,
+ status)
+values
+(?,?,?,?,?,?,?)
+"#;
+
+pub const SELECT_QUERY: &str = r#"
+select
+ id,
+ name,
+ org,
+ path,
+ upstream,
+ ctime,
+ mtime,
+ schema_version,
+ status
+from repositories
+where id = ?
+"#;
+
+/// LIST_VISIBLE_QUERY selects the repositories of an Active org that an
+/// Active user of the org may read: all of them for owners and admins,
+/// otherwise those granted to a team of the user
+pub const LIST_VISIBLE_QUERY: &str = r#"
+select
+ r.id,
+ r.name,
+ r.org,
+ r.path,
+ r.upstream,
+ r.ctime,
+ r.mtime,
+ r.schema_version,
+ r.status
+from repositories r
+join users u on u.id = ? and u.org = r.org and u.status = ?
+join orgs o on o.id = r.org and o.status = ?
+where r.org = ?
+and (u.role in (?, ?)
+     or exists (select 1
+                from repository_grants g
+                join team_members m on m.team = g.team
+                where g.repository = r.id and m.user = u.id))
+and (r.ctime > ? or (r.ctime = ? and r.id > ?))
+order by r.ctime, r.id
+limit ?
+"#;
+
+pub const SELECT_ORG_STATUS_QUERY: &str = r#"
+select status from orgs where id = ?;
+"#;
+
+pub const UPDATE_STATUS_QUERY: &str = r#"
+update repositories set status = ? where id = ?;
+"#;
+
+/// Repository is the data representation of a repositories row
+///
+/// path is the location of the local clone of upstream
+#[derive(Clone, Debug)]
+pub struct Repository {
+    pub id: Uuid,
+    pub name: safe::VarChar,
+    pub org: Uuid,
+    pub path: safe::VarChar,
+    pub upstream: safe::VarChar,
+    pub meta: models::Meta,
+}
+
+impl Repository {
+    /// insert performs db insert with no integrity check on the org (see create)
+    #[allow(dead_code)]
+    pub async fn insert(
+        &self,
+        txn: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    ) -> Result<(), anyhow::Error> {
+        if let Err(insert_error) = sqlx::query(INSERT_QUERY)
+            .bind(self.id.to_string())
+            .bind(self.name.to_string())
+            .bind(self.org.to_string())
+            .bind(self.path.to_string())
+            .bind(self.upstream.to_string())
+            .bind(self.meta.schema_version)
+            .bind(self.meta.status.to_int())
+            .execute(txn)
+            .await
+        {
+            return Err(insert_error.into());
+        }
+
+        Ok(())
+    }
+
+    /// create forms a new Active Repository in an existing Active org,
+    /// with its local clone path under repo_base
+    ///
+    /// actor must be permitted to manage repositories; a duplicate name
+    /// within the org is reported as db::Err::RepositoryViolation
+    #[allow(dead_code)]
+    pub async fn create(
+        pool: &sqlx::SqlitePool,
+        actor: &Uuid,
+        name: &safe::VarChar,
+        org: &Uuid,
+        upstream: &safe::VarChar,
+        repo_base: &str,
+    ) -> Result<Self, anyhow::Error> {
+        let id = Uuid::new_v4();
+        let repository = Self {
+            id,
+            name: name.clone(),
+            org: *org,
+            path: safe::VarChar::new(&format!("{}/{}/{}", repo_base, org, id))?,
+            upstream: upstream.clone(),
+            meta: models::Meta {
+                status: models::Status::Active,
+                schema_version: SCHEMA_VERSION,
+                ..Default::default()
+            },
+        };
+
+        let mut txn = pool.begin().await?;
+
+        role::check(&mut txn, actor, org, role::Permission::ManageRepositories).await?;
+
+        let org_status: i64 = sqlx::query_scalar(SELECT_ORG_STATUS_QUERY)
+            .bind(org.to_string())
+            .fetch_one(&mut txn)
+            .await?;
+        if models::Status::from_int(org_status)? != models::Status::Active {
+            return Err(db::Err::OrgViolation.into());
+        }
+
+        if let Err(insert_error) = repository.insert(&mut txn).await {
+            if db::anyhow_sqlx_duplicate(&insert_error) {
+                return Err(db::Err::RepositoryViolation.into());
+            }
+            return Err(insert_error);
+        }
+
+        audit::insert(
+            &mut txn,
+            audit::REPOSITORY_INSERT,
+            schema::REPOSITORIES_TABLENAME,
+            &repository.id,
+        )
+        .await?;
+
+        txn.commit().await?;
+
+        Ok(repository)
+    }
+
+    /// read selects a repositories row to construct a Repository instance,
+    /// with no access check (see read_as)
+    #[allow(dead_code)]
+    pub async fn read(pool: &sqlx::SqlitePool, id: &Uuid) -> Result<Self, anyhow::Error> {
+        let row = sqlx::query(SELECT_QUERY)
+            .bind(id.to_string())
+            .fetch_one(pool)
+            .await?;
+
+        Self::from_row(&row)
+    }
+
+    /// read_as is read on behalf of actor, who must have read access
+    #[allow(dead_code)]
+    pub async fn read_as(
+        pool: &sqlx::SqlitePool,
+        actor: &Uuid,
+        id: &Uuid,
+    ) -> Result<Self, anyhow::Error> {
+        team::require_access(pool, actor, id, team::Access::Read).await?;
+        Self::read(pool, id).await
+    }
+
+    /// list selects a page of up to limit repositories of org that actor may
+    /// read, ordered by ctime and starting after cursor
+    ///
+    /// an actor that is not an Active user of org, or whose org is not
+    /// Active, sees no repositories; the returned cursor is Some iff there
+    /// may be more repositories to list
+    #[allow(dead_code)]
+    pub async fn list(
+        pool: &sqlx::SqlitePool,
+        actor: &Uuid,
+        org: &Uuid,
+        cursor: Option<&models::Cursor>,
+        limit: i64,
+    ) -> Result<(Vec<Self>, Option<models::Cursor>), anyhow::Error> {
+        let limit = models::limit_ok(limit);
+        let (after_ctime, after_id) = models::Cursor::bind_values(cursor);
+        let rows = sqlx::query(LIST_VISIBLE_QUERY)
+            .bind(actor.to_string())
+            .bind(models::Status::Active.to_int())
+            .bind(models::Status::Active.to_int())
+            .bind(org.to_string())
+            .bind(role::Role::Owner.to_int())
+            .bind(role::Role::Admin.to_int())
+            .bind(after_ctime)
+            .bind(after_ctime)
+            .bind(after_id)
+            .bind(limit + 1)
+            .fetch_all(pool)
+            .await?;
+
+        let mut repositories = Vec::with_capacity(rows.len());
+        for row in rows.iter().take(limit as usize) {
+            repositories.push(Self::from_row(row)?);
+        }
+
+        let next = match rows.len() as i64 > limit {
+            true => repositories.last().map(|v| models::Cursor {
+                ctime: v.meta.ctime.timestamp(),
+                id: v.id,
+            }),
+            false => None,
+        };
+        Ok((repositories, next))
+    }
+
+    /// from_row constructs a Repository from a row selecting all repositories columns
+    fn from_row(row: &SqliteRow) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            id: Uuid::try_parse(&row.try_get::<String, _>("id")?)?,
+            name: safe::VarChar::trusted(&row.try_get::<String, _>("name")?),
+            org: Uuid::try_parse(&row.try_get::<String, _>("org")?)?,
+            path: safe::VarChar::trusted(&row.try_get::<String, _>("path")?),
+            upstream: safe::VarChar::trusted(&row.try_get::<String, _>("upstream")?),
+            meta: models::Meta::from_db(
+                row.try_get::<i64, _>("ctime")?,
+                row.try_get::<i64, _>("mtime")?,
+                row.try_get::<i8, _>("schema_version")?,
+                row.try_get::<i64, _>("status")?,
+            )?,
+        })
+    }
+
+    /// update_status updates the repository status
+    ///
+    /// actor must have admin access to the repository
+    #[allow(dead_code)]
+    pub async fn update_status(
+        &mut self,
+        pool: &sqlx::SqlitePool,
+        actor: &Uuid,
+        new_status: models::Status,
+    ) -> Result<(), anyhow::Error> {
+        team::require_access(pool, actor, &self.id, team::Access::Admin).await?;
+
+        let update_result = match sqlx::query(UPDATE_STATUS_QUERY)
+            .bind(new_status.to_int())
+            .bind(self.id.to_string())
+            .execute(pool)
+            .await
+        {
+            Err(e) => return Err(e.into()),
+            Ok(v) => v,
+        };
+
+        if update_result.rows_affected() != 1 {
+            return Err(sqlx::Error::RowNotFound.into());
+        }
+
+        // the update to the db was a success, set the internal field
+        self.meta.status = new_status;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grokloc::app::admin::user::User;
+    use crate::grokloc::app::state;
+    use crate::grokloc::crypt;
+
+    #[tokio::test]
+    async fn repository_create_read_test() -> Result<(), anyhow::Error> {
+        let app = state::unit().await?;
+        let name = safe::VarChar::rand();
+        let upstream = safe::VarChar::rand();
+
+        let repository = Repository::create(
+            &app.master_pool,
+            &app.root_user.id,
+            &name,
+            &app.root_org.id,
+            &upstream,
+            &app.repo_base,
+        )
+        .await?;
+
+        let repository_read = Repository::read(&app.master_pool, &repository.id).await?;
+        assert_eq!(repository.id, repository_read.id);
+        assert_eq!(name, repository_read.name);
+        assert_eq!(app.root_org.id, repository_read.org);
+        assert_eq!(repository.path, repository_read.path);
+        assert_eq!(upstream, repository_read.upstream);
+        assert_eq!(models::Status::Active, repository_read.meta.status);
+        assert!(repository.meta.ctime < repository_read.meta.ctime);
+
+        // same name in the same org
+        match Repository::create(
+            &app.master_pool,
+            &app.root_user.id,
+            &name,
+            &app.root_org.id,
+            &upstream,
+            &app.repo_base,
+        )
+        .await
+        {
+            Ok(_) => unreachable!(),
+            Err(e) => assert!(matches!(
+                e.downcast_ref::<db::Err>(),
+                Some(db::Err::RepositoryViolation)
+            )),
+        };
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn repository_read_miss_test() -> Result<(), anyhow::Error> {
+        let app = state::unit().await?;
+        match Repository::read(&app.master_pool, &Uuid::new_v4()).await {
+            Ok(_) => unreachable!(),
+            Err(e) => assert!(db::anyhow_sqlx_row_not_found(&e)),
+        };
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn repository_update_status_test() -> Result<(), anyhow::Error> {
+        let app = state::unit().await?;
+        let mut repository = Repository::create(
+            &app.master_pool,
+            &app.root_user.id,
+            &safe::VarChar::rand(),
+            &app.root_org.id,
+            &safe::VarChar::rand(),
+            &app.repo_base,
+        )
+        .await?;
+
+        repository
+            .update_status(
+                &app.master_pool,
+                &app.root_user.id,
+                models::Status::Inactive,
+            )
+            .await?;
+        let repository_read = Repository::read(&app.master_pool, &repository.id).await?;
+        assert_eq!(models::Status::Inactive, repository_read.meta.status);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn repository_forbidden_test() -> Result<(), anyhow::Error> {
+        let app = state::unit().await?;
+        let password = safe::VarChar::new(&crypt::kdf(&crypt::rand_hex(), crypt::MIN_KDF_ROUNDS))?;
+        let mut member = User::create(
+            &app.master_pool,
+            &safe::VarChar::rand(),
+            &safe::VarChar::rand(),
+            &app.root_org.id,
+            &password,
+            &app.key,
+        )
+        .await?;
+        member
+            .update_status(&app.master_pool, &app.root_user.id, models::Status::Active)
+            .await?;
+
+        match Repository::create(
+            &app.master_pool,
+            &member.id,
+            &safe::VarChar::rand(),
+            &app.root_org.id,
+            &safe::VarChar::rand(),
+            &app.repo_base,
+        )
+        .await
+        {
+            Ok(_) => unreachable!(),
+            Err(e) => assert_eq!(Some(&role::Err::Forbidden), e.downcast_ref::<role::Err>()),
+        };
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn repository_list_visible_test() -> Result<(), anyhow::Error> {
+        let app = state::unit().await?;
+        let mut ids = Vec::new();
+        for _ in 0..3 {
+            let repository = Repository::create(
+                &app.master_pool,
+                &app.root_user.id,
+                &safe::VarChar::rand(),
+                &app.root_org.id,
+                &safe::VarChar::rand(),
+                &app.repo_base,
+            )
+            .await?;
+            ids.push(repository.id);
+        }
+
+        // the owner sees every repository, a page at a time
+        let (page, next) = Repository::list(
+            &app.master_pool,
+            &app.root_user.id,
+            &app.root_org.id,
+            None,
+            2,
+        )
+        .await?;
+        assert_eq!(2, page.len());
+        let (rest, next) = Repository::list(
+            &app.master_pool,
+            &app.root_user.id,
+            &app.root_org.id,
+            next.as_ref(),
+            2,
+        )
+        .await?;
+        assert_eq!(1, rest.len());
+        assert!(next.is_none());
+
+        // a member sees only repositories granted to its teams
+        let password = safe::VarChar::new(&crypt::kdf(&crypt::rand_hex(), crypt::MIN_KDF_ROUNDS))?;
+        let mut member = User::create(
+            &app.master_pool,
+            &safe::VarChar::rand(),
+            &safe::VarChar::rand(),
+            &app.root_org.id,
+            &password,
+            &app.key,
+        )
+        .await?;
+        member
+            .update_status(&app.master_pool, &app.root_user.id, models::Status::Active)
+            .await?;
+        let (page, _) =
+            Repository::list(&app.master_pool, &member.id, &app.root_org.id, None, 10).await?;
+        assert!(page.is_empty());
+        match Repository::read_as(&app.master_pool, &member.id, &ids[1]).await {
+            Ok(_) => unreachable!(),
+            Err(e) => assert_eq!(Some(&role::Err::Forbidden), e.downcast_ref::<role::Err>()),
+        };
+
+        let team = team::Team::create(
+            &app.master_pool,
+            &app.root_user.id,
+            &safe::VarChar::rand(),
+            &app.root_org.id,
+        )
+        .await?;
+        team.add_member(&app.master_pool, &app.root_user.id, &member.id)
+            .await?;
+        team.grant(
+            &app.master_pool,
+            &app.root_user.id,
+            &ids[1],
+            team::Access::Read,
+        )
+        .await?;
+        let (page, _) =
+            Repository::list(&app.master_pool, &member.id, &app.root_org.id, None, 10).await?;
+        assert_eq!(vec![ids[1]], page.iter().map(|v| v.id).collect::<Vec<_>>());
+        let mut repository = Repository::read_as(&app.master_pool, &member.id, &ids[1]).await?;
+
+        // read access does not permit changes
+        match repository
+            .update_status(&app.master_pool, &member.id, models::Status::Inactive)
+            .await
+        {
+            Ok(_) => unreachable!(),
+            Err(e) => assert_eq!(Some(&role::Err::Forbidden), e.downcast_ref::<role::Err>()),
+        };
+
+        // nothing is listed under an Inactive org, as nothing is readable
+        let mut org = app.root_org.clone();
+        org.update_status(
+            &app.master_pool,
+            &app.root_user.id,
+            models::Status::Inactive,
+        )
+        .await?;
+        let (page, next) = Repository::list(
+            &app.master_pool,
+            &app.root_user.id,
+            &app.root_org.id,
+            None,
+            10,
+        )
+        .await?;
+        assert!(page.is_empty());
+        assert!(next.is_none());
+        match Repository::read_as(&app.master_pool, &app.root_user.id, &ids[0]).await {
+            Ok(_) => unreachable!(),
+            Err(e) => assert_eq!(Some(&role::Err::Forbidden), e.downcast_ref::<role::Err>()),
+        };
+
+        Ok(())
+    }
+}
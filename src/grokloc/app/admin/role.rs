@@ -0,0 +1,327 @@
+//! role describes user authority within an org and related permission checks
+use crate::grokloc::app::models;
+use crate::grokloc::db;
+use anyhow;
+use sqlx;
+use sqlx::Row;
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+use uuid::Uuid;
+
+/// Err covers role and permission errors
+#[derive(Debug, Error, PartialEq)]
+pub enum Err {
+    #[error("unknown role")]
+    UnknownRole,
+    #[error("permission denied")]
+    Forbidden,
+    #[error("org must have exactly one owner")]
+    OwnerInvariant,
+}
+
+/// Role describes the authority of a user within its org
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum Role {
+    Owner,
+    Admin,
+    #[default]
+    Member,
+    ReadOnly,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl FromStr for Role {
+    type Err = Err;
+
+    /// from_str parses the Display form of a Role, ignoring case
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [Role::Owner, Role::Admin, Role::Member, Role::ReadOnly]
+            .into_iter()
+            .find(|v| v.to_string().eq_ignore_ascii_case(s.trim()))
+            .ok_or(Err::UnknownRole)
+    }
+}
+
+impl Role {
+    /// translate a Role to its database representation
+    pub fn to_int(self) -> i64 {
+        match self {
+            Role::Owner => 1,
+            Role::Admin => 2,
+            Role::Member => 3,
+            Role::ReadOnly => 4,
+        }
+    }
+
+    /// translate a Role from its database representation
+    pub fn from_int(i: i64) -> Result<Self, Err> {
+        match i {
+            1 => Ok(Role::Owner),
+            2 => Ok(Role::Admin),
+            3 => Ok(Role::Member),
+            4 => Ok(Role::ReadOnly),
+            _ => Err(Err::UnknownRole),
+        }
+    }
+
+    /// allows returns true if the role grants permission
+    pub fn allows(self, permission: Permission) -> bool {
+        match permission {
+            Permission::UpdateOrg => self == Role::Owner,
+            Permission::ManageUsers | Permission::ManageRepositories => {
+                matches!(self, Role::Owner | Role::Admin)
+            }
+        }
+    }
+}
+
+/// Permission describes a class of mutating operations
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Permission {
+    /// org status and other org-wide changes
+    UpdateOrg,
+    /// status, role and profile changes of other users
+    ManageUsers,
+    /// creating and changing repositories
+    ManageRepositories,
+}
+
+pub const SELECT_ACTOR_QUERY: &str = r#"
+select
+ u.org,
+ u.role,
+ u.status,
+ o.status as org_status
+from users u
+left join orgs o on o.id = u.org
+where u.id = ?
+"#;
+
+/// check succeeds iff actor is an Active user of an Active org with a role
+/// that allows permission, returning the actor role
+///
+/// an unknown actor is reported as Err::Forbidden
+pub async fn check<'c, E>(
+    conn: E,
+    actor: &Uuid,
+    org: &Uuid,
+    permission: Permission,
+) -> Result<Role, anyhow::Error>
+where
+    E: sqlx::SqliteExecutor<'c>,
+{
+    check_actor(conn, actor, org, Some(permission), true).await
+}
+
+/// check_self is check for an actor acting on its own user row; no role
+/// permission is needed, but the actor must still be an Active user of an
+/// Active org, so that deactivation cannot be sidestepped by self-service
+pub async fn check_self<'c, E>(conn: E, actor: &Uuid, org: &Uuid) -> Result<Role, anyhow::Error>
+where
+    E: sqlx::SqliteExecutor<'c>,
+{
+    check_actor(conn, actor, org, None, true).await
+}
+
+/// check_update_org is check of Permission::UpdateOrg without requiring org
+/// to be Active, so that the owner of an Inactive org can reactivate or
+/// export it; nothing else under an Inactive org may act
+pub async fn check_update_org<'c, E>(
+    conn: E,
+    actor: &Uuid,
+    org: &Uuid,
+) -> Result<Role, anyhow::Error>
+where
+    E: sqlx::SqliteExecutor<'c>,
+{
+    check_actor(conn, actor, org, Some(Permission::UpdateOrg), false).await
+}
+
+/// check_actor reads the actor and its org to decide a permission; a None
+/// permission checks only the actor and org status
+async fn check_actor<'c, E>(
+    conn: E,
+    actor: &Uuid,
+    org: &Uuid,
+    permission: Option<Permission>,
+    active_org: bool,
+) -> Result<Role, anyhow::Error>
+where
+    E: sqlx::SqliteExecutor<'c>,
+{
+    let row = match sqlx::query(SELECT_ACTOR_QUERY)
+        .bind(actor.to_string())
+        .fetch_one(conn)
+        .await
+    {
+        Err(e) if db::sqlx_row_not_found(&e) => return Err(Err::Forbidden.into()),
+        Err(e) => return Err(e.into()),
+        Ok(v) => v,
+    };
+
+    let actor_org = Uuid::try_parse(&row.try_get::<String, _>("org")?)?;
+    let actor_role = Role::from_int(row.try_get::<i64, _>("role")?)?;
+    let actor_status = models::Status::from_int(row.try_get::<i64, _>("status")?)?;
+    // a missing org is never Active
+    let org_active = match row.try_get::<Option<i64>, _>("org_status")? {
+        Some(v) => models::Status::from_int(v)? == models::Status::Active,
+        None => false,
+    };
+
+    if actor_org != *org
+        || actor_status != models::Status::Active
+        || (active_org && !org_active)
+        || !permission.is_none_or(|v| actor_role.allows(v))
+    {
+        return Err(Err::Forbidden.into());
+    }
+
+    Ok(actor_role)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grokloc::app::admin::user::User;
+    use crate::grokloc::app::state;
+    use crate::grokloc::crypt;
+    use crate::grokloc::safe;
+
+    #[test]
+    fn role_int_test() -> Result<(), Err> {
+        for role in [Role::Owner, Role::Admin, Role::Member, Role::ReadOnly] {
+            assert_eq!(role, Role::from_int(role.to_int())?);
+        }
+        assert_eq!(Err::UnknownRole, Role::from_int(0).unwrap_err());
+        Ok(())
+    }
+
+    #[test]
+    fn role_from_str_test() -> Result<(), Err> {
+        for role in [Role::Owner, Role::Admin, Role::Member, Role::ReadOnly] {
+            assert_eq!(role, role.to_string().parse()?);
+        }
+        assert_eq!(Role::ReadOnly, " readonly ".parse()?);
+        assert_eq!(Err::UnknownRole, "superuser".parse::<Role>().unwrap_err());
+        Ok(())
+    }
+
+    #[test]
+    fn role_allows_test() {
+        assert!(Role::Owner.allows(Permission::UpdateOrg));
+        assert!(!Role::Admin.allows(Permission::UpdateOrg));
+        assert!(Role::Admin.allows(Permission::ManageUsers));
+        assert!(Role::Admin.allows(Permission::ManageRepositories));
+        assert!(!Role::Member.allows(Permission::ManageUsers));
+        assert!(!Role::ReadOnly.allows(Permission::ManageRepositories));
+    }
+
+    #[tokio::test]
+    async fn role_check_test() -> Result<(), anyhow::Error> {
+        let app = state::unit().await?;
+
+        assert_eq!(
+            Role::Owner,
+            check(
+                &app.master_pool,
+                &app.root_user.id,
+                &app.root_org.id,
+                Permission::UpdateOrg
+            )
+            .await?
+        );
+
+        // wrong org
+        match check(
+            &app.master_pool,
+            &app.root_user.id,
+            &Uuid::new_v4(),
+            Permission::UpdateOrg,
+        )
+        .await
+        {
+            Ok(_) => unreachable!(),
+            Err(e) => assert_eq!(Some(&Err::Forbidden), e.downcast_ref::<Err>()),
+        };
+
+        // unknown actor
+        match check(
+            &app.master_pool,
+            &Uuid::new_v4(),
+            &app.root_org.id,
+            Permission::UpdateOrg,
+        )
+        .await
+        {
+            Ok(_) => unreachable!(),
+            Err(e) => assert_eq!(Some(&Err::Forbidden), e.downcast_ref::<Err>()),
+        };
+
+        // unconfirmed member
+        let password = safe::VarChar::new(&crypt::kdf(&crypt::rand_hex(), crypt::MIN_KDF_ROUNDS))?;
+        let member = User::create(
+            &app.master_pool,
+            &safe::VarChar::rand(),
+            &safe::VarChar::rand(),
+            &app.root_org.id,
+            &password,
+            &app.key,
+        )
+        .await?;
+        match check(
+            &app.master_pool,
+            &member.id,
+            &app.root_org.id,
+            Permission::ManageUsers,
+        )
+        .await
+        {
+            Ok(_) => unreachable!(),
+            Err(e) => assert_eq!(Some(&Err::Forbidden), e.downcast_ref::<Err>()),
+        };
+
+        // the owner of an Inactive org may only reactivate it
+        let mut org = app.root_org.clone();
+        org.update_status(
+            &app.master_pool,
+            &app.root_user.id,
+            models::Status::Inactive,
+        )
+        .await?;
+        match check(
+            &app.master_pool,
+            &app.root_user.id,
+            &org.id,
+            Permission::UpdateOrg,
+        )
+        .await
+        {
+            Ok(_) => unreachable!(),
+            Err(e) => assert_eq!(Some(&Err::Forbidden), e.downcast_ref::<Err>()),
+        };
+        match org
+            .update_name(&app.master_pool, &app.root_user.id, &safe::VarChar::rand())
+            .await
+        {
+            Ok(_) => unreachable!(),
+            Err(e) => assert_eq!(Some(&Err::Forbidden), e.downcast_ref::<Err>()),
+        };
+        org.update_status(&app.master_pool, &app.root_user.id, models::Status::Active)
+            .await?;
+        check(
+            &app.master_pool,
+            &app.root_user.id,
+            &org.id,
+            Permission::UpdateOrg,
+        )
+        .await?;
+
+        Ok(())
+    }
+}
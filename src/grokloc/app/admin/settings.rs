@@ -0,0 +1,333 @@
+//! settings provides a typed key/value store of per-org configuration
+use crate::grokloc::app::admin::role;
+use crate::grokloc::app::audit;
+use crate::grokloc::app::schema;
+use crate::grokloc::exclude;
+use crate::grokloc::safe;
+use anyhow;
+use sqlx;
+use std::fmt;
+use thiserror::Error;
+use uuid::Uuid;
+
+pub const SCHEMA_VERSION: i8 = 0;
+
+pub const MFA_POLICY_OFF: &str = "off";
+pub const MFA_POLICY_OPTIONAL: &str = "optional";
+pub const MFA_POLICY_REQUIRED: &str = "required";
+
+/// MIN_REPOSITORY_SYNC_INTERVAL is the shortest permitted sync interval in seconds
+pub const MIN_REPOSITORY_SYNC_INTERVAL: i64 = 60;
+
+pub const UPSERT_QUERY: &str = r#"
+insert into org_settings
+(id,
+ org,
+ name,
+ kind,
+ value,
+ schema_version)
+values
+(?,?,?,?,?,?)
+on conflict (org, name) do update set
+ kind = excluded.kind,
+ value = excluded.value
+"#;
+
+pub const SELECT_QUERY: &str = r#"
+select kind, value from org_settings where org = ? and name = ?
+"#;
+
+/// Err covers settings errors
+#[derive(Debug, Error, PartialEq)]
+pub enum Err {
+    #[error("unknown setting")]
+    UnknownKey,
+    #[error("unknown setting kind")]
+    UnknownKind,
+    #[error("bad setting value")]
+    BadValue,
+}
+
+/// Key names a setting
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Key {
+    /// one of MFA_POLICY_*
+    MfaPolicy,
+    /// seconds between repository syncs
+    RepositorySyncInterval,
+    /// days to retain analysis data, 0 retains indefinitely
+    DataRetention,
+    /// paths excluded from analysis, one gitignore pattern per line
+    IgnorePatterns,
+}
+
+/// KEYS lists every setting
+pub const KEYS: [Key; 4] = [
+    Key::MfaPolicy,
+    Key::RepositorySyncInterval,
+    Key::DataRetention,
+    Key::IgnorePatterns,
+];
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl Key {
+    /// name is the database representation of a Key
+    pub fn name(self) -> &'static str {
+        match self {
+            Key::MfaPolicy => "mfa_policy",
+            Key::RepositorySyncInterval => "repository_sync_interval",
+            Key::DataRetention => "data_retention",
+            Key::IgnorePatterns => "ignore_patterns",
+        }
+    }
+
+    /// from_name translates a Key from its database representation
+    #[allow(dead_code)]
+    pub fn from_name(name: &str) -> Result<Self, Err> {
+        KEYS.iter()
+            .find(|v| v.name() == name)
+            .copied()
+            .ok_or(Err::UnknownKey)
+    }
+
+    /// default_value is the value of a setting that has never been set
+    pub fn default_value(self) -> Value {
+        match self {
+            Key::MfaPolicy => Value::Text(safe::VarChar::trusted(MFA_POLICY_OFF)),
+            Key::RepositorySyncInterval => Value::Int(3600),
+            Key::DataRetention => Value::Int(0),
+            Key::IgnorePatterns => Value::Text(safe::VarChar::trusted("")),
+        }
+    }
+
+    /// validate checks that value is of the right kind and range for the setting
+    pub fn validate(self, value: &Value) -> Result<(), Err> {
+        let ok = match (self, value) {
+            (Key::MfaPolicy, Value::Text(v)) => {
+                [MFA_POLICY_OFF, MFA_POLICY_OPTIONAL, MFA_POLICY_REQUIRED]
+                    .contains(&v.to_string().as_str())
+            }
+            (Key::RepositorySyncInterval, Value::Int(v)) => *v >= MIN_REPOSITORY_SYNC_INTERVAL,
+            (Key::DataRetention, Value::Int(v)) => *v >= 0,
+            (Key::IgnorePatterns, Value::Text(v)) => exclude::patterns_ok(&v.to_string()),
+            _ => false,
+        };
+        match ok {
+            true => Ok(()),
+            false => Err(Err::BadValue),
+        }
+    }
+}
+
+/// Value is a typed setting value
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    Int(i64),
+    Text(safe::VarChar),
+}
+
+impl Value {
+    /// to_db translates a Value to its database (kind, value) representation
+    pub fn to_db(&self) -> (i64, String) {
+        match self {
+            Value::Bool(v) => (1, v.to_string()),
+            Value::Int(v) => (2, v.to_string()),
+            Value::Text(v) => (3, v.to_string()),
+        }
+    }
+
+    /// from_db translates a Value from its database (kind, value) representation
+    pub fn from_db(kind: i64, value: &str) -> Result<Self, Err> {
+        match kind {
+            1 => Ok(Value::Bool(value.parse().map_err(|_| Err::BadValue)?)),
+            2 => Ok(Value::Int(value.parse().map_err(|_| Err::BadValue)?)),
+            3 => Ok(Value::Text(safe::VarChar::trusted(value))),
+            _ => Err(Err::UnknownKind),
+        }
+    }
+}
+
+/// get returns the org setting for key, or its default if never set
+#[allow(dead_code)]
+pub async fn get<'c, E>(conn: E, org: &Uuid, key: Key) -> Result<Value, anyhow::Error>
+where
+    E: sqlx::SqliteExecutor<'c>,
+{
+    let row: Option<(i64, String)> = sqlx::query_as(SELECT_QUERY)
+        .bind(org.to_string())
+        .bind(key.name())
+        .fetch_optional(conn)
+        .await?;
+    match row {
+        Some((kind, value)) => Ok(Value::from_db(kind, &value)?),
+        None => Ok(key.default_value()),
+    }
+}
+
+/// all returns every org setting, with defaults for those never set
+#[allow(dead_code)]
+pub async fn all(pool: &sqlx::SqlitePool, org: &Uuid) -> Result<Vec<(Key, Value)>, anyhow::Error> {
+    let mut settings = Vec::with_capacity(KEYS.len());
+    for key in KEYS {
+        settings.push((key, get(pool, org, key).await?));
+    }
+    Ok(settings)
+}
+
+/// set validates and stores the org setting for key
+///
+/// actor must be permitted to update the org
+#[allow(dead_code)]
+pub async fn set(
+    pool: &sqlx::SqlitePool,
+    actor: &Uuid,
+    org: &Uuid,
+    key: Key,
+    value: &Value,
+) -> Result<(), anyhow::Error> {
+    key.validate(value)?;
+    let (kind, value) = value.to_db();
+
+    let mut txn = pool.begin().await?;
+
+    role::check(&mut txn, actor, org, role::Permission::UpdateOrg).await?;
+
+    sqlx::query(UPSERT_QUERY)
+        .bind(Uuid::new_v4().to_string())
+        .bind(org.to_string())
+        .bind(key.name())
+        .bind(kind)
+        .bind(value)
+        .bind(SCHEMA_VERSION)
+        .execute(&mut txn)
+        .await?;
+
+    audit::insert(&mut txn, audit::ORG_SETTING, schema::ORGS_TABLENAME, org).await?;
+
+    txn.commit().await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grokloc::app::state;
+
+    #[test]
+    fn settings_key_test() -> Result<(), Err> {
+        for key in KEYS {
+            assert_eq!(key, Key::from_name(key.name())?);
+            assert_eq!(Ok(()), key.validate(&key.default_value()));
+        }
+        assert_eq!(Err::UnknownKey, Key::from_name("missing").unwrap_err());
+        assert_eq!(
+            Err::BadValue,
+            Key::MfaPolicy
+                .validate(&Value::Text(safe::VarChar::trusted("sometimes")))
+                .unwrap_err()
+        );
+        assert_eq!(
+            Err::BadValue,
+            Key::RepositorySyncInterval
+                .validate(&Value::Int(1))
+                .unwrap_err()
+        );
+        assert_eq!(
+            Err::BadValue,
+            Key::DataRetention.validate(&Value::Bool(true)).unwrap_err()
+        );
+        assert_eq!(
+            Err::BadValue,
+            Key::IgnorePatterns
+                .validate(&Value::Text(safe::VarChar::trusted("a{b")))
+                .unwrap_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn settings_value_test() -> Result<(), Err> {
+        for value in [
+            Value::Bool(true),
+            Value::Int(-7),
+            Value::Text(safe::VarChar::rand()),
+        ] {
+            let (kind, v) = value.to_db();
+            assert_eq!(value, Value::from_db(kind, &v)?);
+        }
+        assert_eq!(Err::BadValue, Value::from_db(2, "x").unwrap_err());
+        assert_eq!(Err::UnknownKind, Value::from_db(0, "x").unwrap_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn settings_get_set_test() -> Result<(), anyhow::Error> {
+        let app = state::unit().await?;
+        let org = app.root_org.id;
+
+        // defaults
+        let settings = all(&app.master_pool, &org).await?;
+        assert_eq!(KEYS.len(), settings.len());
+        for (key, value) in settings {
+            assert_eq!(key.default_value(), value);
+        }
+
+        // set, then overwrite
+        for v in [120, 600] {
+            set(
+                &app.master_pool,
+                &app.root_user.id,
+                &org,
+                Key::RepositorySyncInterval,
+                &Value::Int(v),
+            )
+            .await?;
+            assert_eq!(
+                Value::Int(v),
+                get(&app.master_pool, &org, Key::RepositorySyncInterval).await?
+            );
+        }
+
+        // invalid values are not stored
+        match set(
+            &app.master_pool,
+            &app.root_user.id,
+            &org,
+            Key::MfaPolicy,
+            &Value::Int(1),
+        )
+        .await
+        {
+            Ok(_) => unreachable!(),
+            Err(e) => assert_eq!(Some(&Err::BadValue), e.downcast_ref::<Err>()),
+        };
+        assert_eq!(
+            Key::MfaPolicy.default_value(),
+            get(&app.master_pool, &org, Key::MfaPolicy).await?
+        );
+
+        // only permitted actors may set
+        match set(
+            &app.master_pool,
+            &Uuid::new_v4(),
+            &org,
+            Key::DataRetention,
+            &Value::Int(30),
+        )
+        .await
+        {
+            Ok(_) => unreachable!(),
+            Err(e) => assert_eq!(Some(&role::Err::Forbidden), e.downcast_ref::<role::Err>()),
+        };
+
+        Ok(())
+    }
+}
@@ -0,0 +1,339 @@
+//! subproject models a subprojects row, a named part of a repository
+//! selected by path globs, so that monorepo analyses can be broken down
+use crate::grokloc::app::admin::team;
+use crate::grokloc::app::audit;
+use crate::grokloc::app::schema;
+use crate::grokloc::db;
+use crate::grokloc::safe;
+use anyhow;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use sqlx;
+use sqlx::Row;
+use thiserror::Error;
+use uuid::Uuid;
+
+pub const SCHEMA_VERSION: i8 = 0;
+
+pub const INSERT_QUERY: &str = r#"
+insert into subprojects
+(id,
+ repository,
+ name,
+ patterns,
+ schema_version)
+values
+(?,?,?,?,?)
+"#;
+
+pub const LIST_QUERY: &str = r#"
+select
+ id,
+ repository,
+ name,
+ patterns,
+ schema_version
+from subprojects
+where repository = ?
+order by name
+"#;
+
+pub const UPDATE_PATTERNS_QUERY: &str = r#"
+update subprojects set patterns = ? where id = ?
+"#;
+
+pub const DELETE_QUERY: &str = r#"
+delete from subprojects where id = ?
+"#;
+
+/// Err covers subproject errors
+#[derive(Debug, Error, PartialEq)]
+pub enum Err {
+    #[error("subproject has no patterns")]
+    NoPatterns,
+    #[error("bad subproject pattern")]
+    Pattern,
+}
+
+/// globs compiles patterns into a set matching paths from the repository
+/// root; * and ? do not match /, ** matches any number of directories
+pub fn globs(patterns: &[String]) -> Result<GlobSet, Err> {
+    if patterns.is_empty() {
+        return Err(Err::NoPatterns);
+    }
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        if pattern.trim().is_empty() || pattern.contains('\n') {
+            return Err(Err::Pattern);
+        }
+        let glob = GlobBuilder::new(pattern)
+            .literal_separator(true)
+            .build()
+            .map_err(|_| Err::Pattern)?;
+        builder.add(glob);
+    }
+    builder.build().map_err(|_| Err::Pattern)
+}
+
+/// Subproject is the data representation of a subprojects row
+#[derive(Clone, Debug, PartialEq)]
+pub struct Subproject {
+    pub id: Uuid,
+    pub repository: Uuid,
+    pub name: safe::VarChar,
+    pub patterns: Vec<String>,
+    pub schema_version: i8,
+}
+
+impl Subproject {
+    /// create forms a new Subproject of repository, validating patterns
+    ///
+    /// actor must have admin access to the repository; a duplicate name
+    /// within the repository is reported as db::Err::SubprojectViolation
+    #[allow(dead_code)]
+    pub async fn create(
+        pool: &sqlx::SqlitePool,
+        actor: &Uuid,
+        repository: &Uuid,
+        name: &safe::VarChar,
+        patterns: &[String],
+    ) -> Result<Self, anyhow::Error> {
+        globs(patterns)?;
+        let subproject = Self {
+            id: Uuid::new_v4(),
+            repository: *repository,
+            name: name.clone(),
+            patterns: patterns.to_vec(),
+            schema_version: SCHEMA_VERSION,
+        };
+
+        let mut txn = pool.begin().await?;
+
+        team::require_access(&mut txn, actor, repository, team::Access::Admin).await?;
+
+        if let Err(e) = sqlx::query(INSERT_QUERY)
+            .bind(subproject.id.to_string())
+            .bind(subproject.repository.to_string())
+            .bind(subproject.name.to_string())
+            .bind(subproject.patterns.join("\n"))
+            .bind(subproject.schema_version)
+            .execute(&mut txn)
+            .await
+        {
+            if db::sqlx_duplicate(&e) {
+                return Err(db::Err::SubprojectViolation.into());
+            }
+            return Err(e.into());
+        }
+
+        audit::insert(
+            &mut txn,
+            audit::SUBPROJECT_INSERT,
+            schema::SUBPROJECTS_TABLENAME,
+            &subproject.id,
+        )
+        .await?;
+
+        txn.commit().await?;
+
+        Ok(subproject)
+    }
+
+    /// update_patterns validates and replaces the subproject patterns
+    ///
+    /// actor must have admin access to the repository
+    #[allow(dead_code)]
+    pub async fn update_patterns(
+        &mut self,
+        pool: &sqlx::SqlitePool,
+        actor: &Uuid,
+        patterns: &[String],
+    ) -> Result<(), anyhow::Error> {
+        globs(patterns)?;
+
+        let mut txn = pool.begin().await?;
+
+        team::require_access(&mut txn, actor, &self.repository, team::Access::Admin).await?;
+
+        let update_result = sqlx::query(UPDATE_PATTERNS_QUERY)
+            .bind(patterns.join("\n"))
+            .bind(self.id.to_string())
+            .execute(&mut txn)
+            .await?;
+        if update_result.rows_affected() != 1 {
+            return Err(sqlx::Error::RowNotFound.into());
+        }
+
+        audit::insert(
+            &mut txn,
+            audit::SUBPROJECT_UPDATE,
+            schema::SUBPROJECTS_TABLENAME,
+            &self.id,
+        )
+        .await?;
+
+        txn.commit().await?;
+
+        // the update to the db was a success, set the internal field
+        self.patterns = patterns.to_vec();
+
+        Ok(())
+    }
+
+    /// delete removes the subproject; analyses already made keep its totals
+    ///
+    /// actor must have admin access to the repository
+    #[allow(dead_code)]
+    pub async fn delete(self, pool: &sqlx::SqlitePool, actor: &Uuid) -> Result<(), anyhow::Error> {
+        let mut txn = pool.begin().await?;
+
+        team::require_access(&mut txn, actor, &self.repository, team::Access::Admin).await?;
+
+        let delete_result = sqlx::query(DELETE_QUERY)
+            .bind(self.id.to_string())
+            .execute(&mut txn)
+            .await?;
+        if delete_result.rows_affected() != 1 {
+            return Err(sqlx::Error::RowNotFound.into());
+        }
+
+        audit::insert(
+            &mut txn,
+            audit::SUBPROJECT_DELETE,
+            schema::SUBPROJECTS_TABLENAME,
+            &self.id,
+        )
+        .await?;
+
+        txn.commit().await?;
+
+        Ok(())
+    }
+}
+
+/// list selects the subprojects of repository, ordered by name
+pub async fn list(
+    pool: &sqlx::SqlitePool,
+    repository: &Uuid,
+) -> Result<Vec<Subproject>, anyhow::Error> {
+    let rows = sqlx::query(LIST_QUERY)
+        .bind(repository.to_string())
+        .fetch_all(pool)
+        .await?;
+    let mut subprojects = Vec::with_capacity(rows.len());
+    for row in rows {
+        subprojects.push(Subproject {
+            id: Uuid::try_parse(&row.try_get::<String, _>("id")?)?,
+            repository: Uuid::try_parse(&row.try_get::<String, _>("repository")?)?,
+            name: safe::VarChar::trusted(&row.try_get::<String, _>("name")?),
+            patterns: row
+                .try_get::<String, _>("patterns")?
+                .lines()
+                .map(|v| v.to_string())
+                .collect(),
+            schema_version: row.try_get::<i8, _>("schema_version")?,
+        });
+    }
+    Ok(subprojects)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grokloc::app::admin::repository::Repository;
+    use crate::grokloc::app::admin::role;
+    use crate::grokloc::app::state;
+
+    #[test]
+    fn subproject_globs_test() -> Result<(), Err> {
+        let set = globs(&["services/api/**".to_string(), "libs/*.rs".to_string()])?;
+        assert!(set.is_match("services/api/src/main.rs"));
+        assert!(set.is_match("libs/a.rs"));
+        assert!(!set.is_match("libs/x/a.rs"));
+        assert!(!set.is_match("services/web/index.js"));
+        assert_eq!(Err::NoPatterns, globs(&[]).unwrap_err());
+        assert_eq!(Err::Pattern, globs(&["a{b".to_string()]).unwrap_err());
+        assert_eq!(Err::Pattern, globs(&[" ".to_string()]).unwrap_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn subproject_crud_test() -> Result<(), anyhow::Error> {
+        let app = state::unit().await?;
+        let repository = Repository::create(
+            &app.master_pool,
+            &app.root_user.id,
+            &safe::VarChar::rand(),
+            &app.root_org.id,
+            &safe::VarChar::rand(),
+            &app.repo_base,
+        )
+        .await?;
+        let name = safe::VarChar::trusted("api");
+        let patterns = vec!["services/api/**".to_string()];
+
+        let mut subproject = Subproject::create(
+            &app.master_pool,
+            &app.root_user.id,
+            &repository.id,
+            &name,
+            &patterns,
+        )
+        .await?;
+        assert_eq!(
+            vec![subproject.clone()],
+            list(&app.master_pool, &repository.id).await?
+        );
+
+        // duplicate names, bad patterns and unpermitted actors are refused
+        match Subproject::create(
+            &app.master_pool,
+            &app.root_user.id,
+            &repository.id,
+            &name,
+            &patterns,
+        )
+        .await
+        {
+            Ok(_) => unreachable!(),
+            Err(e) => assert!(matches!(
+                e.downcast_ref::<db::Err>(),
+                Some(db::Err::SubprojectViolation)
+            )),
+        };
+        match subproject
+            .update_patterns(&app.master_pool, &app.root_user.id, &[])
+            .await
+        {
+            Ok(_) => unreachable!(),
+            Err(e) => assert_eq!(Some(&Err::NoPatterns), e.downcast_ref::<Err>()),
+        };
+        match Subproject::create(
+            &app.master_pool,
+            &Uuid::new_v4(),
+            &repository.id,
+            &safe::VarChar::rand(),
+            &patterns,
+        )
+        .await
+        {
+            Ok(_) => unreachable!(),
+            Err(e) => assert_eq!(Some(&role::Err::Forbidden), e.downcast_ref::<role::Err>()),
+        };
+
+        // update, then delete
+        let patterns = vec!["services/api/**".to_string(), "proto/api/**".to_string()];
+        subproject
+            .update_patterns(&app.master_pool, &app.root_user.id, &patterns)
+            .await?;
+        assert_eq!(
+            patterns,
+            list(&app.master_pool, &repository.id).await?[0].patterns
+        );
+        subproject
+            .delete(&app.master_pool, &app.root_user.id)
+            .await?;
+        assert!(list(&app.master_pool, &repository.id).await?.is_empty());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,754 @@
+//! team models a teams row, a named group of users within an org that may be
+//! granted access to repositories, and resolves effective repository access
+use crate::grokloc::app::admin::role;
+use crate::grokloc::app::audit;
+use crate::grokloc::app::models;
+use crate::grokloc::app::schema;
+use crate::grokloc::db;
+use crate::grokloc::safe;
+use anyhow;
+use sqlx;
+use sqlx::Row;
+use std::fmt;
+use thiserror::Error;
+use uuid::Uuid;
+
+pub const SCHEMA_VERSION: i8 = 0;
+
+pub const INSERT_QUERY: &str = r#"
+insert into teams
+(id,
+ name,
+ org,
+ schema_version)
+values
+(?,?,?,?)
+"#;
+
+pub const SELECT_QUERY: &str = r#"
+select
+ id,
+ name,
+ org,
+ schema_version
+from teams
+where id = ?
+"#;
+
+pub const SELECT_ORG_STATUS_QUERY: &str = r#"
+select status from orgs where id = ?;
+"#;
+
+pub const SELECT_USER_QUERY: &str = r#"
+select org, status from users where id = ?
+"#;
+
+pub const SELECT_REPOSITORY_ORG_QUERY: &str = r#"
+select org from repositories where id = ?
+"#;
+
+pub const INSERT_MEMBER_QUERY: &str = r#"
+insert into team_members
+(id,
+ team,
+ user,
+ schema_version)
+values
+(?,?,?,?)
+"#;
+
+pub const DELETE_MEMBER_QUERY: &str = r#"
+delete from team_members where team = ? and user = ?
+"#;
+
+pub const SELECT_MEMBERS_QUERY: &str = r#"
+select user from team_members where team = ? order by ctime, user
+"#;
+
+pub const UPSERT_GRANT_QUERY: &str = r#"
+insert into repository_grants
+(id,
+ repository,
+ team,
+ access,
+ schema_version)
+values
+(?,?,?,?,?)
+on conflict (repository, team) do update set
+ access = excluded.access
+"#;
+
+pub const DELETE_GRANT_QUERY: &str = r#"
+delete from repository_grants where repository = ? and team = ?
+"#;
+
+pub const DELETE_MEMBERS_QUERY: &str = r#"
+delete from team_members where team = ?
+"#;
+
+pub const DELETE_GRANTS_QUERY: &str = r#"
+delete from repository_grants where team = ?
+"#;
+
+pub const DELETE_QUERY: &str = r#"
+delete from teams where id = ?
+"#;
+
+pub const SELECT_EFFECTIVE_QUERY: &str = r#"
+select
+ u.org = r.org as same_org,
+ u.role,
+ u.status,
+ o.status as org_status,
+ (select max(g.access)
+  from repository_grants g
+  join team_members m on m.team = g.team
+  where g.repository = r.id and m.user = u.id) as granted
+from users u
+join orgs o on o.id = u.org, repositories r
+where u.id = ? and r.id = ?
+"#;
+
+/// Err covers access errors
+#[derive(Debug, Error, PartialEq)]
+pub enum Err {
+    #[error("unknown access")]
+    UnknownAccess,
+}
+
+/// Access is the authority of a user over a repository; Admin implies Read
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Access {
+    Read,
+    Admin,
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Access {
+    /// translate an Access to its database representation
+    pub fn to_int(self) -> i64 {
+        match self {
+            Access::Read => 1,
+            Access::Admin => 2,
+        }
+    }
+
+    /// translate an Access from its database representation
+    pub fn from_int(i: i64) -> Result<Self, Err> {
+        match i {
+            1 => Ok(Access::Read),
+            2 => Ok(Access::Admin),
+            _ => Err(Err::UnknownAccess),
+        }
+    }
+}
+
+/// effective_access resolves the access of user to repository, if any
+///
+/// the user must be an Active member of the repository org, which must be
+/// Active; org owners and admins have Admin access to every org repository,
+/// other users have the greatest access granted to any of their teams
+pub async fn effective_access<'c, E>(
+    conn: E,
+    user: &Uuid,
+    repository: &Uuid,
+) -> Result<Option<Access>, anyhow::Error>
+where
+    E: sqlx::SqliteExecutor<'c>,
+{
+    let row = match sqlx::query(SELECT_EFFECTIVE_QUERY)
+        .bind(user.to_string())
+        .bind(repository.to_string())
+        .fetch_optional(conn)
+        .await?
+    {
+        None => return Ok(None),
+        Some(v) => v,
+    };
+
+    let same_org = row.try_get::<bool, _>("same_org")?;
+    let user_role = role::Role::from_int(row.try_get::<i64, _>("role")?)?;
+    let user_status = models::Status::from_int(row.try_get::<i64, _>("status")?)?;
+    let org_status = models::Status::from_int(row.try_get::<i64, _>("org_status")?)?;
+    if !same_org || user_status != models::Status::Active || org_status != models::Status::Active {
+        return Ok(None);
+    }
+    if user_role.allows(role::Permission::ManageRepositories) {
+        return Ok(Some(Access::Admin));
+    }
+    match row.try_get::<Option<i64>, _>("granted")? {
+        None => Ok(None),
+        Some(v) => Ok(Some(Access::from_int(v)?)),
+    }
+}
+
+/// require_access succeeds iff user has at least access to repository,
+/// returning the effective access
+///
+/// insufficient access is reported as role::Err::Forbidden
+pub async fn require_access<'c, E>(
+    conn: E,
+    user: &Uuid,
+    repository: &Uuid,
+    access: Access,
+) -> Result<Access, anyhow::Error>
+where
+    E: sqlx::SqliteExecutor<'c>,
+{
+    match effective_access(conn, user, repository).await? {
+        Some(v) if v >= access => Ok(v),
+        _ => Err(role::Err::Forbidden.into()),
+    }
+}
+
+/// Team is the data representation of a teams row
+#[derive(Clone, Debug)]
+pub struct Team {
+    pub id: Uuid,
+    pub name: safe::VarChar,
+    pub org: Uuid,
+    pub schema_version: i8,
+}
+
+impl Team {
+    /// create forms a new Team in an existing Active org
+    ///
+    /// actor must be permitted to manage users; a duplicate name within the
+    /// org is reported as db::Err::TeamViolation
+    #[allow(dead_code)]
+    pub async fn create(
+        pool: &sqlx::SqlitePool,
+        actor: &Uuid,
+        name: &safe::VarChar,
+        org: &Uuid,
+    ) -> Result<Self, anyhow::Error> {
+        let team = Self {
+            id: Uuid::new_v4(),
+            name: name.clone(),
+            org: *org,
+            schema_version: SCHEMA_VERSION,
+        };
+
+        let mut txn = pool.begin().await?;
+
+        role::check(&mut txn, actor, org, role::Permission::ManageUsers).await?;
+
+        let org_status: i64 = sqlx::query_scalar(SELECT_ORG_STATUS_QUERY)
+            .bind(org.to_string())
+            .fetch_one(&mut txn)
+            .await?;
+        if models::Status::from_int(org_status)? != models::Status::Active {
+            return Err(db::Err::OrgViolation.into());
+        }
+
+        if let Err(e) = sqlx::query(INSERT_QUERY)
+            .bind(team.id.to_string())
+            .bind(team.name.to_string())
+            .bind(team.org.to_string())
+            .bind(team.schema_version)
+            .execute(&mut txn)
+            .await
+        {
+            if db::sqlx_duplicate(&e) {
+                return Err(db::Err::TeamViolation.into());
+            }
+            return Err(e.into());
+        }
+
+        audit::insert(
+            &mut txn,
+            audit::TEAM_INSERT,
+            schema::TEAMS_TABLENAME,
+            &team.id,
+        )
+        .await?;
+
+        txn.commit().await?;
+
+        Ok(team)
+    }
+
+    /// read selects a teams row to construct a Team instance
+    #[allow(dead_code)]
+    pub async fn read(pool: &sqlx::SqlitePool, id: &Uuid) -> Result<Self, anyhow::Error> {
+        let row = sqlx::query(SELECT_QUERY)
+            .bind(id.to_string())
+            .fetch_one(pool)
+            .await?;
+
+        Ok(Self {
+            id: Uuid::try_parse(&row.try_get::<String, _>("id")?)?,
+            name: safe::VarChar::trusted(&row.try_get::<String, _>("name")?),
+            org: Uuid::try_parse(&row.try_get::<String, _>("org")?)?,
+            schema_version: row.try_get::<i8, _>("schema_version")?,
+        })
+    }
+
+    /// delete removes the team with its memberships and grants
+    ///
+    /// actor must be permitted to manage users
+    #[allow(dead_code)]
+    pub async fn delete(self, pool: &sqlx::SqlitePool, actor: &Uuid) -> Result<(), anyhow::Error> {
+        let mut txn = pool.begin().await?;
+
+        role::check(&mut txn, actor, &self.org, role::Permission::ManageUsers).await?;
+
+        for query in [DELETE_MEMBERS_QUERY, DELETE_GRANTS_QUERY] {
+            sqlx::query(query)
+                .bind(self.id.to_string())
+                .execute(&mut txn)
+                .await?;
+        }
+        let delete_result = sqlx::query(DELETE_QUERY)
+            .bind(self.id.to_string())
+            .execute(&mut txn)
+            .await?;
+        if delete_result.rows_affected() != 1 {
+            return Err(sqlx::Error::RowNotFound.into());
+        }
+
+        audit::insert(
+            &mut txn,
+            audit::TEAM_DELETE,
+            schema::TEAMS_TABLENAME,
+            &self.id,
+        )
+        .await?;
+
+        txn.commit().await?;
+
+        Ok(())
+    }
+
+    /// add_member adds user to the team
+    ///
+    /// actor must be permitted to manage users; user must belong to the team
+    /// org and not be erased, else db::Err::UserViolation; an existing member
+    /// is reported as db::Err::TeamViolation
+    #[allow(dead_code)]
+    pub async fn add_member(
+        &self,
+        pool: &sqlx::SqlitePool,
+        actor: &Uuid,
+        user: &Uuid,
+    ) -> Result<(), anyhow::Error> {
+        let mut txn = pool.begin().await?;
+
+        role::check(&mut txn, actor, &self.org, role::Permission::ManageUsers).await?;
+
+        let (user_org, user_status): (String, i64) = match sqlx::query_as(SELECT_USER_QUERY)
+            .bind(user.to_string())
+            .fetch_one(&mut txn)
+            .await
+        {
+            Err(e) if db::sqlx_row_not_found(&e) => return Err(db::Err::UserViolation.into()),
+            Err(e) => return Err(e.into()),
+            Ok(v) => v,
+        };
+        if Uuid::try_parse(&user_org)? != self.org
+            || models::Status::from_int(user_status)? == models::Status::Deleted
+        {
+            return Err(db::Err::UserViolation.into());
+        }
+
+        if let Err(e) = sqlx::query(INSERT_MEMBER_QUERY)
+            .bind(Uuid::new_v4().to_string())
+            .bind(self.id.to_string())
+            .bind(user.to_string())
+            .bind(SCHEMA_VERSION)
+            .execute(&mut txn)
+            .await
+        {
+            if db::sqlx_duplicate(&e) {
+                return Err(db::Err::TeamViolation.into());
+            }
+            return Err(e.into());
+        }
+
+        audit::insert(
+            &mut txn,
+            audit::TEAM_MEMBER,
+            schema::TEAMS_TABLENAME,
+            &self.id,
+        )
+        .await?;
+
+        txn.commit().await?;
+
+        Ok(())
+    }
+
+    /// remove_member removes user from the team
+    ///
+    /// actor must be permitted to manage users
+    #[allow(dead_code)]
+    pub async fn remove_member(
+        &self,
+        pool: &sqlx::SqlitePool,
+        actor: &Uuid,
+        user: &Uuid,
+    ) -> Result<(), anyhow::Error> {
+        let mut txn = pool.begin().await?;
+
+        role::check(&mut txn, actor, &self.org, role::Permission::ManageUsers).await?;
+
+        let delete_result = sqlx::query(DELETE_MEMBER_QUERY)
+            .bind(self.id.to_string())
+            .bind(user.to_string())
+            .execute(&mut txn)
+            .await?;
+        if delete_result.rows_affected() != 1 {
+            return Err(sqlx::Error::RowNotFound.into());
+        }
+
+        audit::insert(
+            &mut txn,
+            audit::TEAM_MEMBER,
+            schema::TEAMS_TABLENAME,
+            &self.id,
+        )
+        .await?;
+
+        txn.commit().await?;
+
+        Ok(())
+    }
+
+    /// members lists the ids of the team members
+    #[allow(dead_code)]
+    pub async fn members(&self, pool: &sqlx::SqlitePool) -> Result<Vec<Uuid>, anyhow::Error> {
+        let rows: Vec<String> = sqlx::query_scalar(SELECT_MEMBERS_QUERY)
+            .bind(self.id.to_string())
+            .fetch_all(pool)
+            .await?;
+        let mut members = Vec::with_capacity(rows.len());
+        for row in rows {
+            members.push(Uuid::try_parse(&row)?);
+        }
+        Ok(members)
+    }
+
+    /// grant sets the team access to repository, replacing any earlier grant
+    ///
+    /// actor must be permitted to manage repositories; repository must
+    /// belong to the team org, else db::Err::RepositoryViolation
+    #[allow(dead_code)]
+    pub async fn grant(
+        &self,
+        pool: &sqlx::SqlitePool,
+        actor: &Uuid,
+        repository: &Uuid,
+        access: Access,
+    ) -> Result<(), anyhow::Error> {
+        let mut txn = pool.begin().await?;
+
+        role::check(
+            &mut txn,
+            actor,
+            &self.org,
+            role::Permission::ManageRepositories,
+        )
+        .await?;
+
+        let repository_org: Option<String> = sqlx::query_scalar(SELECT_REPOSITORY_ORG_QUERY)
+            .bind(repository.to_string())
+            .fetch_optional(&mut txn)
+            .await?;
+        if repository_org != Some(self.org.to_string()) {
+            return Err(db::Err::RepositoryViolation.into());
+        }
+
+        sqlx::query(UPSERT_GRANT_QUERY)
+            .bind(Uuid::new_v4().to_string())
+            .bind(repository.to_string())
+            .bind(self.id.to_string())
+            .bind(access.to_int())
+            .bind(SCHEMA_VERSION)
+            .execute(&mut txn)
+            .await?;
+
+        audit::insert(
+            &mut txn,
+            audit::TEAM_GRANT,
+            schema::TEAMS_TABLENAME,
+            &self.id,
+        )
+        .await?;
+
+        txn.commit().await?;
+
+        Ok(())
+    }
+
+    /// revoke_grant removes the team access to repository
+    ///
+    /// actor must be permitted to manage repositories
+    #[allow(dead_code)]
+    pub async fn revoke_grant(
+        &self,
+        pool: &sqlx::SqlitePool,
+        actor: &Uuid,
+        repository: &Uuid,
+    ) -> Result<(), anyhow::Error> {
+        let mut txn = pool.begin().await?;
+
+        role::check(
+            &mut txn,
+            actor,
+            &self.org,
+            role::Permission::ManageRepositories,
+        )
+        .await?;
+
+        let delete_result = sqlx::query(DELETE_GRANT_QUERY)
+            .bind(repository.to_string())
+            .bind(self.id.to_string())
+            .execute(&mut txn)
+            .await?;
+        if delete_result.rows_affected() != 1 {
+            return Err(sqlx::Error::RowNotFound.into());
+        }
+
+        audit::insert(
+            &mut txn,
+            audit::TEAM_GRANT,
+            schema::TEAMS_TABLENAME,
+            &self.id,
+        )
+        .await?;
+
+        txn.commit().await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grokloc::app::admin::repository::Repository;
+    use crate::grokloc::app::admin::user::User;
+    use crate::grokloc::app::state;
+    use crate::grokloc::crypt;
+
+    /// active_member creates an Active Member of the root org
+    async fn active_member(app: &state::App) -> Result<User, anyhow::Error> {
+        let password = safe::VarChar::new(&crypt::kdf(&crypt::rand_hex(), crypt::MIN_KDF_ROUNDS))?;
+        let mut member = User::create(
+            &app.master_pool,
+            &safe::VarChar::rand(),
+            &safe::VarChar::rand(),
+            &app.root_org.id,
+            &password,
+            &app.key,
+        )
+        .await?;
+        member
+            .update_status(&app.master_pool, &app.root_user.id, models::Status::Active)
+            .await?;
+        Ok(member)
+    }
+
+    #[test]
+    fn access_int_test() -> Result<(), Err> {
+        for access in [Access::Read, Access::Admin] {
+            assert_eq!(access, Access::from_int(access.to_int())?);
+        }
+        assert_eq!(Err::UnknownAccess, Access::from_int(0).unwrap_err());
+        assert!(Access::Admin > Access::Read);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn team_create_members_test() -> Result<(), anyhow::Error> {
+        let app = state::unit().await?;
+        let name = safe::VarChar::rand();
+        let team =
+            Team::create(&app.master_pool, &app.root_user.id, &name, &app.root_org.id).await?;
+        let team_read = Team::read(&app.master_pool, &team.id).await?;
+        assert_eq!(name, team_read.name);
+        assert_eq!(app.root_org.id, team_read.org);
+
+        match Team::create(&app.master_pool, &app.root_user.id, &name, &app.root_org.id).await {
+            Ok(_) => unreachable!(),
+            Err(e) => assert!(matches!(
+                e.downcast_ref::<db::Err>(),
+                Some(db::Err::TeamViolation)
+            )),
+        };
+
+        let member = active_member(&app).await?;
+        team.add_member(&app.master_pool, &app.root_user.id, &member.id)
+            .await?;
+        assert_eq!(vec![member.id], team.members(&app.master_pool).await?);
+        match team
+            .add_member(&app.master_pool, &app.root_user.id, &member.id)
+            .await
+        {
+            Ok(_) => unreachable!(),
+            Err(e) => assert!(matches!(
+                e.downcast_ref::<db::Err>(),
+                Some(db::Err::TeamViolation)
+            )),
+        };
+        match team
+            .add_member(&app.master_pool, &app.root_user.id, &Uuid::new_v4())
+            .await
+        {
+            Ok(_) => unreachable!(),
+            Err(e) => assert!(matches!(
+                e.downcast_ref::<db::Err>(),
+                Some(db::Err::UserViolation)
+            )),
+        };
+
+        // members may not manage teams
+        match team
+            .remove_member(&app.master_pool, &member.id, &member.id)
+            .await
+        {
+            Ok(_) => unreachable!(),
+            Err(e) => assert_eq!(Some(&role::Err::Forbidden), e.downcast_ref::<role::Err>()),
+        };
+
+        team.remove_member(&app.master_pool, &app.root_user.id, &member.id)
+            .await?;
+        assert!(team.members(&app.master_pool).await?.is_empty());
+
+        let id = team.id;
+        team.delete(&app.master_pool, &app.root_user.id).await?;
+        match Team::read(&app.master_pool, &id).await {
+            Ok(_) => unreachable!(),
+            Err(e) => assert!(db::anyhow_sqlx_row_not_found(&e)),
+        };
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn team_effective_access_test() -> Result<(), anyhow::Error> {
+        let app = state::unit().await?;
+        let repository = Repository::create(
+            &app.master_pool,
+            &app.root_user.id,
+            &safe::VarChar::rand(),
+            &app.root_org.id,
+            &safe::VarChar::rand(),
+            &app.repo_base,
+        )
+        .await?;
+        let member = active_member(&app).await?;
+
+        // owners have implicit admin, members need a grant
+        assert_eq!(
+            Some(Access::Admin),
+            effective_access(&app.master_pool, &app.root_user.id, &repository.id).await?
+        );
+        assert_eq!(
+            None,
+            effective_access(&app.master_pool, &member.id, &repository.id).await?
+        );
+        assert_eq!(
+            None,
+            effective_access(&app.master_pool, &Uuid::new_v4(), &repository.id).await?
+        );
+
+        let readers = Team::create(
+            &app.master_pool,
+            &app.root_user.id,
+            &safe::VarChar::rand(),
+            &app.root_org.id,
+        )
+        .await?;
+        let admins = Team::create(
+            &app.master_pool,
+            &app.root_user.id,
+            &safe::VarChar::rand(),
+            &app.root_org.id,
+        )
+        .await?;
+        readers
+            .add_member(&app.master_pool, &app.root_user.id, &member.id)
+            .await?;
+        admins
+            .add_member(&app.master_pool, &app.root_user.id, &member.id)
+            .await?;
+
+        readers
+            .grant(
+                &app.master_pool,
+                &app.root_user.id,
+                &repository.id,
+                Access::Read,
+            )
+            .await?;
+        assert_eq!(
+            Some(Access::Read),
+            effective_access(&app.master_pool, &member.id, &repository.id).await?
+        );
+        match require_access(&app.master_pool, &member.id, &repository.id, Access::Admin).await {
+            Ok(_) => unreachable!(),
+            Err(e) => assert_eq!(Some(&role::Err::Forbidden), e.downcast_ref::<role::Err>()),
+        };
+
+        // the greatest grant wins
+        admins
+            .grant(
+                &app.master_pool,
+                &app.root_user.id,
+                &repository.id,
+                Access::Admin,
+            )
+            .await?;
+        assert_eq!(
+            Access::Admin,
+            require_access(&app.master_pool, &member.id, &repository.id, Access::Admin).await?
+        );
+
+        admins
+            .revoke_grant(&app.master_pool, &app.root_user.id, &repository.id)
+            .await?;
+        assert_eq!(
+            Some(Access::Read),
+            effective_access(&app.master_pool, &member.id, &repository.id).await?
+        );
+
+        // a grant to a repository of another org
+        match readers
+            .grant(
+                &app.master_pool,
+                &app.root_user.id,
+                &Uuid::new_v4(),
+                Access::Read,
+            )
+            .await
+        {
+            Ok(_) => unreachable!(),
+            Err(e) => assert!(matches!(
+                e.downcast_ref::<db::Err>(),
+                Some(db::Err::RepositoryViolation)
+            )),
+        };
+
+        // inactive users have no access
+        let mut member = member;
+        member
+            .update_status(
+                &app.master_pool,
+                &app.root_user.id,
+                models::Status::Inactive,
+            )
+            .await?;
+        assert_eq!(
+            None,
+            effective_access(&app.master_pool, &member.id, &repository.id).await?
+        );
+
+        Ok(())
+    }
+}
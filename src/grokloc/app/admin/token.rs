@@ -0,0 +1,132 @@
+//! token issues and redeems single-use, expiring user tokens
+use crate::grokloc::crypt;
+use anyhow;
+use sqlx;
+use thiserror::Error;
+use uuid::Uuid;
+
+pub const SCHEMA_VERSION: i8 = 0;
+
+pub const INSERT_QUERY: &str = r#"
+insert into user_tokens
+(id,
+ user,
+ token_digest,
+ expires,
+ schema_version)
+values
+(?,?,?,?,?)
+"#;
+
+pub const SELECT_QUERY: &str = r#"
+select user, expires from user_tokens where token_digest = ?
+"#;
+
+pub const DELETE_QUERY: &str = r#"
+delete from user_tokens where token_digest = ?
+"#;
+
+pub const DELETE_BY_USER_QUERY: &str = r#"
+delete from user_tokens where user = ?
+"#;
+
+/// Err covers token errors
+#[derive(Debug, Error, PartialEq)]
+pub enum Err {
+    #[error("invalid or expired token")]
+    Invalid,
+}
+
+/// issue creates a token for user that expires after ttl, returning the token
+///
+/// only the token digest is stored; the plaintext must be delivered by the caller
+pub async fn issue(
+    txn: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    user: &Uuid,
+    ttl: chrono::Duration,
+) -> Result<String, anyhow::Error> {
+    let token = crypt::rand_hex();
+    sqlx::query(INSERT_QUERY)
+        .bind(Uuid::new_v4().to_string())
+        .bind(user.to_string())
+        .bind(crypt::sha256_hex(&token))
+        .bind((chrono::Utc::now() + ttl).timestamp())
+        .bind(SCHEMA_VERSION)
+        .execute(txn)
+        .await?;
+    Ok(token)
+}
+
+/// redeem consumes token, returning the user it was issued for
+///
+/// an unknown, already redeemed or expired token is reported as Err::Invalid
+pub async fn redeem(
+    txn: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    token: &str,
+) -> Result<Uuid, anyhow::Error> {
+    let token_digest = crypt::sha256_hex(token);
+    let row: Option<(String, i64)> = sqlx::query_as(SELECT_QUERY)
+        .bind(&token_digest)
+        .fetch_optional(&mut *txn)
+        .await?;
+    sqlx::query(DELETE_QUERY)
+        .bind(&token_digest)
+        .execute(&mut *txn)
+        .await?;
+    match row {
+        Some((user, expires)) if expires > chrono::Utc::now().timestamp() => {
+            Ok(Uuid::try_parse(&user)?)
+        }
+        _ => Err(Err::Invalid.into()),
+    }
+}
+
+/// revoke deletes every outstanding token of user
+pub async fn revoke(
+    txn: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    user: &Uuid,
+) -> Result<(), anyhow::Error> {
+    sqlx::query(DELETE_BY_USER_QUERY)
+        .bind(user.to_string())
+        .execute(txn)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grokloc::app::state;
+
+    #[tokio::test]
+    async fn token_issue_redeem_test() -> Result<(), anyhow::Error> {
+        let app = state::unit().await?;
+        let user = app.root_user.id;
+
+        let mut txn = app.master_pool.begin().await?;
+        let token = issue(&mut txn, &user, chrono::Duration::hours(1)).await?;
+        let expired = issue(&mut txn, &user, chrono::Duration::seconds(-1)).await?;
+        let revoked = issue(&mut txn, &user, chrono::Duration::hours(1)).await?;
+        txn.commit().await?;
+
+        let mut txn = app.master_pool.begin().await?;
+        assert_eq!(user, redeem(&mut txn, &token).await?);
+        // single use
+        match redeem(&mut txn, &token).await {
+            Ok(_) => unreachable!(),
+            Err(e) => assert_eq!(Some(&Err::Invalid), e.downcast_ref::<Err>()),
+        };
+        match redeem(&mut txn, &expired).await {
+            Ok(_) => unreachable!(),
+            Err(e) => assert_eq!(Some(&Err::Invalid), e.downcast_ref::<Err>()),
+        };
+        revoke(&mut txn, &user).await?;
+        match redeem(&mut txn, &revoked).await {
+            Ok(_) => unreachable!(),
+            Err(e) => assert_eq!(Some(&Err::Invalid), e.downcast_ref::<Err>()),
+        };
+        txn.commit().await?;
+
+        Ok(())
+    }
+}
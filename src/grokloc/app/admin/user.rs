@@ -1,9 +1,16 @@
 //! user models an orgs row and related db functionality
+use crate::grokloc::app::admin::role;
+use crate::grokloc::app::admin::token;
+use crate::grokloc::app::audit;
 use crate::grokloc::app::models;
+use crate::grokloc::app::outbox;
+use crate::grokloc::app::schema;
 use crate::grokloc::crypt;
+use crate::grokloc::db;
 use crate::grokloc::safe;
 use anyhow;
 use sqlx;
+use sqlx::sqlite::SqliteRow;
 use sqlx::Row;
 use uuid::Uuid;
 
@@ -20,14 +27,16 @@ insert into users
  email_digest,
  org,
  password,
+ role,
  schema_version,
  status)
 values
-(?,?,?,?,?,?,?,?,?,?,?)
+(?,?,?,?,?,?,?,?,?,?,?,?)
 "#;
 
 pub const SELECT_QUERY: &str = r#"
 select
+ id,
  api_secret,
  api_secret_digest,
  display_name,
@@ -36,6 +45,7 @@ select
  email_digest,
  org,
  password,
+ role,
  ctime,
  mtime,
  schema_version,
@@ -44,15 +54,207 @@ from users
 where id = ?;
 "#;
 
+#[allow(dead_code)]
+pub const LIST_BY_ORG_QUERY: &str = r#"
+select
+ id,
+ display_name,
+ email,
+ email_digest,
+ role,
+ ctime,
+ mtime,
+ schema_version,
+ status
+from users
+where org = ?
+and (? is null or status = ?)
+and (? is null or status != ?)
+and (ctime > ? or (ctime = ? and id > ?))
+order by ctime, id
+limit ?
+"#;
+
+#[allow(dead_code)]
+pub const LIST_SUMMARIES_BY_ORG_QUERY: &str = r#"
+select
+ id,
+ role,
+ ctime,
+ mtime,
+ schema_version,
+ status
+from users
+where org = ?
+and (? is null or status = ?)
+and (? is null or status != ?)
+and (ctime > ? or (ctime = ? and id > ?))
+order by ctime, id
+limit ?
+"#;
+
+pub const SELECT_ORG_STATUS_QUERY: &str = r#"
+select status from orgs where id = ?;
+"#;
+
 pub const UPDATE_STATUS_QUERY: &str = r#"
 update users set status = ? where id = ?;
 "#;
 
+#[allow(dead_code)]
+pub const UPDATE_STATUS_UNLESS_DELETED_QUERY: &str = r#"
+update users set status = ? where id = ? and status != ?;
+"#;
+
+#[allow(dead_code)]
+pub const UPDATE_ROLE_QUERY: &str = r#"
+update users set role = ? where id = ? and status != ?
+"#;
+
 #[allow(dead_code)]
 pub const UPDATE_DISPLAY_NAME_QUERY: &str = r#"
-update users set display_name = ?, display_name_digest = ? where id = ?
+update users set display_name = ?, display_name_digest = ? where id = ? and status != ?
+"#;
+
+#[allow(dead_code)]
+pub const UPDATE_EMAIL_QUERY: &str = r#"
+update users set
+ api_secret = ?,
+ display_name = ?,
+ email = ?,
+ email_digest = ?,
+ status = coalesce(?, status)
+where id = ? and status != ?
+"#;
+
+#[allow(dead_code)]
+pub const UPDATE_API_SECRET_QUERY: &str = r#"
+update users set api_secret = ?, api_secret_digest = ? where id = ? and status != ?
+"#;
+
+#[allow(dead_code)]
+pub const INSERT_API_SECRET_GRACE_QUERY: &str = r#"
+insert into api_secret_grace
+(id,
+ user,
+ api_secret_digest,
+ expires)
+values
+(?,?,?,?)
+"#;
+
+#[allow(dead_code)]
+pub const DELETE_EXPIRED_API_SECRET_GRACE_QUERY: &str = r#"
+delete from api_secret_grace where user = ? and expires <= ?
+"#;
+
+#[allow(dead_code)]
+pub const SELECT_ID_BY_API_SECRET_DIGEST_QUERY: &str = r#"
+select users.id from users
+join orgs on orgs.id = users.org
+where users.api_secret_digest = ?
+and users.status = ?
+and orgs.status = ?
+union
+select users.id from api_secret_grace
+join users on users.id = api_secret_grace.user
+join orgs on orgs.id = users.org
+where api_secret_grace.api_secret_digest = ?
+and api_secret_grace.expires > ?
+and users.status = ?
+and orgs.status = ?
+"#;
+
+#[allow(dead_code)]
+pub const ERASE_QUERY: &str = r#"
+update users set
+ api_secret = ?,
+ api_secret_digest = ?,
+ display_name = ?,
+ display_name_digest = ?,
+ email = ?,
+ email_digest = ?,
+ password = ?,
+ role = ?,
+ status = ?
+where id = ?
+"#;
+
+#[allow(dead_code)]
+pub const DELETE_API_SECRET_GRACE_QUERY: &str = r#"
+delete from api_secret_grace where user = ?
+"#;
+
+#[allow(dead_code)]
+pub const DELETE_STATUS_CASCADE_QUERY: &str = r#"
+delete from status_cascade where source = ? and source_id = ?
+"#;
+
+#[allow(dead_code)]
+pub const DELETE_OUTBOX_QUERY: &str = r#"
+delete from outbox where user = ?
+"#;
+
+#[allow(dead_code)]
+pub const DELETE_TEAM_MEMBERS_QUERY: &str = r#"
+delete from team_members where user = ?
+"#;
+
+#[allow(dead_code)]
+pub const DELETE_INVITATIONS_QUERY: &str = r#"
+delete from invitations where org = ? and email_digest = ?
+"#;
+
+pub const DELETE_AUTHOR_STATS_QUERY: &str = r#"
+delete from author_stats where author in
+ (select id from authors
+  where user = ?
+  or (email_digest = ? and repository in (select id from repositories where org = ?)))
+"#;
+
+pub const DELETE_DIRECTORY_OWNERS_QUERY: &str = r#"
+delete from directory_owners where author in
+ (select id from authors
+  where user = ?
+  or (email_digest = ? and repository in (select id from repositories where org = ?)))
+"#;
+
+pub const DELETE_AUTHORS_QUERY: &str = r#"
+delete from authors
+where user = ?
+or (email_digest = ? and repository in (select id from repositories where org = ?))
 "#;
 
+/// ErasureReceipt records the erasure of a user; id is that of the audit entry
+#[derive(Clone, Debug)]
+#[allow(dead_code)]
+pub struct ErasureReceipt {
+    pub id: Uuid,
+    pub user: Uuid,
+    pub org: Uuid,
+    pub ctime: chrono::DateTime<chrono::Utc>,
+}
+
+/// Profile is a decrypted projection of a users row without secrets
+#[derive(Clone, Debug)]
+#[allow(dead_code)]
+pub struct Profile {
+    pub id: Uuid,
+    pub display_name: safe::VarChar,
+    pub email: safe::VarChar,
+    pub role: role::Role,
+    pub meta: models::Meta,
+}
+
+/// Summary is a projection of a users row that requires no decryption
+#[derive(Clone, Debug)]
+#[allow(dead_code)]
+pub struct Summary {
+    pub id: Uuid,
+    pub role: role::Role,
+    pub meta: models::Meta,
+}
+
 /// User is the data representation of an users row
 #[derive(Clone, Debug)]
 pub struct User {
@@ -65,12 +267,14 @@ pub struct User {
     pub email_digest: safe::VarChar,
     pub org: Uuid,
     pub password: safe::VarChar,
+    pub role: role::Role,
     pub meta: models::Meta,
 }
 
 impl User {
     /// encrypted makes a new User with PII fields encrypted with key as key
-    /// and salt derived from the email (which is constrained to be unique in the db)
+    /// and salt derived from the email (which is constrained to be unique in the db);
+    /// the email is normalised first (see safe::email_normal)
     ///
     /// if you want a decrypted User, you must read() it from the db
     ///
@@ -84,6 +288,7 @@ impl User {
         password: &safe::VarChar, // assumed already derived
         key: &str,
     ) -> Result<User, anyhow::Error> {
+        let email = &safe::VarChar::new(&safe::email_normal(&email.to_string()))?;
         let email_digest = crypt::sha256_hex(&email.to_string());
         let iv = crypt::iv(&email_digest);
         let api_secret_ = Uuid::new_v4();
@@ -102,6 +307,7 @@ impl User {
             email_digest: safe::VarChar::new(&email_digest)?,
             org: *org,
             password: password.clone(),
+            role: role::Role::default(),
             meta: models::Meta {
                 schema_version: SCHEMA_VERSION,
                 ..Default::default()
@@ -128,6 +334,7 @@ impl User {
             .bind(self.email_digest.to_string())
             .bind(self.org.to_string())
             .bind(self.password.to_string())
+            .bind(self.role.to_int())
             .bind(self.meta.schema_version)
             .bind(self.meta.status.to_int())
             .execute(txn)
@@ -138,7 +345,66 @@ impl User {
         Ok(())
     }
 
+    /// insert_checked performs the org integrity check, the insert and the
+    /// audit entry within txn, for callers that create users in their own
+    /// transaction (see create)
+    ///
+    /// the org must exist and be Active; a duplicate email within the org is
+    /// reported as db::Err::UserViolation
+    #[allow(dead_code)]
+    pub async fn insert_checked(
+        &self,
+        txn: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    ) -> Result<(), anyhow::Error> {
+        let org_status: i64 = match sqlx::query_scalar(SELECT_ORG_STATUS_QUERY)
+            .bind(self.org.to_string())
+            .fetch_one(&mut *txn)
+            .await
+        {
+            Err(e) if db::sqlx_row_not_found(&e) => return Err(db::Err::OrgViolation.into()),
+            Err(e) => return Err(e.into()),
+            Ok(v) => v,
+        };
+        if models::Status::from_int(org_status)? != models::Status::Active {
+            return Err(db::Err::OrgViolation.into());
+        }
+
+        if let Err(insert_error) = self.insert(txn).await {
+            if db::anyhow_sqlx_duplicate(&insert_error) {
+                return Err(db::Err::UserViolation.into());
+            }
+            return Err(insert_error);
+        }
+
+        audit::insert(txn, audit::USER_INSERT, schema::USERS_TABLENAME, &self.id).await?;
+
+        Ok(())
+    }
+
+    /// create forms a new User in an existing Active org
+    ///
+    /// the org check, the insert and the audit entry share one transaction;
+    /// a duplicate email within the org is reported as db::Err::UserViolation
+    #[allow(dead_code)]
+    pub async fn create(
+        pool: &sqlx::SqlitePool,
+        display_name: &safe::VarChar,
+        email: &safe::VarChar,
+        org: &Uuid,
+        password: &safe::VarChar, // assumed already derived
+        key: &str,
+    ) -> Result<Self, anyhow::Error> {
+        let user = User::encrypted(display_name, email, org, password, key)?;
+        let mut txn = pool.begin().await?;
+        user.insert_checked(&mut txn).await?;
+        txn.commit().await?;
+
+        Ok(user)
+    }
+
     /// read selects and decrypts a users row to construct a User instance
+    ///
+    /// an erased user has nothing to decrypt and is reported as not found
     #[allow(dead_code)]
     pub async fn read(
         pool: &sqlx::SqlitePool,
@@ -146,9 +412,153 @@ impl User {
         key: &str,
     ) -> Result<Self, anyhow::Error> {
         let row = sqlx::query(SELECT_QUERY)
-            .bind(&id.to_string())
+            .bind(id.to_string())
             .fetch_one(pool)
             .await?;
+        if models::Status::from_int(row.try_get::<i64, _>("status")?)? == models::Status::Deleted {
+            return Err(sqlx::Error::RowNotFound.into());
+        }
+
+        Self::from_row(&row, key)
+    }
+
+    /// list_by_org selects and decrypts a page of up to limit users of org,
+    /// optionally with status, ordered by ctime and starting after cursor;
+    /// erased users are never listed
+    ///
+    /// the returned cursor is Some iff there may be more users to list
+    #[allow(dead_code)]
+    pub async fn list_by_org(
+        pool: &sqlx::SqlitePool,
+        org: &Uuid,
+        status: Option<models::Status>,
+        cursor: Option<&models::Cursor>,
+        limit: i64,
+        key: &str,
+    ) -> Result<(Vec<Profile>, Option<models::Cursor>), anyhow::Error> {
+        let limit = models::limit_ok(limit);
+        let rows = Self::list_rows(
+            pool,
+            LIST_BY_ORG_QUERY,
+            org,
+            status,
+            Some(models::Status::Deleted),
+            cursor,
+            limit,
+        )
+        .await?;
+
+        let mut profiles = Vec::with_capacity(rows.len());
+        for row in rows.iter().take(limit as usize) {
+            let iv = crypt::iv(&row.try_get::<String, _>("email_digest")?);
+            let display_name_ =
+                crypt::decrypt(key, &iv, &row.try_get::<String, _>("display_name")?)?;
+            let email_ = crypt::decrypt(key, &iv, &row.try_get::<String, _>("email")?)?;
+            let summary = Self::summary_from_row(row)?;
+            profiles.push(Profile {
+                id: summary.id,
+                display_name: safe::VarChar::trusted(&display_name_),
+                email: safe::VarChar::trusted(&email_),
+                role: summary.role,
+                meta: summary.meta,
+            });
+        }
+
+        let next = Self::next_cursor(
+            rows.len() as i64 > limit,
+            profiles.last().map(|v| (v.id, v.meta)),
+        );
+        Ok((profiles, next))
+    }
+
+    /// list_summaries_by_org is list_by_org without decryption, for large orgs;
+    /// erased users are listed as tombstones
+    #[allow(dead_code)]
+    pub async fn list_summaries_by_org(
+        pool: &sqlx::SqlitePool,
+        org: &Uuid,
+        status: Option<models::Status>,
+        cursor: Option<&models::Cursor>,
+        limit: i64,
+    ) -> Result<(Vec<Summary>, Option<models::Cursor>), anyhow::Error> {
+        let limit = models::limit_ok(limit);
+        let rows = Self::list_rows(
+            pool,
+            LIST_SUMMARIES_BY_ORG_QUERY,
+            org,
+            status,
+            None,
+            cursor,
+            limit,
+        )
+        .await?;
+
+        let mut summaries = Vec::with_capacity(rows.len());
+        for row in rows.iter().take(limit as usize) {
+            summaries.push(Self::summary_from_row(row)?);
+        }
+
+        let next = Self::next_cursor(
+            rows.len() as i64 > limit,
+            summaries.last().map(|v| (v.id, v.meta)),
+        );
+        Ok((summaries, next))
+    }
+
+    /// list_rows fetches up to limit + 1 rows of a by-org listing query
+    async fn list_rows(
+        pool: &sqlx::SqlitePool,
+        query: &str,
+        org: &Uuid,
+        status: Option<models::Status>,
+        exclude: Option<models::Status>,
+        cursor: Option<&models::Cursor>,
+        limit: i64,
+    ) -> Result<Vec<SqliteRow>, anyhow::Error> {
+        let status = status.map(|v| v.to_int());
+        let exclude = exclude.map(|v| v.to_int());
+        let (after_ctime, after_id) = models::Cursor::bind_values(cursor);
+        Ok(sqlx::query(query)
+            .bind(org.to_string())
+            .bind(status)
+            .bind(status)
+            .bind(exclude)
+            .bind(exclude)
+            .bind(after_ctime)
+            .bind(after_ctime)
+            .bind(after_id)
+            .bind(limit + 1)
+            .fetch_all(pool)
+            .await?)
+    }
+
+    /// next_cursor forms the cursor following the last listed row, if there are more
+    fn next_cursor(more: bool, last: Option<(Uuid, models::Meta)>) -> Option<models::Cursor> {
+        match more {
+            true => last.map(|(id, meta)| models::Cursor {
+                ctime: meta.ctime.timestamp(),
+                id,
+            }),
+            false => None,
+        }
+    }
+
+    /// summary_from_row constructs a Summary from a row selecting its columns
+    fn summary_from_row(row: &SqliteRow) -> Result<Summary, anyhow::Error> {
+        Ok(Summary {
+            id: Uuid::try_parse(&row.try_get::<String, _>("id")?)?,
+            role: role::Role::from_int(row.try_get::<i64, _>("role")?)?,
+            meta: models::Meta::from_db(
+                row.try_get::<i64, _>("ctime")?,
+                row.try_get::<i64, _>("mtime")?,
+                row.try_get::<i8, _>("schema_version")?,
+                row.try_get::<i64, _>("status")?,
+            )?,
+        })
+    }
+
+    /// from_row decrypts a row selecting all users columns to construct a User
+    fn from_row(row: &SqliteRow, key: &str) -> Result<Self, anyhow::Error> {
         let email_digest_ = row.try_get::<String, _>("email_digest")?;
         let iv = crypt::iv(&email_digest_);
         let encrypted_api_secret = row.try_get::<String, _>("api_secret")?;
@@ -159,7 +569,7 @@ impl User {
         let email_ = crypt::decrypt(key, &iv, &encrypted_email)?;
 
         Ok(Self {
-            id: *id,
+            id: Uuid::try_parse(&row.try_get::<String, _>("id")?)?,
             api_secret: safe::VarChar::trusted(&api_secret_),
             api_secret_digest: safe::VarChar::trusted(
                 &row.try_get::<String, _>("api_secret_digest")?,
@@ -172,6 +582,7 @@ impl User {
             email_digest: safe::VarChar::trusted(&email_digest_),
             org: Uuid::try_parse(&row.try_get::<String, _>("org")?)?,
             password: safe::VarChar::trusted(&row.try_get::<String, _>("password")?),
+            role: role::Role::from_int(row.try_get::<i64, _>("role")?)?,
             meta: models::Meta::from_db(
                 row.try_get::<i64, _>("ctime")?,
                 row.try_get::<i64, _>("mtime")?,
@@ -181,16 +592,45 @@ impl User {
         })
     }
 
+    /// check_self_or_manage succeeds if actor is this user and Active in an
+    /// Active org, or may manage users in its org
+    async fn check_self_or_manage(
+        &self,
+        pool: &sqlx::SqlitePool,
+        actor: &Uuid,
+    ) -> Result<(), anyhow::Error> {
+        if *actor == self.id {
+            role::check_self(pool, actor, &self.org).await?;
+        } else {
+            role::check(pool, actor, &self.org, role::Permission::ManageUsers).await?;
+        }
+        Ok(())
+    }
+
     /// update_status updates the user status
+    ///
+    /// actor must be permitted to manage users; the org owner must remain Active;
+    /// a user is only Deleted by erase, and an erased user is never changed
     #[allow(dead_code)]
     pub async fn update_status(
         &mut self,
         pool: &sqlx::SqlitePool,
+        actor: &Uuid,
         new_status: models::Status,
     ) -> Result<(), anyhow::Error> {
-        let update_result = match sqlx::query(UPDATE_STATUS_QUERY)
+        if new_status == models::Status::Deleted || self.meta.status == models::Status::Deleted {
+            return Err(models::Err::Deleted.into());
+        }
+        role::check(pool, actor, &self.org, role::Permission::ManageUsers).await?;
+        if self.role == role::Role::Owner && new_status != models::Status::Active {
+            return Err(role::Err::OwnerInvariant.into());
+        }
+
+        // the status guard also covers a row erased since self was read
+        let update_result = match sqlx::query(UPDATE_STATUS_UNLESS_DELETED_QUERY)
             .bind(new_status.to_int())
             .bind(self.id.to_string())
+            .bind(models::Status::Deleted.to_int())
             .execute(pool)
             .await
         {
@@ -208,14 +648,68 @@ impl User {
         Ok(())
     }
 
+    /// update_role updates the user role
+    ///
+    /// actor must be permitted to manage users; the owner role can only
+    /// change hands through an org ownership transfer
+    #[allow(dead_code)]
+    pub async fn update_role(
+        &mut self,
+        pool: &sqlx::SqlitePool,
+        actor: &Uuid,
+        new_role: role::Role,
+    ) -> Result<(), anyhow::Error> {
+        role::check(pool, actor, &self.org, role::Permission::ManageUsers).await?;
+        if self.role == role::Role::Owner || new_role == role::Role::Owner {
+            return Err(role::Err::OwnerInvariant.into());
+        }
+
+        let mut txn = pool.begin().await?;
+
+        let update_result = match sqlx::query(UPDATE_ROLE_QUERY)
+            .bind(new_role.to_int())
+            .bind(self.id.to_string())
+            .bind(models::Status::Deleted.to_int())
+            .execute(&mut txn)
+            .await
+        {
+            Err(e) => return Err(e.into()),
+            Ok(v) => v,
+        };
+
+        if update_result.rows_affected() != 1 {
+            return Err(sqlx::Error::RowNotFound.into());
+        }
+
+        audit::insert(
+            &mut txn,
+            audit::USER_ROLE,
+            schema::USERS_TABLENAME,
+            &self.id,
+        )
+        .await?;
+
+        txn.commit().await?;
+
+        // the update to the db was a success, set the internal field
+        self.role = new_role;
+
+        Ok(())
+    }
+
     /// update_display_name updates the user diplay_name and its digest
+    ///
+    /// actor must be this user, or be permitted to manage users
     #[allow(dead_code)]
     pub async fn update_display_name(
         &mut self,
         pool: &sqlx::SqlitePool,
+        actor: &Uuid,
         new_display_name: &safe::VarChar,
         key: &str,
     ) -> Result<(), anyhow::Error> {
+        self.check_self_or_manage(pool, actor).await?;
+
         let iv = crypt::iv(&self.email_digest.to_string());
         let encrypted_display_name = &crypt::encrypt(key, &iv, &new_display_name.to_string())?;
         let display_name_digest = &crypt::sha256_hex(&new_display_name.to_string());
@@ -223,6 +717,7 @@ impl User {
             .bind(encrypted_display_name)
             .bind(display_name_digest)
             .bind(self.id.to_string())
+            .bind(models::Status::Deleted.to_int())
             .execute(pool)
             .await
         {
@@ -240,44 +735,349 @@ impl User {
 
         Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::grokloc::app::schema;
-    use crate::grokloc::db;
+    /// rotate_api_secret replaces the user api secret with a new random one
+    ///
+    /// self must be decrypted (see read); if grace is provided, the previous
+    /// secret is still accepted by authenticate_api_secret until grace elapses,
+    /// otherwise every earlier secret still in a grace period is revoked too
+    ///
+    /// actor must be this user, or be permitted to manage users
+    #[allow(dead_code)]
+    pub async fn rotate_api_secret(
+        &mut self,
+        pool: &sqlx::SqlitePool,
+        actor: &Uuid,
+        grace: Option<chrono::Duration>,
+        key: &str,
+    ) -> Result<(), anyhow::Error> {
+        self.check_self_or_manage(pool, actor).await?;
 
-    #[test]
-    fn user_encrypted_test() -> Result<(), anyhow::Error> {
-        let key = crypt::rand_key();
-        let display_name = safe::VarChar::rand();
-        let email = safe::VarChar::rand();
-        let org = Uuid::new_v4();
-        let password = safe::VarChar::new(&crypt::kdf(&crypt::rand_hex(), crypt::MIN_KDF_ROUNDS))?;
-        let user = User::encrypted(&display_name, &email, &org, &password, &key)?;
+        let iv = crypt::iv(&self.email_digest.to_string());
+        let api_secret_ = Uuid::new_v4().to_string();
+        let encrypted_api_secret = crypt::encrypt(key, &iv, &api_secret_)?;
+        let api_secret_digest = crypt::sha256_hex(&api_secret_);
+        let now = chrono::Utc::now();
 
-        let email_digest = crypt::sha256_hex(&email.to_string());
-        let iv = crypt::iv(&email_digest);
+        let mut txn = pool.begin().await?;
 
-        let decrypted_api_secret = crypt::decrypt(&key, &iv, &user.api_secret.to_string())?;
+        let update_result = match sqlx::query(UPDATE_API_SECRET_QUERY)
+            .bind(encrypted_api_secret)
+            .bind(&api_secret_digest)
+            .bind(self.id.to_string())
+            .bind(models::Status::Deleted.to_int())
+            .execute(&mut txn)
+            .await
+        {
+            Err(e) => return Err(e.into()),
+            Ok(v) => v,
+        };
 
-        assert_eq!(
-            crypt::sha256_hex(&decrypted_api_secret),
-            user.api_secret_digest.to_string(),
-            "api_secret_digest"
-        );
+        if update_result.rows_affected() != 1 {
+            return Err(sqlx::Error::RowNotFound.into());
+        }
 
-        let decrypted_display_name = crypt::decrypt(&key, &iv, &user.display_name.to_string())?;
-        assert_eq!(
-            &decrypted_display_name,
-            &display_name.to_string(),
-            "display name"
-        );
+        match grace {
+            Some(grace) => {
+                sqlx::query(DELETE_EXPIRED_API_SECRET_GRACE_QUERY)
+                    .bind(self.id.to_string())
+                    .bind(now.timestamp())
+                    .execute(&mut txn)
+                    .await?;
+                sqlx::query(INSERT_API_SECRET_GRACE_QUERY)
+                    .bind(Uuid::new_v4().to_string())
+                    .bind(self.id.to_string())
+                    .bind(self.api_secret_digest.to_string())
+                    .bind((now + grace).timestamp())
+                    .execute(&mut txn)
+                    .await?;
+            }
+            // an emergency rotation, no earlier secret may survive it
+            None => {
+                sqlx::query(DELETE_API_SECRET_GRACE_QUERY)
+                    .bind(self.id.to_string())
+                    .execute(&mut txn)
+                    .await?;
+            }
+        }
 
-        assert_eq!(
-            crypt::sha256_hex(&display_name.to_string()),
-            user.display_name_digest.to_string(),
+        audit::insert(
+            &mut txn,
+            audit::USER_API_SECRET,
+            schema::USERS_TABLENAME,
+            &self.id,
+        )
+        .await?;
+
+        txn.commit().await?;
+
+        // the update to the db was a success, set the internal fields
+        self.api_secret = safe::VarChar::trusted(&api_secret_);
+        self.api_secret_digest = safe::VarChar::trusted(&api_secret_digest);
+
+        Ok(())
+    }
+
+    /// authenticate_api_secret returns the id of the Active user of an Active org
+    /// holding api_secret, accepting previous secrets still within their
+    /// rotation grace period
+    #[allow(dead_code)]
+    pub async fn authenticate_api_secret(
+        pool: &sqlx::SqlitePool,
+        api_secret: &str,
+    ) -> Result<Uuid, anyhow::Error> {
+        let api_secret_digest = crypt::sha256_hex(api_secret);
+        let active = models::Status::Active.to_int();
+        let id: String = sqlx::query_scalar(SELECT_ID_BY_API_SECRET_DIGEST_QUERY)
+            .bind(&api_secret_digest)
+            .bind(active)
+            .bind(active)
+            .bind(&api_secret_digest)
+            .bind(chrono::Utc::now().timestamp())
+            .bind(active)
+            .bind(active)
+            .fetch_one(pool)
+            .await?;
+        Ok(Uuid::try_parse(&id)?)
+    }
+
+    /// update_email changes the user email, re-encrypting all PII fields
+    /// since the salt is derived from the email
+    ///
+    /// self must be decrypted (see read); if reconfirm is true, the user
+    /// status is set back to Unconfirmed, otherwise it is left as stored; the
+    /// old address is queued a notification in the outbox; the new email is
+    /// normalised first (see safe::email_normal)
+    ///
+    /// actor must be this user, or be permitted to manage users
+    #[allow(dead_code)]
+    pub async fn update_email(
+        &mut self,
+        pool: &sqlx::SqlitePool,
+        actor: &Uuid,
+        new_email: &safe::VarChar,
+        reconfirm: bool,
+        key: &str,
+    ) -> Result<(), anyhow::Error> {
+        self.check_self_or_manage(pool, actor).await?;
+
+        let new_email = &safe::VarChar::new(&safe::email_normal(&new_email.to_string()))?;
+        let email_digest = crypt::sha256_hex(&new_email.to_string());
+        let iv = crypt::iv(&email_digest);
+        let encrypted_api_secret = crypt::encrypt(key, &iv, &self.api_secret.to_string())?;
+        let encrypted_display_name = crypt::encrypt(key, &iv, &self.display_name.to_string())?;
+        let encrypted_email = crypt::encrypt(key, &iv, &new_email.to_string())?;
+        // the status is left as stored unless reconfirming
+        let new_status = match reconfirm {
+            true => Some(models::Status::Unconfirmed),
+            false => None,
+        };
+
+        let mut txn = pool.begin().await?;
+
+        let update_result = match sqlx::query(UPDATE_EMAIL_QUERY)
+            .bind(encrypted_api_secret)
+            .bind(encrypted_display_name)
+            .bind(encrypted_email)
+            .bind(&email_digest)
+            .bind(new_status.map(|v| v.to_int()))
+            .bind(self.id.to_string())
+            .bind(models::Status::Deleted.to_int())
+            .execute(&mut txn)
+            .await
+        {
+            Err(e) if db::sqlx_duplicate(&e) => return Err(db::Err::UserViolation.into()),
+            Err(e) => return Err(e.into()),
+            Ok(v) => v,
+        };
+
+        if update_result.rows_affected() != 1 {
+            return Err(sqlx::Error::RowNotFound.into());
+        }
+
+        audit::insert(
+            &mut txn,
+            audit::USER_EMAIL,
+            schema::USERS_TABLENAME,
+            &self.id,
+        )
+        .await?;
+
+        outbox::insert(&mut txn, outbox::EMAIL_CHANGED, &self.email, &self.id, key).await?;
+
+        txn.commit().await?;
+
+        // the update to the db was a success, set the internal fields
+        self.email = new_email.clone();
+        self.email_digest = safe::VarChar::trusted(&email_digest);
+        if let Some(v) = new_status {
+            self.meta.status = v;
+        }
+
+        Ok(())
+    }
+
+    /// erase crypto-shreds the user PII, leaving a Deleted tombstone row so
+    /// that audit entries referencing the user remain valid
+    ///
+    /// encrypted fields and digests are replaced with random values, so the
+    /// PII cannot be recovered even with the key; pending notifications to any
+    /// address the user has had, invitations to the current email, outstanding tokens and team memberships
+    /// are dropped, and git authors linked to the user or with its email in
+    /// org repositories are deleted with their statistics and ownership;
+    /// a later analysis of history still holding the email records them
+    /// again; actor must be this user, or
+    /// be permitted to manage users; the org owner cannot be erased
+    #[allow(dead_code)]
+    pub async fn erase(
+        &mut self,
+        pool: &sqlx::SqlitePool,
+        actor: &Uuid,
+    ) -> Result<ErasureReceipt, anyhow::Error> {
+        self.check_self_or_manage(pool, actor).await?;
+        if self.role == role::Role::Owner {
+            return Err(role::Err::OwnerInvariant.into());
+        }
+
+        let shredded = || safe::VarChar::trusted(&crypt::rand_hex());
+        let api_secret_ = shredded();
+        let api_secret_digest_ = shredded();
+        let display_name_ = shredded();
+        let display_name_digest_ = shredded();
+        let email_ = shredded();
+        let email_digest_ = shredded();
+        let password_ =
+            safe::VarChar::trusted(&crypt::kdf(&crypt::rand_hex(), crypt::MIN_KDF_ROUNDS));
+        let role_ = role::Role::ReadOnly;
+        let status_ = models::Status::Deleted;
+
+        let mut txn = pool.begin().await?;
+
+        let update_result = match sqlx::query(ERASE_QUERY)
+            .bind(api_secret_.to_string())
+            .bind(api_secret_digest_.to_string())
+            .bind(display_name_.to_string())
+            .bind(display_name_digest_.to_string())
+            .bind(email_.to_string())
+            .bind(email_digest_.to_string())
+            .bind(password_.to_string())
+            .bind(role_.to_int())
+            .bind(status_.to_int())
+            .bind(self.id.to_string())
+            .execute(&mut txn)
+            .await
+        {
+            Err(e) => return Err(e.into()),
+            Ok(v) => v,
+        };
+
+        if update_result.rows_affected() != 1 {
+            return Err(sqlx::Error::RowNotFound.into());
+        }
+
+        sqlx::query(DELETE_API_SECRET_GRACE_QUERY)
+            .bind(self.id.to_string())
+            .execute(&mut txn)
+            .await?;
+        token::revoke(&mut txn, &self.id).await?;
+        sqlx::query(DELETE_TEAM_MEMBERS_QUERY)
+            .bind(self.id.to_string())
+            .execute(&mut txn)
+            .await?;
+        // authors are referenced by id, so their references go first
+        for query in [
+            DELETE_AUTHOR_STATS_QUERY,
+            DELETE_DIRECTORY_OWNERS_QUERY,
+            DELETE_AUTHORS_QUERY,
+        ] {
+            sqlx::query(query)
+                .bind(self.id.to_string())
+                .bind(self.email_digest.to_string())
+                .bind(self.org.to_string())
+                .execute(&mut txn)
+                .await?;
+        }
+        sqlx::query(DELETE_STATUS_CASCADE_QUERY)
+            .bind(schema::USERS_TABLENAME)
+            .bind(self.id.to_string())
+            .execute(&mut txn)
+            .await?;
+        sqlx::query(DELETE_OUTBOX_QUERY)
+            .bind(self.id.to_string())
+            .execute(&mut txn)
+            .await?;
+        sqlx::query(DELETE_INVITATIONS_QUERY)
+            .bind(self.org.to_string())
+            .bind(self.email_digest.to_string())
+            .execute(&mut txn)
+            .await?;
+
+        let receipt_id = audit::insert(
+            &mut txn,
+            audit::USER_ERASE,
+            schema::USERS_TABLENAME,
+            &self.id,
+        )
+        .await?;
+
+        txn.commit().await?;
+
+        // the update to the db was a success, set the internal fields
+        self.api_secret = api_secret_;
+        self.api_secret_digest = api_secret_digest_;
+        self.display_name = display_name_;
+        self.display_name_digest = display_name_digest_;
+        self.email = email_;
+        self.email_digest = email_digest_;
+        self.password = password_;
+        self.role = role_;
+        self.meta.status = status_;
+
+        Ok(ErasureReceipt {
+            id: receipt_id,
+            user: self.id,
+            org: self.org,
+            ctime: chrono::Utc::now(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grokloc::app::admin::org::Org;
+    use crate::grokloc::app::state;
+
+    #[test]
+    fn user_encrypted_test() -> Result<(), anyhow::Error> {
+        let key = crypt::rand_key();
+        let display_name = safe::VarChar::rand();
+        let email = safe::VarChar::rand();
+        let org = Uuid::new_v4();
+        let password = safe::VarChar::new(&crypt::kdf(&crypt::rand_hex(), crypt::MIN_KDF_ROUNDS))?;
+        let user = User::encrypted(&display_name, &email, &org, &password, &key)?;
+
+        let email_digest = crypt::sha256_hex(&email.to_string());
+        let iv = crypt::iv(&email_digest);
+
+        let decrypted_api_secret = crypt::decrypt(&key, &iv, &user.api_secret.to_string())?;
+
+        assert_eq!(
+            crypt::sha256_hex(&decrypted_api_secret),
+            user.api_secret_digest.to_string(),
+            "api_secret_digest"
+        );
+
+        let decrypted_display_name = crypt::decrypt(&key, &iv, &user.display_name.to_string())?;
+        assert_eq!(
+            &decrypted_display_name,
+            &display_name.to_string(),
+            "display name"
+        );
+
+        assert_eq!(
+            crypt::sha256_hex(&display_name.to_string()),
+            user.display_name_digest.to_string(),
             "display_name_digest"
         );
 
@@ -406,6 +1206,19 @@ mod tests {
         Ok(())
     }
 
+    /// active_org is an Active org to insert beside users in a bare db
+    fn active_org(id: &Uuid, owner: &Uuid) -> Org {
+        Org {
+            id: *id,
+            name: safe::VarChar::rand(),
+            owner: *owner,
+            meta: models::Meta {
+                status: models::Status::Active,
+                ..Default::default()
+            },
+        }
+    }
+
     #[tokio::test]
     async fn user_update_status_test() -> Result<(), anyhow::Error> {
         // build the user
@@ -416,6 +1229,17 @@ mod tests {
         let password = safe::VarChar::new(&crypt::kdf(&crypt::rand_hex(), crypt::MIN_KDF_ROUNDS))?;
         let mut user = User::encrypted(&display_name, &email, &org, &password, &key)?;
 
+        // build an actor permitted to manage users in the org
+        let mut actor = User::encrypted(
+            &safe::VarChar::rand(),
+            &safe::VarChar::rand(),
+            &org,
+            &password,
+            &key,
+        )?;
+        actor.role = role::Role::Admin;
+        actor.meta.status = models::Status::Active;
+
         // create the db
         let pool: sqlx::SqlitePool = sqlx::sqlite::SqlitePoolOptions::new()
             .connect("sqlite::memory:")
@@ -424,14 +1248,17 @@ mod tests {
             .execute(&pool)
             .await?;
 
-        // insert the user
+        // insert the org, the user and the actor
         let mut txn = pool.begin().await?;
+        active_org(&org, &actor.id).insert(&mut txn).await?;
         user.insert(&mut txn).await?;
+        actor.insert(&mut txn).await?;
         // implicit rollback
         txn.commit().await?;
 
         // update the status of the user
-        user.update_status(&pool, models::Status::Active).await?;
+        user.update_status(&pool, &actor.id, models::Status::Active)
+            .await?;
 
         // read that user
         let user_read = match User::read(&pool, &user.id, &key).await {
@@ -442,6 +1269,59 @@ mod tests {
         assert_eq!(user.id, user_read.id);
         assert_eq!(models::Status::Active, user_read.meta.status);
 
+        // only erase deletes
+        match user
+            .update_status(&pool, &actor.id, models::Status::Deleted)
+            .await
+        {
+            Ok(_) => unreachable!(),
+            Err(e) => assert_eq!(Some(&models::Err::Deleted), e.downcast_ref::<models::Err>()),
+        };
+        assert_eq!(models::Status::Active, user.meta.status);
+
+        // an erased user never changes, even through a stale copy
+        let mut stale = user.clone();
+        user.erase(&pool, &actor.id).await?;
+        match user
+            .update_status(&pool, &actor.id, models::Status::Active)
+            .await
+        {
+            Ok(_) => unreachable!(),
+            Err(e) => assert_eq!(Some(&models::Err::Deleted), e.downcast_ref::<models::Err>()),
+        };
+        match stale
+            .update_status(&pool, &actor.id, models::Status::Inactive)
+            .await
+        {
+            Ok(_) => unreachable!(),
+            Err(e) => assert!(db::anyhow_sqlx_row_not_found(&e)),
+        };
+        match stale
+            .update_role(&pool, &actor.id, role::Role::ReadOnly)
+            .await
+        {
+            Ok(_) => unreachable!(),
+            Err(e) => assert!(db::anyhow_sqlx_row_not_found(&e)),
+        };
+        match stale
+            .update_display_name(&pool, &actor.id, &safe::VarChar::rand(), &key)
+            .await
+        {
+            Ok(_) => unreachable!(),
+            Err(e) => assert!(db::anyhow_sqlx_row_not_found(&e)),
+        };
+        match stale
+            .update_email(&pool, &actor.id, &safe::VarChar::rand(), false, &key)
+            .await
+        {
+            Ok(_) => unreachable!(),
+            Err(e) => assert!(db::anyhow_sqlx_row_not_found(&e)),
+        };
+        match stale.rotate_api_secret(&pool, &actor.id, None, &key).await {
+            Ok(_) => unreachable!(),
+            Err(e) => assert!(db::anyhow_sqlx_row_not_found(&e)),
+        };
+
         Ok(())
     }
 
@@ -455,6 +1335,17 @@ mod tests {
         let password = safe::VarChar::new(&crypt::kdf(&crypt::rand_hex(), crypt::MIN_KDF_ROUNDS))?;
         let mut user = User::encrypted(&display_name, &email, &org, &password, &key)?;
 
+        // build an actor permitted to manage users in the org
+        let mut actor = User::encrypted(
+            &safe::VarChar::rand(),
+            &safe::VarChar::rand(),
+            &org,
+            &password,
+            &key,
+        )?;
+        actor.role = role::Role::Admin;
+        actor.meta.status = models::Status::Active;
+
         // create the db
         let pool: sqlx::SqlitePool = sqlx::sqlite::SqlitePoolOptions::new()
             .connect("sqlite::memory:")
@@ -463,11 +1354,802 @@ mod tests {
             .execute(&pool)
             .await?;
 
-        match user.update_status(&pool, models::Status::Active).await {
+        // insert only the org and the actor
+        let mut txn = pool.begin().await?;
+        active_org(&org, &actor.id).insert(&mut txn).await?;
+        actor.insert(&mut txn).await?;
+        txn.commit().await?;
+
+        match user
+            .update_status(&pool, &actor.id, models::Status::Active)
+            .await
+        {
             Ok(_) => unreachable!(),
             Err(e) => assert!(db::anyhow_sqlx_row_not_found(&e)),
         };
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn user_create_test() -> Result<(), anyhow::Error> {
+        let app = state::unit().await?;
+        let display_name = safe::VarChar::rand();
+        let email = safe::VarChar::rand();
+        let password = safe::VarChar::new(&crypt::kdf(&crypt::rand_hex(), crypt::MIN_KDF_ROUNDS))?;
+
+        let user = User::create(
+            &app.master_pool,
+            &display_name,
+            &email,
+            &app.root_org.id,
+            &password,
+            &app.key,
+        )
+        .await?;
+
+        let user_read = User::read(&app.master_pool, &user.id, &app.key).await?;
+        assert_eq!(display_name, user_read.display_name);
+        assert_eq!(email, user_read.email);
+        assert_eq!(app.root_org.id, user_read.org);
+        assert_eq!(models::Status::Unconfirmed, user_read.meta.status);
+
+        let audit_count: i64 = sqlx::query_scalar(
+            "select count(*) from audit where source = ? and source_id = ? and code = ?",
+        )
+        .bind(schema::USERS_TABLENAME)
+        .bind(user.id.to_string())
+        .bind(audit::USER_INSERT)
+        .fetch_one(&app.master_pool)
+        .await?;
+        assert_eq!(1, audit_count);
+
+        // same email in the same org
+        match User::create(
+            &app.master_pool,
+            &safe::VarChar::rand(),
+            &email,
+            &app.root_org.id,
+            &password,
+            &app.key,
+        )
+        .await
+        {
+            Ok(_) => unreachable!(),
+            Err(e) => assert!(matches!(
+                e.downcast_ref::<db::Err>(),
+                Some(db::Err::UserViolation)
+            )),
+        };
+
+        // ...including the same email in another case
+        match User::create(
+            &app.master_pool,
+            &safe::VarChar::rand(),
+            &safe::VarChar::new(&email.to_string().to_uppercase())?,
+            &app.root_org.id,
+            &password,
+            &app.key,
+        )
+        .await
+        {
+            Ok(_) => unreachable!(),
+            Err(e) => assert!(matches!(
+                e.downcast_ref::<db::Err>(),
+                Some(db::Err::UserViolation)
+            )),
+        };
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn user_create_org_violation_test() -> Result<(), anyhow::Error> {
+        let app = state::unit().await?;
+        let password = safe::VarChar::new(&crypt::kdf(&crypt::rand_hex(), crypt::MIN_KDF_ROUNDS))?;
+
+        // missing org
+        match User::create(
+            &app.master_pool,
+            &safe::VarChar::rand(),
+            &safe::VarChar::rand(),
+            &Uuid::new_v4(),
+            &password,
+            &app.key,
+        )
+        .await
+        {
+            Ok(_) => unreachable!(),
+            Err(e) => assert!(matches!(
+                e.downcast_ref::<db::Err>(),
+                Some(db::Err::OrgViolation)
+            )),
+        };
+
+        // inactive org
+        let mut org = Org::read(&app.master_pool, &app.root_org.id).await?;
+        org.update_status(
+            &app.master_pool,
+            &app.root_user.id,
+            models::Status::Inactive,
+        )
+        .await?;
+        match User::create(
+            &app.master_pool,
+            &safe::VarChar::rand(),
+            &safe::VarChar::rand(),
+            &org.id,
+            &password,
+            &app.key,
+        )
+        .await
+        {
+            Ok(_) => unreachable!(),
+            Err(e) => assert!(matches!(
+                e.downcast_ref::<db::Err>(),
+                Some(db::Err::OrgViolation)
+            )),
+        };
+
+        // nothing was inserted
+        let user_count: i64 = sqlx::query_scalar("select count(*) from users")
+            .fetch_one(&app.master_pool)
+            .await?;
+        assert_eq!(1, user_count);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn user_update_email_test() -> Result<(), anyhow::Error> {
+        let app = state::unit().await?;
+        let mut user = User::read(&app.master_pool, &app.root_user.id, &app.key).await?;
+        let old_email = user.email.clone();
+        let new_email = safe::VarChar::rand();
+
+        user.update_email(
+            &app.master_pool,
+            &app.root_user.id,
+            &new_email,
+            true,
+            &app.key,
+        )
+        .await?;
+        assert_eq!(new_email, user.email);
+        assert_eq!(models::Status::Unconfirmed, user.meta.status);
+
+        // all PII reads back under the new salt
+        let user_read = User::read(&app.master_pool, &user.id, &app.key).await?;
+        assert_eq!(new_email, user_read.email);
+        assert_eq!(user.email_digest, user_read.email_digest);
+        assert_eq!(user.api_secret, user_read.api_secret);
+        assert_eq!(user.display_name, user_read.display_name);
+        assert_eq!(models::Status::Unconfirmed, user_read.meta.status);
+
+        // the old address was notified
+        let notified: i64 = sqlx::query_scalar(
+            "select count(*) from outbox where code = ? and recipient_digest = ?",
+        )
+        .bind(outbox::EMAIL_CHANGED)
+        .bind(crypt::sha256_hex(&old_email.to_string()))
+        .fetch_one(&app.master_pool)
+        .await?;
+        assert_eq!(1, notified);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn user_update_email_keep_status_test() -> Result<(), anyhow::Error> {
+        let app = state::unit().await?;
+
+        // without reconfirm the stored status is kept, even under a stale copy
+        let password = safe::VarChar::new(&crypt::kdf(&crypt::rand_hex(), crypt::MIN_KDF_ROUNDS))?;
+        let mut member = User::create(
+            &app.master_pool,
+            &safe::VarChar::rand(),
+            &safe::VarChar::rand(),
+            &app.root_org.id,
+            &password,
+            &app.key,
+        )
+        .await?;
+        member
+            .update_status(&app.master_pool, &app.root_user.id, models::Status::Active)
+            .await?;
+        let mut stale = member.clone();
+        member
+            .update_status(
+                &app.master_pool,
+                &app.root_user.id,
+                models::Status::Inactive,
+            )
+            .await?;
+        stale
+            .update_email(
+                &app.master_pool,
+                &app.root_user.id,
+                &safe::VarChar::rand(),
+                false,
+                &app.key,
+            )
+            .await?;
+        let member_read = User::read(&app.master_pool, &member.id, &app.key).await?;
+        assert_eq!(models::Status::Inactive, member_read.meta.status);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn user_update_email_duplicate_test() -> Result<(), anyhow::Error> {
+        let app = state::unit().await?;
+        let password = safe::VarChar::new(&crypt::kdf(&crypt::rand_hex(), crypt::MIN_KDF_ROUNDS))?;
+        let other = User::create(
+            &app.master_pool,
+            &safe::VarChar::rand(),
+            &safe::VarChar::rand(),
+            &app.root_org.id,
+            &password,
+            &app.key,
+        )
+        .await?;
+        let other = User::read(&app.master_pool, &other.id, &app.key).await?;
+        let mut user = User::read(&app.master_pool, &app.root_user.id, &app.key).await?;
+        let email = user.email.clone();
+
+        // the email of another user, in any case
+        for other_email in [
+            other.email.clone(),
+            safe::VarChar::new(&other.email.to_string().to_uppercase())?,
+        ] {
+            match user
+                .update_email(
+                    &app.master_pool,
+                    &app.root_user.id,
+                    &other_email,
+                    false,
+                    &app.key,
+                )
+                .await
+            {
+                Ok(_) => unreachable!(),
+                Err(e) => assert!(matches!(
+                    e.downcast_ref::<db::Err>(),
+                    Some(db::Err::UserViolation)
+                )),
+            };
+        }
+
+        // nothing changed
+        assert_eq!(email, user.email);
+        let user_read = User::read(&app.master_pool, &user.id, &app.key).await?;
+        assert_eq!(email, user_read.email);
+        assert_eq!(models::Status::Active, user_read.meta.status);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn user_rotate_api_secret_test() -> Result<(), anyhow::Error> {
+        let app = state::unit().await?;
+        let mut user = User::read(&app.master_pool, &app.root_user.id, &app.key).await?;
+        let first_secret = user.api_secret.to_string();
+        assert_eq!(
+            user.id,
+            User::authenticate_api_secret(&app.master_pool, &first_secret).await?
+        );
+
+        // rotate with a grace period, the previous secret is still accepted
+        user.rotate_api_secret(
+            &app.master_pool,
+            &app.root_user.id,
+            Some(chrono::Duration::hours(1)),
+            &app.key,
+        )
+        .await?;
+        let second_secret = user.api_secret.to_string();
+        assert_ne!(first_secret, second_secret);
+        assert_eq!(
+            crypt::sha256_hex(&second_secret),
+            user.api_secret_digest.to_string()
+        );
+        let user_read = User::read(&app.master_pool, &user.id, &app.key).await?;
+        assert_eq!(user.api_secret, user_read.api_secret);
+        assert_eq!(user.api_secret_digest, user_read.api_secret_digest);
+        assert_eq!(
+            user.id,
+            User::authenticate_api_secret(&app.master_pool, &second_secret).await?
+        );
+        assert_eq!(
+            user.id,
+            User::authenticate_api_secret(&app.master_pool, &first_secret).await?
+        );
+
+        // rotate with no grace period, the previous secret is rejected, as
+        // is the first secret still within its grace period
+        user.rotate_api_secret(&app.master_pool, &app.root_user.id, None, &app.key)
+            .await?;
+        for secret in [&second_secret, &first_secret] {
+            match User::authenticate_api_secret(&app.master_pool, secret).await {
+                Ok(_) => unreachable!(),
+                Err(e) => assert!(db::anyhow_sqlx_row_not_found(&e)),
+            };
+        }
+
+        // the audit log records each rotation
+        let audit_count: i64 =
+            sqlx::query_scalar("select count(*) from audit where source_id = ? and code = ?")
+                .bind(user.id.to_string())
+                .bind(audit::USER_API_SECRET)
+                .fetch_one(&app.master_pool)
+                .await?;
+        assert_eq!(2, audit_count);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn user_rotate_api_secret_grace_expired_test() -> Result<(), anyhow::Error> {
+        let app = state::unit().await?;
+        let mut user = User::read(&app.master_pool, &app.root_user.id, &app.key).await?;
+        let first_secret = user.api_secret.to_string();
+
+        // a grace period that has already elapsed
+        user.rotate_api_secret(
+            &app.master_pool,
+            &app.root_user.id,
+            Some(chrono::Duration::seconds(-1)),
+            &app.key,
+        )
+        .await?;
+        match User::authenticate_api_secret(&app.master_pool, &first_secret).await {
+            Ok(_) => unreachable!(),
+            Err(e) => assert!(db::anyhow_sqlx_row_not_found(&e)),
+        };
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn user_update_status_forbidden_test() -> Result<(), anyhow::Error> {
+        let app = state::unit().await?;
+        let password = safe::VarChar::new(&crypt::kdf(&crypt::rand_hex(), crypt::MIN_KDF_ROUNDS))?;
+        let mut member = User::create(
+            &app.master_pool,
+            &safe::VarChar::rand(),
+            &safe::VarChar::rand(),
+            &app.root_org.id,
+            &password,
+            &app.key,
+        )
+        .await?;
+        member
+            .update_status(&app.master_pool, &app.root_user.id, models::Status::Active)
+            .await?;
+
+        // a member cannot change the status of other users
+        let mut owner = app.root_user.clone();
+        match owner
+            .update_status(&app.master_pool, &member.id, models::Status::Inactive)
+            .await
+        {
+            Ok(_) => unreachable!(),
+            Err(e) => assert_eq!(Some(&role::Err::Forbidden), e.downcast_ref::<role::Err>()),
+        };
+
+        // the owner must remain Active
+        match owner
+            .update_status(
+                &app.master_pool,
+                &app.root_user.id,
+                models::Status::Inactive,
+            )
+            .await
+        {
+            Ok(_) => unreachable!(),
+            Err(e) => assert_eq!(
+                Some(&role::Err::OwnerInvariant),
+                e.downcast_ref::<role::Err>()
+            ),
+        };
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn user_update_display_name_test() -> Result<(), anyhow::Error> {
+        let app = state::unit().await?;
+        let password = safe::VarChar::new(&crypt::kdf(&crypt::rand_hex(), crypt::MIN_KDF_ROUNDS))?;
+        let member = User::create(
+            &app.master_pool,
+            &safe::VarChar::rand(),
+            &safe::VarChar::rand(),
+            &app.root_org.id,
+            &password,
+            &app.key,
+        )
+        .await?;
+        let mut member = User::read(&app.master_pool, &member.id, &app.key).await?;
+        let member_id = member.id;
+
+        // an Unconfirmed user may not act on itself
+        match member
+            .update_display_name(
+                &app.master_pool,
+                &member_id,
+                &safe::VarChar::rand(),
+                &app.key,
+            )
+            .await
+        {
+            Ok(_) => unreachable!(),
+            Err(e) => assert_eq!(Some(&role::Err::Forbidden), e.downcast_ref::<role::Err>()),
+        };
+        member
+            .update_status(&app.master_pool, &app.root_user.id, models::Status::Active)
+            .await?;
+
+        // a user may change its own display name
+        let display_name = safe::VarChar::rand();
+        member
+            .update_display_name(&app.master_pool, &member_id, &display_name, &app.key)
+            .await?;
+        let member_read = User::read(&app.master_pool, &member.id, &app.key).await?;
+        assert_eq!(display_name, member_read.display_name);
+        assert_eq!(member.display_name_digest, member_read.display_name_digest);
+
+        // ...but not that of other users
+        let mut owner = User::read(&app.master_pool, &app.root_user.id, &app.key).await?;
+        match owner
+            .update_display_name(
+                &app.master_pool,
+                &member.id,
+                &safe::VarChar::rand(),
+                &app.key,
+            )
+            .await
+        {
+            Ok(_) => unreachable!(),
+            Err(e) => assert_eq!(Some(&role::Err::Forbidden), e.downcast_ref::<role::Err>()),
+        };
+
+        // a deactivated user may not act on itself, nor escape deactivation
+        // by reconfirming a new email
+        member
+            .update_status(
+                &app.master_pool,
+                &app.root_user.id,
+                models::Status::Inactive,
+            )
+            .await?;
+        match member
+            .update_display_name(
+                &app.master_pool,
+                &member_id,
+                &safe::VarChar::rand(),
+                &app.key,
+            )
+            .await
+        {
+            Ok(_) => unreachable!(),
+            Err(e) => assert_eq!(Some(&role::Err::Forbidden), e.downcast_ref::<role::Err>()),
+        };
+        match member
+            .update_email(
+                &app.master_pool,
+                &member_id,
+                &safe::VarChar::rand(),
+                true,
+                &app.key,
+            )
+            .await
+        {
+            Ok(_) => unreachable!(),
+            Err(e) => assert_eq!(Some(&role::Err::Forbidden), e.downcast_ref::<role::Err>()),
+        };
+        match member
+            .rotate_api_secret(&app.master_pool, &member_id, None, &app.key)
+            .await
+        {
+            Ok(_) => unreachable!(),
+            Err(e) => assert_eq!(Some(&role::Err::Forbidden), e.downcast_ref::<role::Err>()),
+        };
+        let member_read = User::read(&app.master_pool, &member.id, &app.key).await?;
+        assert_eq!(models::Status::Inactive, member_read.meta.status);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn user_update_role_test() -> Result<(), anyhow::Error> {
+        let app = state::unit().await?;
+        let password = safe::VarChar::new(&crypt::kdf(&crypt::rand_hex(), crypt::MIN_KDF_ROUNDS))?;
+        let mut member = User::create(
+            &app.master_pool,
+            &safe::VarChar::rand(),
+            &safe::VarChar::rand(),
+            &app.root_org.id,
+            &password,
+            &app.key,
+        )
+        .await?;
+        assert_eq!(role::Role::Member, member.role);
+
+        member
+            .update_role(&app.master_pool, &app.root_user.id, role::Role::ReadOnly)
+            .await?;
+        let member_read = User::read(&app.master_pool, &member.id, &app.key).await?;
+        assert_eq!(role::Role::ReadOnly, member_read.role);
+
+        // there can be only one owner
+        match member
+            .update_role(&app.master_pool, &app.root_user.id, role::Role::Owner)
+            .await
+        {
+            Ok(_) => unreachable!(),
+            Err(e) => assert_eq!(
+                Some(&role::Err::OwnerInvariant),
+                e.downcast_ref::<role::Err>()
+            ),
+        };
+        let mut owner = app.root_user.clone();
+        match owner
+            .update_role(&app.master_pool, &app.root_user.id, role::Role::Admin)
+            .await
+        {
+            Ok(_) => unreachable!(),
+            Err(e) => assert_eq!(
+                Some(&role::Err::OwnerInvariant),
+                e.downcast_ref::<role::Err>()
+            ),
+        };
+
+        // the db also rejects a second owner
+        let mut txn = app.master_pool.begin().await?;
+        match sqlx::query(UPDATE_ROLE_QUERY)
+            .bind(role::Role::Owner.to_int())
+            .bind(member.id.to_string())
+            .bind(models::Status::Deleted.to_int())
+            .execute(&mut txn)
+            .await
+        {
+            Ok(_) => unreachable!(),
+            Err(e) => assert!(db::sqlx_duplicate(&e)),
+        };
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn user_list_by_org_test() -> Result<(), anyhow::Error> {
+        let app = state::unit().await?;
+        let password = safe::VarChar::new(&crypt::kdf(&crypt::rand_hex(), crypt::MIN_KDF_ROUNDS))?;
+        let mut emails = vec![User::read(&app.master_pool, &app.root_user.id, &app.key)
+            .await?
+            .email
+            .to_string()];
+        for _ in 0..4 {
+            let email = safe::VarChar::rand();
+            User::create(
+                &app.master_pool,
+                &safe::VarChar::rand(),
+                &email,
+                &app.root_org.id,
+                &password,
+                &app.key,
+            )
+            .await?;
+            emails.push(email.to_string());
+        }
+
+        // users of another org are not listed
+        Org::create(
+            &app.master_pool,
+            &safe::VarChar::rand(),
+            &safe::VarChar::rand(),
+            &safe::VarChar::rand(),
+            &password,
+            &app.key,
+        )
+        .await?;
+
+        let mut listed = Vec::new();
+        let mut cursor: Option<models::Cursor> = None;
+        loop {
+            let (page, next) = User::list_by_org(
+                &app.master_pool,
+                &app.root_org.id,
+                None,
+                cursor.as_ref(),
+                2,
+                &app.key,
+            )
+            .await?;
+            for profile in page.iter() {
+                let user_read = User::read(&app.master_pool, &profile.id, &app.key).await?;
+                assert_eq!(user_read.display_name, profile.display_name);
+                assert_eq!(user_read.email, profile.email);
+            }
+            listed.extend(page.iter().map(|v| v.email.to_string()));
+            match next {
+                Some(v) => cursor = Some(v),
+                None => break,
+            }
+        }
+        listed.sort();
+        emails.sort();
+        assert_eq!(emails, listed);
+
+        // status filter
+        let (page, next) = User::list_by_org(
+            &app.master_pool,
+            &app.root_org.id,
+            Some(models::Status::Active),
+            None,
+            10,
+            &app.key,
+        )
+        .await?;
+        assert_eq!(1, page.len());
+        assert_eq!(app.root_user.id, page[0].id);
+        assert_eq!(role::Role::Owner, page[0].role);
+        assert!(next.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn user_list_summaries_by_org_test() -> Result<(), anyhow::Error> {
+        let app = state::unit().await?;
+        let password = safe::VarChar::new(&crypt::kdf(&crypt::rand_hex(), crypt::MIN_KDF_ROUNDS))?;
+        for _ in 0..2 {
+            User::create(
+                &app.master_pool,
+                &safe::VarChar::rand(),
+                &safe::VarChar::rand(),
+                &app.root_org.id,
+                &password,
+                &app.key,
+            )
+            .await?;
+        }
+
+        let (page, next) = User::list_summaries_by_org(
+            &app.master_pool,
+            &app.root_org.id,
+            Some(models::Status::Unconfirmed),
+            None,
+            1,
+        )
+        .await?;
+        assert_eq!(1, page.len());
+        assert_eq!(models::Status::Unconfirmed, page[0].meta.status);
+        assert!(next.is_some());
+
+        let (page, next) = User::list_summaries_by_org(
+            &app.master_pool,
+            &app.root_org.id,
+            Some(models::Status::Unconfirmed),
+            next.as_ref(),
+            1,
+        )
+        .await?;
+        assert_eq!(1, page.len());
+        assert!(next.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn user_erase_test() -> Result<(), anyhow::Error> {
+        let app = state::unit().await?;
+        let password = safe::VarChar::new(&crypt::kdf(&crypt::rand_hex(), crypt::MIN_KDF_ROUNDS))?;
+        let user = User::create(
+            &app.master_pool,
+            &safe::VarChar::rand(),
+            &safe::VarChar::rand(),
+            &app.root_org.id,
+            &password,
+            &app.key,
+        )
+        .await?;
+        let mut user = User::read(&app.master_pool, &user.id, &app.key).await?;
+        let email = user.email.clone();
+        let user_email_digest = user.email_digest.to_string();
+        let api_secret = user.api_secret.to_string();
+
+        // leave a pending notification to the user
+        let mut txn = app.master_pool.begin().await?;
+        outbox::insert(&mut txn, outbox::EMAIL_CHANGED, &email, &user.id, &app.key).await?;
+        txn.commit().await?;
+
+        // change the email, queueing a notification to the previous address
+        let new_email = safe::VarChar::rand();
+        user.update_email(
+            &app.master_pool,
+            &app.root_user.id,
+            &new_email,
+            false,
+            &app.key,
+        )
+        .await?;
+
+        let receipt = user.erase(&app.master_pool, &app.root_user.id).await?;
+        assert_eq!(user.id, receipt.user);
+        assert_eq!(app.root_org.id, receipt.org);
+        assert_eq!(models::Status::Deleted, user.meta.status);
+
+        // the receipt is the audit entry
+        let (code, source_id): (i64, String) =
+            sqlx::query_as("select code, source_id from audit where id = ?")
+                .bind(receipt.id.to_string())
+                .fetch_one(&app.master_pool)
+                .await?;
+        assert_eq!(audit::USER_ERASE, code);
+        assert_eq!(user.id.to_string(), source_id);
+
+        // the tombstone remains, but nothing of the PII
+        let (stored_email, stored_email_digest, status): (String, String, i64) =
+            sqlx::query_as("select email, email_digest, status from users where id = ?")
+                .bind(user.id.to_string())
+                .fetch_one(&app.master_pool)
+                .await?;
+        assert_eq!(models::Status::Deleted.to_int(), status);
+        assert_ne!(crypt::sha256_hex(&email.to_string()), stored_email_digest);
+        assert!(
+            crypt::decrypt(&app.key, &crypt::iv(&stored_email_digest), &stored_email)
+                .map(|v| v != email.to_string())
+                .unwrap_or(true)
+        );
+        let audit_count: i64 = sqlx::query_scalar("select count(*) from audit where source_id = ?")
+            .bind(user.id.to_string())
+            .fetch_one(&app.master_pool)
+            .await?;
+        assert_eq!(3, audit_count);
+        let outbox_count: i64 = sqlx::query_scalar(
+            "select count(*) from outbox where recipient_digest in (?, ?) or user = ?",
+        )
+        .bind(user_email_digest)
+        .bind(crypt::sha256_hex(&new_email.to_string()))
+        .bind(user.id.to_string())
+        .fetch_one(&app.master_pool)
+        .await?;
+        assert_eq!(0, outbox_count);
+
+        // the user can no longer be read, listed or authenticated
+        match User::read(&app.master_pool, &user.id, &app.key).await {
+            Ok(_) => unreachable!(),
+            Err(e) => assert!(db::anyhow_sqlx_row_not_found(&e)),
+        };
+        let (page, _) =
+            User::list_by_org(&app.master_pool, &app.root_org.id, None, None, 10, &app.key).await?;
+        assert!(page.iter().all(|v| v.id != user.id));
+        match User::authenticate_api_secret(&app.master_pool, &api_secret).await {
+            Ok(_) => unreachable!(),
+            Err(e) => assert!(db::anyhow_sqlx_row_not_found(&e)),
+        };
+
+        // the email may be reused
+        User::create(
+            &app.master_pool,
+            &safe::VarChar::rand(),
+            &email,
+            &app.root_org.id,
+            &password,
+            &app.key,
+        )
+        .await?;
+
+        // the owner cannot be erased
+        let mut owner = app.root_user.clone();
+        match owner.erase(&app.master_pool, &app.root_user.id).await {
+            Ok(_) => unreachable!(),
+            Err(e) => assert_eq!(
+                Some(&role::Err::OwnerInvariant),
+                e.downcast_ref::<role::Err>()
+            ),
+        };
+
+        Ok(())
+    }
 }
@@ -0,0 +1,943 @@
+//! analysis counts the lines of code in repository commits
+//!
+//! counts are cached per git blob and language definition, so analysing a
+//! commit only reads and counts the files that changed since any earlier
+//! analysis
+//!
+//! files excluded by ignore lists or heuristics (see exclude) are counted
+//! and stored apart from the included totals, which are also broken down
+//! by subproject and top-level directory (see breakdown)
+//!
+//! functions and estimated complexity (see complexity) are cached and
+//! summed per language beside the line counts
+pub mod authors;
+pub mod breakdown;
+pub mod churn;
+pub mod diff;
+pub mod history;
+pub mod ownership;
+
+use crate::grokloc::app::admin::language;
+use crate::grokloc::app::admin::repository::Repository;
+use crate::grokloc::app::admin::settings;
+use crate::grokloc::app::admin::subproject;
+use crate::grokloc::complexity;
+use crate::grokloc::exclude;
+use crate::grokloc::git;
+use crate::grokloc::loc;
+use anyhow;
+use sqlx;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use uuid::Uuid;
+
+pub const SCHEMA_VERSION: i8 = 0;
+
+/// SELECT_BLOBS_QUERY is completed with one placeholder per blob
+pub const SELECT_BLOBS_QUERY: &str = r#"
+select blob, definition, code, comment, blank, functions, complexity, generated
+from blob_counts
+where blob in
+"#;
+
+/// SELECT_INTERPRETERS_QUERY is completed with one placeholder per blob
+pub const SELECT_INTERPRETERS_QUERY: &str = r#"
+select blob, interpreter
+from blob_interpreters
+where blob in
+"#;
+
+pub const INSERT_INTERPRETER_QUERY: &str = r#"
+insert into blob_interpreters
+(id,
+ blob,
+ interpreter,
+ schema_version)
+values
+(?,?,?,?)
+on conflict (blob) do nothing
+"#;
+
+/// BLOB_BATCH_SIZE is the number of blobs looked up per query, under the
+/// SQLite limit on bound parameters, and read from git at once
+pub const BLOB_BATCH_SIZE: usize = 500;
+
+pub const INSERT_BLOB_QUERY: &str = r#"
+insert into blob_counts
+(id,
+ blob,
+ definition,
+ code,
+ comment,
+ blank,
+ functions,
+ complexity,
+ generated,
+ schema_version)
+values
+(?,?,?,?,?,?,?,?,?,?)
+on conflict (blob, definition) do nothing
+"#;
+
+pub const DELETE_COMMIT_QUERY: &str = r#"
+delete from commit_counts where repository = ? and commit_id = ?
+"#;
+
+pub const INSERT_COMMIT_QUERY: &str = r#"
+insert into commit_counts
+(id,
+ repository,
+ commit_id,
+ language,
+ exclusion,
+ files,
+ code,
+ comment,
+ blank,
+ schema_version)
+values
+(?,?,?,?,?,?,?,?,?,?)
+"#;
+
+pub const SELECT_COMMIT_QUERY: &str = r#"
+select language, files, code, comment, blank
+from commit_counts
+where repository = ? and commit_id = ? and exclusion = 0
+order by language
+"#;
+
+pub const SELECT_EXCLUDED_QUERY: &str = r#"
+select exclusion, language, files, code, comment, blank
+from commit_counts
+where repository = ? and commit_id = ? and exclusion != 0
+order by exclusion, language
+"#;
+
+pub const DELETE_COMPLEXITY_QUERY: &str = r#"
+delete from commit_complexity where repository = ? and commit_id = ?
+"#;
+
+pub const INSERT_COMPLEXITY_QUERY: &str = r#"
+insert into commit_complexity
+(id,
+ repository,
+ commit_id,
+ language,
+ files,
+ functions,
+ complexity,
+ schema_version)
+values
+(?,?,?,?,?,?,?,?)
+"#;
+
+pub const SELECT_COMPLEXITY_QUERY: &str = r#"
+select language, files, functions, complexity
+from commit_complexity
+where repository = ? and commit_id = ?
+order by language
+"#;
+
+/// LanguageTotals are the summed counts of the files of a language in a commit
+#[derive(Clone, Debug, PartialEq)]
+pub struct LanguageTotals {
+    pub language: String,
+    pub files: i64,
+    pub counts: loc::Counts,
+}
+
+/// ExcludedTotals are the summed counts of the files of a language in a
+/// commit that are excluded for the same reason
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExcludedTotals {
+    pub exclusion: exclude::Exclusion,
+    pub totals: LanguageTotals,
+}
+
+/// LanguageComplexity are the summed metrics of the files of a language
+/// in a commit whose complexity is estimated
+#[derive(Clone, Debug, PartialEq)]
+pub struct LanguageComplexity {
+    pub language: String,
+    pub files: i64,
+    pub metrics: complexity::Metrics,
+}
+
+/// Analysis is the result of analysing a commit
+#[derive(Clone, Debug)]
+#[allow(dead_code)]
+pub struct Analysis {
+    pub repository: Uuid,
+    pub commit: String,
+    /// ordered by language
+    pub totals: Vec<LanguageTotals>,
+    /// ordered by exclusion, then language
+    pub excluded: Vec<ExcludedTotals>,
+    /// ordered by language
+    pub complexity: Vec<LanguageComplexity>,
+    /// ordered by subproject, then language
+    pub subprojects: Vec<breakdown::GroupTotals>,
+    /// ordered by top-level directory, then language
+    pub directories: Vec<breakdown::GroupTotals>,
+    /// blobs read and counted by this analysis
+    pub counted: usize,
+    /// blobs whose counts were already cached
+    pub cached: usize,
+}
+
+/// FileCount is a counted file of a commit tree
+#[derive(Clone, Debug)]
+#[allow(dead_code)]
+pub struct FileCount {
+    pub path: String,
+    pub blob: String,
+    pub language: String,
+    pub counts: loc::Counts,
+    /// None for languages whose complexity is not estimated
+    pub metrics: Option<complexity::Metrics>,
+    pub exclusion: Option<exclude::Exclusion>,
+}
+
+/// ignore_patterns is the org ignore list of repository
+pub async fn ignore_patterns(
+    pool: &sqlx::SqlitePool,
+    repository: &Repository,
+) -> Result<String, anyhow::Error> {
+    match settings::get(pool, &repository.org, settings::Key::IgnorePatterns).await? {
+        settings::Value::Text(v) => Ok(v.to_string()),
+        _ => Err(settings::Err::BadValue.into()),
+    }
+}
+
+/// count_tree counts every file of commit in the clone at repo that is in
+/// one of languages, reading only blobs missing from the cache, which is
+/// then updated
+///
+/// files without an extension or known name are recognised by shebang,
+/// reading only the first line of blobs missing from the interpreter cache,
+/// which is then updated; files are classified against org_patterns followed by the
+/// ignore file of the tree, if any
+///
+/// returns the counted files with the number of blobs counted and cached
+pub async fn count_tree(
+    pool: &sqlx::SqlitePool,
+    repo: &Path,
+    commit: &str,
+    languages: &loc::Languages,
+    org_patterns: &str,
+) -> Result<(Vec<FileCount>, usize, usize), anyhow::Error> {
+    let entries = git::ls_tree(repo, commit).await?;
+
+    let ignore_file = match entries.iter().find(|v| v.path == exclude::IGNORE_FILE) {
+        Some(entry) => git::cat_blobs(repo, std::slice::from_ref(&entry.blob))
+            .await?
+            .remove(0),
+        None => Vec::new(),
+    };
+    let ignore = exclude::Ignore::new(&[org_patterns, &String::from_utf8_lossy(&ignore_file)])?;
+
+    let mut unknown: Vec<&str> = entries
+        .iter()
+        .filter(|v| !loc::has_extension(&v.path) && languages.detect(&v.path).is_none())
+        .map(|v| v.blob.as_str())
+        .collect();
+    unknown.sort_unstable();
+    unknown.dedup();
+    let interpreters = interpreters(pool, repo, &unknown).await?;
+
+    let mut files = Vec::with_capacity(entries.len());
+    let mut digests: HashMap<&str, String> = HashMap::new();
+    let mut keys: BTreeMap<(String, String), &loc::Language> = BTreeMap::new();
+    for entry in entries {
+        let language = match languages.detect(&entry.path).or_else(|| {
+            interpreters
+                .get(&entry.blob)
+                .and_then(|v| v.as_deref())
+                .and_then(|v| languages.detect_interpreter(v))
+        }) {
+            None => continue,
+            Some(v) => v,
+        };
+        let digest = digests
+            .entry(&language.name)
+            .or_insert_with(|| language.digest())
+            .clone();
+        let key = (entry.blob.clone(), digest);
+        keys.entry(key.clone()).or_insert(language);
+        files.push((entry, language, key));
+    }
+
+    // keys are ordered by blob, so repeats are adjacent
+    let mut blobs: Vec<&str> = keys.keys().map(|v| v.0.as_str()).collect();
+    blobs.dedup();
+    let mut cache = select_blobs(pool, &blobs).await?;
+    cache.retain(|key, _| keys.contains_key(key));
+    let cached = cache.len();
+    let missing: Vec<(&(String, String), &loc::Language)> = keys
+        .iter()
+        .filter(|(key, _)| !cache.contains_key(*key))
+        .map(|(key, language)| (key, *language))
+        .collect();
+
+    // blobs are read and counted a batch at a time, so that only a batch
+    // of contents is held at once however large the tree
+    for batch in missing.chunks(BLOB_BATCH_SIZE) {
+        let unread: Vec<String> = batch.iter().map(|v| v.0 .0.clone()).collect();
+        let read = git::cat_blobs(repo, &unread).await?;
+        let mut txn = pool.begin().await?;
+        for ((key, language), content) in batch.iter().zip(read) {
+            // binary files are cached as empty, so they are not read again
+            let counts = loc::count(language, &content).unwrap_or_default();
+            let metrics = complexity::measure(language, &content).unwrap_or_default();
+            let generated = !loc::is_binary(&content) && exclude::is_generated(&content);
+            sqlx::query(INSERT_BLOB_QUERY)
+                .bind(Uuid::new_v4().to_string())
+                .bind(&key.0)
+                .bind(&key.1)
+                .bind(counts.code)
+                .bind(counts.comment)
+                .bind(counts.blank)
+                .bind(metrics.functions)
+                .bind(metrics.complexity)
+                .bind(generated)
+                .bind(SCHEMA_VERSION)
+                .execute(&mut txn)
+                .await?;
+            cache.insert((*key).clone(), (counts, metrics, generated));
+        }
+        txn.commit().await?;
+    }
+
+    let file_counts = files
+        .into_iter()
+        .map(|(entry, language, key)| {
+            let (counts, metrics, generated) = cache[&key];
+            FileCount {
+                counts,
+                metrics: complexity::is_supported(&language.name).then_some(metrics),
+                exclusion: exclude::classify(&ignore, &entry.path, generated),
+                path: entry.path,
+                blob: entry.blob,
+                language: language.name.clone(),
+            }
+        })
+        .collect();
+    Ok((file_counts, missing.len(), cached))
+}
+
+/// interpreters finds the shebang interpreter of each of blobs, None if it
+/// has none, from the cache or else from the first line of the blob,
+/// caching it
+async fn interpreters(
+    pool: &sqlx::SqlitePool,
+    repo: &Path,
+    blobs: &[&str],
+) -> Result<HashMap<String, Option<String>>, anyhow::Error> {
+    let mut interpreters = HashMap::new();
+    for batch in blobs.chunks(BLOB_BATCH_SIZE) {
+        let query = format!(
+            "{} ({})",
+            SELECT_INTERPRETERS_QUERY,
+            vec!["?"; batch.len()].join(",")
+        );
+        let mut select = sqlx::query_as::<_, (String, Option<String>)>(&query);
+        for blob in batch {
+            select = select.bind(*blob);
+        }
+        interpreters.extend(select.fetch_all(pool).await?);
+    }
+
+    let unread: Vec<String> = blobs
+        .iter()
+        .filter(|v| !interpreters.contains_key(**v))
+        .map(|v| v.to_string())
+        .collect();
+    let lines = git::first_lines(repo, &unread).await?;
+    let mut txn = pool.begin().await?;
+    for (blob, line) in unread.into_iter().zip(lines) {
+        let interpreter = loc::shebang(&line);
+        sqlx::query(INSERT_INTERPRETER_QUERY)
+            .bind(Uuid::new_v4().to_string())
+            .bind(&blob)
+            .bind(&interpreter)
+            .bind(SCHEMA_VERSION)
+            .execute(&mut txn)
+            .await?;
+        interpreters.insert(blob, interpreter);
+    }
+    txn.commit().await?;
+    Ok(interpreters)
+}
+
+/// select_blobs reads the cached counts of blobs under every definition,
+/// BLOB_BATCH_SIZE blobs per query
+async fn select_blobs(
+    pool: &sqlx::SqlitePool,
+    blobs: &[&str],
+) -> Result<HashMap<(String, String), (loc::Counts, complexity::Metrics, bool)>, anyhow::Error> {
+    let mut cache = HashMap::new();
+    for batch in blobs.chunks(BLOB_BATCH_SIZE) {
+        let query = format!(
+            "{} ({})",
+            SELECT_BLOBS_QUERY,
+            vec!["?"; batch.len()].join(",")
+        );
+        let mut select =
+            sqlx::query_as::<_, (String, String, i64, i64, i64, i64, i64, bool)>(&query);
+        for blob in batch {
+            select = select.bind(*blob);
+        }
+        for (blob, definition, code, comment, blank, functions, complexity, generated) in
+            select.fetch_all(pool).await?
+        {
+            cache.insert(
+                (blob, definition),
+                (
+                    loc::Counts {
+                        code,
+                        comment,
+                        blank,
+                    },
+                    complexity::Metrics {
+                        functions,
+                        complexity,
+                    },
+                    generated,
+                ),
+            );
+        }
+    }
+    Ok(cache)
+}
+
+/// count_commit counts the tree of commit of repository with the
+/// languages and ignore list of the repository org (see count_tree)
+pub async fn count_commit(
+    pool: &sqlx::SqlitePool,
+    repository: &Repository,
+    commit: &str,
+) -> Result<(Vec<FileCount>, usize, usize), anyhow::Error> {
+    let repo = Path::new(&repository.path.to_string()).to_path_buf();
+    let languages = language::languages(pool, &repository.org).await?;
+    let org_patterns = ignore_patterns(pool, repository).await?;
+    count_tree(pool, &repo, commit, &languages, &org_patterns).await
+}
+
+/// sum_by sums file counts per key, ordered by key
+fn sum_by<'a, K: Ord>(
+    files: impl Iterator<Item = &'a FileCount>,
+    key: impl Fn(&'a FileCount) -> K,
+) -> BTreeMap<K, LanguageTotals> {
+    let mut by_key: BTreeMap<K, LanguageTotals> = BTreeMap::new();
+    for file in files {
+        let totals = by_key.entry(key(file)).or_insert_with(|| LanguageTotals {
+            language: file.language.clone(),
+            files: 0,
+            counts: loc::Counts::default(),
+        });
+        totals.files += 1;
+        totals.counts += file.counts;
+    }
+    by_key
+}
+
+/// totals sums the counts of included files per language, ordered by language
+pub fn totals(files: &[FileCount]) -> Vec<LanguageTotals> {
+    sum_by(files.iter().filter(|v| v.exclusion.is_none()), |v| {
+        v.language.as_str()
+    })
+    .into_values()
+    .collect()
+}
+
+/// excluded sums the counts of excluded files per exclusion and language,
+/// ordered by exclusion, then language
+pub fn excluded(files: &[FileCount]) -> Vec<ExcludedTotals> {
+    sum_by(files.iter(), |v| (v.exclusion, v.language.as_str()))
+        .into_iter()
+        .filter_map(|((exclusion, _), totals)| {
+            exclusion.map(|exclusion| ExcludedTotals { exclusion, totals })
+        })
+        .collect()
+}
+
+/// complexity_totals sums the metrics of included files per language,
+/// ordered by language; files without metrics are left out
+pub fn complexity_totals(files: &[FileCount]) -> Vec<LanguageComplexity> {
+    let mut by_language: BTreeMap<&str, LanguageComplexity> = BTreeMap::new();
+    for file in files.iter().filter(|v| v.exclusion.is_none()) {
+        if let Some(metrics) = file.metrics {
+            let totals = by_language
+                .entry(&file.language)
+                .or_insert_with(|| LanguageComplexity {
+                    language: file.language.clone(),
+                    files: 0,
+                    metrics: complexity::Metrics::default(),
+                });
+            totals.files += 1;
+            totals.metrics += metrics;
+        }
+    }
+    by_language.into_values().collect()
+}
+
+impl Analysis {
+    /// run analyses the commit rev of the local clone of repository,
+    /// replacing any stored totals for the commit
+    #[allow(dead_code)]
+    pub async fn run(
+        pool: &sqlx::SqlitePool,
+        repository: &Repository,
+        rev: &str,
+    ) -> Result<Self, anyhow::Error> {
+        let repo = Path::new(&repository.path.to_string()).to_path_buf();
+        let commit = git::rev_parse(&repo, rev).await?;
+        let (files, counted, cached) = count_commit(pool, repository, &commit).await?;
+        let totals = totals(&files);
+        let excluded = excluded(&files);
+        let complexity = complexity_totals(&files);
+        let subprojects =
+            breakdown::by_subproject(&files, &subproject::list(pool, &repository.id).await?)?;
+        let directories = breakdown::by_directory(&files);
+
+        let mut txn = pool.begin().await?;
+        sqlx::query(DELETE_COMMIT_QUERY)
+            .bind(repository.id.to_string())
+            .bind(&commit)
+            .execute(&mut txn)
+            .await?;
+        let rows = totals
+            .iter()
+            .map(|v| (0, v))
+            .chain(excluded.iter().map(|v| (v.exclusion.to_int(), &v.totals)));
+        for (exclusion, language_totals) in rows {
+            sqlx::query(INSERT_COMMIT_QUERY)
+                .bind(Uuid::new_v4().to_string())
+                .bind(repository.id.to_string())
+                .bind(&commit)
+                .bind(&language_totals.language)
+                .bind(exclusion)
+                .bind(language_totals.files)
+                .bind(language_totals.counts.code)
+                .bind(language_totals.counts.comment)
+                .bind(language_totals.counts.blank)
+                .bind(SCHEMA_VERSION)
+                .execute(&mut txn)
+                .await?;
+        }
+        sqlx::query(DELETE_COMPLEXITY_QUERY)
+            .bind(repository.id.to_string())
+            .bind(&commit)
+            .execute(&mut txn)
+            .await?;
+        for language_complexity in &complexity {
+            sqlx::query(INSERT_COMPLEXITY_QUERY)
+                .bind(Uuid::new_v4().to_string())
+                .bind(repository.id.to_string())
+                .bind(&commit)
+                .bind(&language_complexity.language)
+                .bind(language_complexity.files)
+                .bind(language_complexity.metrics.functions)
+                .bind(language_complexity.metrics.complexity)
+                .bind(SCHEMA_VERSION)
+                .execute(&mut txn)
+                .await?;
+        }
+        breakdown::store(
+            &mut txn,
+            &repository.id,
+            &commit,
+            &subprojects,
+            &directories,
+        )
+        .await?;
+        txn.commit().await?;
+
+        Ok(Self {
+            repository: repository.id,
+            commit,
+            totals,
+            excluded,
+            complexity,
+            subprojects,
+            directories,
+            counted,
+            cached,
+        })
+    }
+
+    /// read selects the stored totals of an analysed commit of repository
+    ///
+    /// a commit that was never analysed has no totals
+    #[allow(dead_code)]
+    pub async fn read(
+        pool: &sqlx::SqlitePool,
+        repository: &Uuid,
+        commit: &str,
+    ) -> Result<Vec<LanguageTotals>, anyhow::Error> {
+        let rows: Vec<(String, i64, i64, i64, i64)> = sqlx::query_as(SELECT_COMMIT_QUERY)
+            .bind(repository.to_string())
+            .bind(commit)
+            .fetch_all(pool)
+            .await?;
+        Ok(rows
+            .into_iter()
+            .map(|(language, files, code, comment, blank)| LanguageTotals {
+                language,
+                files,
+                counts: loc::Counts {
+                    code,
+                    comment,
+                    blank,
+                },
+            })
+            .collect())
+    }
+
+    /// read_complexity selects the stored complexity totals of an analysed
+    /// commit of repository
+    #[allow(dead_code)]
+    pub async fn read_complexity(
+        pool: &sqlx::SqlitePool,
+        repository: &Uuid,
+        commit: &str,
+    ) -> Result<Vec<LanguageComplexity>, anyhow::Error> {
+        let rows: Vec<(String, i64, i64, i64)> = sqlx::query_as(SELECT_COMPLEXITY_QUERY)
+            .bind(repository.to_string())
+            .bind(commit)
+            .fetch_all(pool)
+            .await?;
+        Ok(rows
+            .into_iter()
+            .map(
+                |(language, files, functions, complexity)| LanguageComplexity {
+                    language,
+                    files,
+                    metrics: complexity::Metrics {
+                        functions,
+                        complexity,
+                    },
+                },
+            )
+            .collect())
+    }
+
+    /// read_excluded selects the stored excluded totals of an analysed
+    /// commit of repository
+    #[allow(dead_code)]
+    pub async fn read_excluded(
+        pool: &sqlx::SqlitePool,
+        repository: &Uuid,
+        commit: &str,
+    ) -> Result<Vec<ExcludedTotals>, anyhow::Error> {
+        let rows: Vec<(i64, String, i64, i64, i64, i64)> = sqlx::query_as(SELECT_EXCLUDED_QUERY)
+            .bind(repository.to_string())
+            .bind(commit)
+            .fetch_all(pool)
+            .await?;
+        let mut excluded = Vec::with_capacity(rows.len());
+        for (exclusion, language, files, code, comment, blank) in rows {
+            excluded.push(ExcludedTotals {
+                exclusion: exclude::Exclusion::from_int(exclusion)?,
+                totals: LanguageTotals {
+                    language,
+                    files,
+                    counts: loc::Counts {
+                        code,
+                        comment,
+                        blank,
+                    },
+                },
+            });
+        }
+        Ok(excluded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grokloc::app::state;
+    use crate::grokloc::safe;
+
+    #[tokio::test]
+    async fn select_blobs_test() -> Result<(), anyhow::Error> {
+        let app = state::unit().await?;
+        // more blobs than one batch, each cached under two definitions
+        let blobs: Vec<String> = (0..=BLOB_BATCH_SIZE)
+            .map(|i| format!("{:040x}", i))
+            .collect();
+        for blob in &blobs {
+            for definition in ["a", "b"] {
+                sqlx::query(INSERT_BLOB_QUERY)
+                    .bind(Uuid::new_v4().to_string())
+                    .bind(blob)
+                    .bind(definition)
+                    .bind(1)
+                    .bind(0)
+                    .bind(0)
+                    .bind(0)
+                    .bind(0)
+                    .bind(false)
+                    .bind(SCHEMA_VERSION)
+                    .execute(&app.master_pool)
+                    .await?;
+            }
+        }
+        let mut wanted: Vec<&str> = blobs.iter().map(|v| v.as_str()).collect();
+        wanted.push("missing");
+
+        let cache = select_blobs(&app.master_pool, &wanted).await?;
+        assert_eq!(2 * blobs.len(), cache.len());
+        assert_eq!(
+            1,
+            cache[&(blobs[BLOB_BATCH_SIZE].clone(), "b".to_string())]
+                .0
+                .code
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn analysis_incremental_test() -> Result<(), anyhow::Error> {
+        let app = state::unit().await?;
+        let (repository, repo) = git::fixture::repository(&app).await?;
+        let author = ("A", "a@example.com");
+        repo.write("src/main.rs", "// main\nfn main() {}\n\n");
+        repo.write("src/lib.rs", "pub mod a;\n");
+        repo.write("copy/lib.rs", "pub mod a;\n");
+        repo.write("run.py", "print(1)\n");
+        repo.write("README", "not counted\n");
+        let first = repo.commit(author, 1_600_000_000);
+
+        let analysis = Analysis::run(&app.master_pool, &repository, "HEAD").await?;
+        assert_eq!(first, analysis.commit);
+        // identical files share a blob, counted once
+        assert_eq!(3, analysis.counted);
+        assert_eq!(0, analysis.cached);
+        assert_eq!(
+            vec![
+                LanguageTotals {
+                    language: "Python".to_string(),
+                    files: 1,
+                    counts: loc::Counts {
+                        code: 1,
+                        comment: 0,
+                        blank: 0
+                    }
+                },
+                LanguageTotals {
+                    language: "Rust".to_string(),
+                    files: 3,
+                    counts: loc::Counts {
+                        code: 3,
+                        comment: 1,
+                        blank: 1
+                    }
+                },
+            ],
+            analysis.totals
+        );
+
+        // only the changed file is counted
+        repo.write("run.py", "print(1)\nprint(2)\n");
+        repo.remove("copy/lib.rs");
+        let second = repo.commit(author, 1_600_000_100);
+        let analysis = Analysis::run(&app.master_pool, &repository, &second).await?;
+        assert_eq!(1, analysis.counted);
+        assert_eq!(2, analysis.cached);
+        assert_eq!(2, analysis.totals[0].counts.code);
+        assert_eq!(2, analysis.totals[1].files);
+
+        // stored against the repository, per commit
+        assert_eq!(
+            analysis.totals,
+            Analysis::read(&app.master_pool, &repository.id, &second).await?
+        );
+        assert_eq!(
+            1,
+            Analysis::read(&app.master_pool, &repository.id, &first).await?[0]
+                .counts
+                .code
+        );
+        assert!(Analysis::read(&app.master_pool, &Uuid::new_v4(), &second)
+            .await?
+            .is_empty());
+
+        // re-analysis counts nothing and replaces the stored totals
+        let analysis = Analysis::run(&app.master_pool, &repository, &first).await?;
+        assert_eq!(0, analysis.counted);
+        assert_eq!(
+            analysis.totals,
+            Analysis::read(&app.master_pool, &repository.id, &first).await?
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn analysis_exclusion_test() -> Result<(), anyhow::Error> {
+        let app = state::unit().await?;
+        let (repository, repo) = git::fixture::repository(&app).await?;
+        settings::set(
+            &app.master_pool,
+            &app.root_user.id,
+            &app.root_org.id,
+            settings::Key::IgnorePatterns,
+            &settings::Value::Text(safe::VarChar::trusted("*.min.js\ndocs/")),
+        )
+        .await?;
+        // the repository ignore file re-includes what the org list ignores
+        repo.write(exclude::IGNORE_FILE, "!docs/\nscripts/\n");
+        repo.write("main.go", "package main\n");
+        repo.write("docs/gen.go", "package docs\n");
+        repo.write("app.min.js", "a();\nb();\n");
+        repo.write("scripts/run.sh", "echo\n");
+        repo.write("vendor/x/x.go", "package x\n\n");
+        repo.write(
+            "api.pb.go",
+            "// Code generated by protoc. DO NOT EDIT.\npackage api\n",
+        );
+        repo.write("package-lock.json", "{}\n");
+        repo.commit(("A", "a@example.com"), 1_600_000_000);
+
+        let analysis = Analysis::run(&app.master_pool, &repository, "HEAD").await?;
+        let go = |files, code, comment, blank| LanguageTotals {
+            language: "Go".to_string(),
+            files,
+            counts: loc::Counts {
+                code,
+                comment,
+                blank,
+            },
+        };
+        assert_eq!(vec![go(2, 2, 0, 0)], analysis.totals);
+        let summary: Vec<_> = analysis
+            .excluded
+            .iter()
+            .map(|v| {
+                (
+                    v.exclusion,
+                    v.totals.language.as_str(),
+                    v.totals.counts.lines(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                (exclude::Exclusion::Ignored, "JavaScript", 2),
+                (exclude::Exclusion::Ignored, "Shell", 1),
+                (exclude::Exclusion::Vendored, "Go", 2),
+                (exclude::Exclusion::Generated, "Go", 2),
+                (exclude::Exclusion::Generated, "JSON", 1),
+            ],
+            summary
+        );
+        assert_eq!(
+            analysis.excluded,
+            Analysis::read_excluded(&app.master_pool, &repository.id, &analysis.commit).await?
+        );
+        assert_eq!(
+            analysis.totals,
+            Analysis::read(&app.master_pool, &repository.id, &analysis.commit).await?
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn analysis_languages_test() -> Result<(), anyhow::Error> {
+        let app = state::unit().await?;
+        let (repository, repo) = git::fixture::repository(&app).await?;
+        let dsl = loc::Language {
+            name: "Dsl".to_string(),
+            extensions: vec!["dsl".to_string()],
+            line_comments: vec![";".to_string()],
+            ..Default::default()
+        };
+        let mut custom = language::CustomLanguage::create(
+            &app.master_pool,
+            &app.root_user.id,
+            &app.root_org.id,
+            &dsl,
+        )
+        .await?;
+        repo.write("rules.dsl", "; rule\nallow all\n# deny\n");
+        repo.write("bin/run", "#!/usr/bin/env python3\nprint(1)\n");
+        repo.write("bin/data", "not counted\n");
+        repo.commit(("A", "a@example.com"), 1_600_000_000);
+
+        let analysis = Analysis::run(&app.master_pool, &repository, "HEAD").await?;
+        assert_eq!(2, analysis.counted);
+
+        // extensionless files are looked at once, with or without a shebang
+        let interpreters: Vec<Option<String>> =
+            sqlx::query_scalar("select interpreter from blob_interpreters order by interpreter")
+                .fetch_all(&app.master_pool)
+                .await?;
+        assert_eq!(vec![None, Some("python3".to_string())], interpreters);
+        let summary: Vec<_> = analysis
+            .totals
+            .iter()
+            .map(|v| (v.language.as_str(), v.counts.code, v.counts.comment))
+            .collect();
+        assert_eq!(vec![("Dsl", 2, 1), ("Python", 1, 1)], summary);
+
+        // a changed definition is not served counts cached under the old one
+        let changed = loc::Language {
+            line_comments: vec![";".to_string(), "#".to_string()],
+            ..dsl
+        };
+        custom
+            .update(&app.master_pool, &app.root_user.id, &changed)
+            .await?;
+        let analysis = Analysis::run(&app.master_pool, &repository, "HEAD").await?;
+        assert_eq!(1, analysis.counted);
+        assert_eq!(1, analysis.cached);
+        assert_eq!(2, analysis.totals[0].counts.comment);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn analysis_complexity_test() -> Result<(), anyhow::Error> {
+        let app = state::unit().await?;
+        let (repository, repo) = git::fixture::repository(&app).await?;
+        repo.write(
+            "a.rs",
+            "fn a(x: bool) -> i64 {\n    if x { 1 } else { 2 }\n}\n",
+        );
+        repo.write("b.rs", "fn b() {}\nfn c() {}\n");
+        repo.write("run.py", "def run(x):\n    return x or 1\n");
+        repo.write("q.sql", "select 1;\n");
+        repo.write("vendor/v.rs", "fn v() {}\n");
+        repo.commit(("A", "a@example.com"), 1_600_000_000);
+
+        // unsupported languages and excluded files are left out
+        let analysis = Analysis::run(&app.master_pool, &repository, "HEAD").await?;
+        let summary: Vec<_> = analysis
+            .complexity
+            .iter()
+            .map(|v| {
+                (
+                    v.language.as_str(),
+                    v.files,
+                    v.metrics.functions,
+                    v.metrics.complexity,
+                )
+            })
+            .collect();
+        assert_eq!(vec![("Python", 1, 1, 2), ("Rust", 2, 3, 4)], summary);
+        assert_eq!(
+            analysis.complexity,
+            Analysis::read_complexity(&app.master_pool, &repository.id, &analysis.commit).await?
+        );
+
+        // metrics are cached with the line counts
+        let again = Analysis::run(&app.master_pool, &repository, "HEAD").await?;
+        assert_eq!(0, again.counted);
+        assert_eq!(analysis.complexity, again.complexity);
+
+        Ok(())
+    }
+}
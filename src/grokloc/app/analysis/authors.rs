@@ -0,0 +1,584 @@
+//! authors computes per-author contribution statistics from the commit
+//! history of a repository
+//!
+//! git author names and emails are PII, so they are stored encrypted and
+//! digested in the same way as User PII
+use crate::grokloc::app::admin::repository::Repository;
+use crate::grokloc::app::models;
+use crate::grokloc::crypt;
+use crate::grokloc::git;
+use crate::grokloc::safe;
+use anyhow;
+use chrono::Datelike;
+use sqlx;
+use sqlx::Row;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+use thiserror::Error;
+use uuid::Uuid;
+
+pub const SCHEMA_VERSION: i8 = 0;
+
+pub const UPSERT_AUTHOR_QUERY: &str = r#"
+insert into authors
+(id,
+ repository,
+ name,
+ name_digest,
+ email,
+ email_digest,
+ schema_version)
+values
+(?,?,?,?,?,?,?)
+on conflict (repository, email_digest) do update set
+ name = excluded.name,
+ name_digest = excluded.name_digest
+"#;
+
+pub const INSERT_AUTHOR_QUERY: &str = r#"
+insert into authors
+(id,
+ repository,
+ name,
+ name_digest,
+ email,
+ email_digest,
+ schema_version)
+values
+(?,?,?,?,?,?,?)
+on conflict (repository, email_digest) do nothing
+"#;
+
+pub const SELECT_AUTHOR_ID_QUERY: &str = r#"
+select id from authors where repository = ? and email_digest = ?
+"#;
+
+pub const DELETE_STATS_QUERY: &str = r#"
+delete from author_stats
+where period = ?
+and author in (select id from authors where repository = ?)
+"#;
+
+pub const INSERT_STATS_QUERY: &str = r#"
+insert into author_stats
+(id,
+ author,
+ period,
+ period_start,
+ commits,
+ added,
+ removed,
+ files,
+ schema_version)
+values
+(?,?,?,?,?,?,?,?,?)
+"#;
+
+pub const SELECT_STATS_QUERY: &str = r#"
+select
+ a.id,
+ a.name,
+ a.email,
+ a.email_digest,
+ a.user,
+ s.period_start,
+ s.commits,
+ s.added,
+ s.removed,
+ s.files
+from author_stats s
+join authors a on a.id = s.author
+where a.repository = ?
+and s.period = ?
+and s.period_start >= ?
+and s.period_start < ?
+order by s.period_start, a.email_digest
+"#;
+
+pub const LINK_USERS_QUERY: &str = r#"
+update authors set user =
+ (select u.id from users u
+  where u.email_digest = authors.email_digest
+  and u.org = ?
+  and u.status != ?)
+where repository = ?
+"#;
+
+pub const COUNT_LINKED_QUERY: &str = r#"
+select count(*) from authors where repository = ? and user is not null
+"#;
+
+const DAY_SECONDS: i64 = 86400;
+
+/// Err covers author statistics errors
+#[derive(Debug, Error, PartialEq)]
+pub enum Err {
+    #[error("unknown period")]
+    UnknownPeriod,
+}
+
+/// Period is the span over which author statistics are summed
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Period {
+    /// UTC days
+    Day,
+    /// weeks starting Monday UTC
+    Week,
+    /// UTC calendar months
+    Month,
+}
+
+impl Period {
+    /// translate a Period to its database representation
+    pub fn to_int(self) -> i64 {
+        match self {
+            Period::Day => 1,
+            Period::Week => 2,
+            Period::Month => 3,
+        }
+    }
+
+    /// translate a Period from its database representation
+    #[allow(dead_code)]
+    pub fn from_int(i: i64) -> Result<Self, Err> {
+        match i {
+            1 => Ok(Period::Day),
+            2 => Ok(Period::Week),
+            3 => Ok(Period::Month),
+            _ => Err(Err::UnknownPeriod),
+        }
+    }
+
+    /// start is the time at which the period containing time starts
+    pub fn start(self, time: i64) -> i64 {
+        let day = time.div_euclid(DAY_SECONDS);
+        match self {
+            Period::Day => day * DAY_SECONDS,
+            // the epoch was a Thursday
+            Period::Week => (day - (day + 3).rem_euclid(7)) * DAY_SECONDS,
+            Period::Month => {
+                let date = chrono::DateTime::from_timestamp(time, 0)
+                    .unwrap_or_default()
+                    .date_naive();
+                date.with_day(1)
+                    .and_then(|v| v.and_hms_opt(0, 0, 0))
+                    .map(|v| v.and_utc().timestamp())
+                    .unwrap_or(day * DAY_SECONDS)
+            }
+        }
+    }
+}
+
+/// Author is a decrypted git author identity of a repository, optionally
+/// linked to the User with the same email
+#[derive(Clone, Debug, PartialEq)]
+pub struct Author {
+    pub id: Uuid,
+    pub name: safe::VarChar,
+    pub email: safe::VarChar,
+    pub user: Option<Uuid>,
+}
+
+/// AuthorStats are the contributions of an author during a period
+#[derive(Clone, Debug, PartialEq)]
+pub struct AuthorStats {
+    pub author: Author,
+    pub period_start: chrono::DateTime<chrono::Utc>,
+    pub commits: i64,
+    pub added: i64,
+    pub removed: i64,
+    /// distinct files changed
+    pub files: i64,
+}
+
+/// Tally accumulates the contributions of an author during a period
+#[derive(Default)]
+struct Tally {
+    commits: i64,
+    added: i64,
+    removed: i64,
+    files: HashSet<String>,
+}
+
+/// upsert stores the encrypted identity of a git author of repository,
+/// identified by email and renamed to name, returning the authors row id
+///
+/// run names authors as in their newest commit; other analyses only add
+/// authors it has not seen (see insert)
+pub async fn upsert(
+    txn: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    repository: &Uuid,
+    name: &str,
+    email: &str,
+    key: &str,
+) -> Result<String, anyhow::Error> {
+    store(txn, UPSERT_AUTHOR_QUERY, repository, name, email, key).await
+}
+
+/// insert stores the encrypted identity of a git author of repository,
+/// identified by email, keeping the name of one already stored, and
+/// returns the authors row id
+pub async fn insert(
+    txn: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    repository: &Uuid,
+    name: &str,
+    email: &str,
+    key: &str,
+) -> Result<String, anyhow::Error> {
+    store(txn, INSERT_AUTHOR_QUERY, repository, name, email, key).await
+}
+
+/// store runs query, UPSERT_AUTHOR_QUERY or INSERT_AUTHOR_QUERY, for an
+/// author and returns the authors row id
+async fn store(
+    txn: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    query: &str,
+    repository: &Uuid,
+    name: &str,
+    email: &str,
+    key: &str,
+) -> Result<String, anyhow::Error> {
+    // normalised as user emails are, so that links match regardless of case
+    let email = &safe::email_normal(email);
+    let email_digest = crypt::sha256_hex(email);
+    let iv = crypt::iv(&email_digest);
+    sqlx::query(query)
+        .bind(Uuid::new_v4().to_string())
+        .bind(repository.to_string())
+        .bind(crypt::encrypt(key, &iv, name)?)
+        .bind(crypt::sha256_hex(name))
+        .bind(crypt::encrypt(key, &iv, email)?)
+        .bind(&email_digest)
+        .bind(SCHEMA_VERSION)
+        .execute(&mut *txn)
+        .await?;
+    Ok(sqlx::query_scalar(SELECT_AUTHOR_ID_QUERY)
+        .bind(repository.to_string())
+        .bind(&email_digest)
+        .fetch_one(&mut *txn)
+        .await?)
+}
+
+/// decrypt constructs an Author from the id, name, email, email_digest and
+/// user columns of an authors row
+pub fn decrypt(row: &sqlx::sqlite::SqliteRow, key: &str) -> Result<Author, anyhow::Error> {
+    let iv = crypt::iv(&row.try_get::<String, _>("email_digest")?);
+    let name = crypt::decrypt(key, &iv, &row.try_get::<String, _>("name")?)?;
+    let email = crypt::decrypt(key, &iv, &row.try_get::<String, _>("email")?)?;
+    let user = match row.try_get::<Option<String>, _>("user")? {
+        Some(v) => Some(Uuid::try_parse(&v)?),
+        None => None,
+    };
+    Ok(Author {
+        id: Uuid::try_parse(&row.try_get::<String, _>("id")?)?,
+        name: safe::VarChar::trusted(&name),
+        email: safe::VarChar::trusted(&email),
+        user,
+    })
+}
+
+/// run computes the statistics per author and period of the non-merge
+/// history of rev in the local clone of repository, replacing those
+/// stored for the period, and returns them (see stats)
+///
+/// an author is identified by normalised email (see safe::email_normal); the
+/// name is that of the newest commit
+#[allow(dead_code)]
+pub async fn run(
+    pool: &sqlx::SqlitePool,
+    repository: &Repository,
+    rev: &str,
+    period: Period,
+    key: &str,
+) -> Result<Vec<AuthorStats>, anyhow::Error> {
+    let repo = Path::new(&repository.path.to_string()).to_path_buf();
+    let commit = git::rev_parse(&repo, rev).await?;
+    let log = git::log_numstat(&repo, &commit).await?;
+
+    let mut names: HashMap<String, &str> = HashMap::new();
+    let mut tallies: HashMap<String, BTreeMap<i64, Tally>> = HashMap::new();
+    for log_commit in &log {
+        let email = safe::email_normal(&log_commit.author_email);
+        names
+            .entry(email.clone())
+            .or_insert(&log_commit.author_name);
+        let tally = tallies
+            .entry(email)
+            .or_default()
+            .entry(period.start(log_commit.time))
+            .or_default();
+        tally.commits += 1;
+        for change in &log_commit.changes {
+            tally.added += change.added;
+            tally.removed += change.removed;
+            tally.files.insert(change.path.clone());
+        }
+    }
+
+    let mut txn = pool.begin().await?;
+    sqlx::query(DELETE_STATS_QUERY)
+        .bind(period.to_int())
+        .bind(repository.id.to_string())
+        .execute(&mut txn)
+        .await?;
+    for (email, periods) in &tallies {
+        let author = upsert(&mut txn, &repository.id, names[email], email, key).await?;
+        for (period_start, tally) in periods {
+            sqlx::query(INSERT_STATS_QUERY)
+                .bind(Uuid::new_v4().to_string())
+                .bind(&author)
+                .bind(period.to_int())
+                .bind(period_start)
+                .bind(tally.commits)
+                .bind(tally.added)
+                .bind(tally.removed)
+                .bind(tally.files.len() as i64)
+                .bind(SCHEMA_VERSION)
+                .execute(&mut txn)
+                .await?;
+        }
+    }
+    txn.commit().await?;
+
+    stats(
+        pool,
+        &repository.id,
+        period,
+        chrono::DateTime::<chrono::Utc>::MIN_UTC,
+        chrono::DateTime::<chrono::Utc>::MAX_UTC,
+        key,
+    )
+    .await
+}
+
+/// stats selects and decrypts the stored statistics of repository for
+/// periods starting in [from, to), ordered by period start
+#[allow(dead_code)]
+pub async fn stats(
+    pool: &sqlx::SqlitePool,
+    repository: &Uuid,
+    period: Period,
+    from: chrono::DateTime<chrono::Utc>,
+    to: chrono::DateTime<chrono::Utc>,
+    key: &str,
+) -> Result<Vec<AuthorStats>, anyhow::Error> {
+    let rows = sqlx::query(SELECT_STATS_QUERY)
+        .bind(repository.to_string())
+        .bind(period.to_int())
+        .bind(from.timestamp())
+        .bind(to.timestamp())
+        .fetch_all(pool)
+        .await?;
+
+    let mut stats = Vec::with_capacity(rows.len());
+    for row in rows {
+        stats.push(AuthorStats {
+            author: decrypt(&row, key)?,
+            period_start: chrono::DateTime::from_timestamp(
+                row.try_get::<i64, _>("period_start")?,
+                0,
+            )
+            .unwrap_or_default(),
+            commits: row.try_get::<i64, _>("commits")?,
+            added: row.try_get::<i64, _>("added")?,
+            removed: row.try_get::<i64, _>("removed")?,
+            files: row.try_get::<i64, _>("files")?,
+        });
+    }
+    Ok(stats)
+}
+
+/// link_users links each author of repository to the user of the
+/// repository org with the same email digest, if any, returning the
+/// number of linked authors
+///
+/// links to users that no longer match are removed
+#[allow(dead_code)]
+pub async fn link_users(
+    pool: &sqlx::SqlitePool,
+    repository: &Repository,
+) -> Result<i64, anyhow::Error> {
+    sqlx::query(LINK_USERS_QUERY)
+        .bind(repository.org.to_string())
+        .bind(models::Status::Deleted.to_int())
+        .bind(repository.id.to_string())
+        .execute(pool)
+        .await?;
+    Ok(sqlx::query_scalar(COUNT_LINKED_QUERY)
+        .bind(repository.id.to_string())
+        .fetch_one(pool)
+        .await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grokloc::app::admin::user::User;
+    use crate::grokloc::app::analysis::ownership;
+    use crate::grokloc::app::state;
+
+    // Sunday 2020-09-13 12:26:40 UTC
+    const SUNDAY: i64 = 1_600_000_000;
+
+    #[test]
+    fn period_test() -> Result<(), Err> {
+        for period in [Period::Day, Period::Week, Period::Month] {
+            assert_eq!(period, Period::from_int(period.to_int())?);
+        }
+        assert_eq!(Err::UnknownPeriod, Period::from_int(0).unwrap_err());
+
+        // 2020-09-13 00:00, 2020-09-07 00:00 (Monday), 2020-09-01 00:00
+        assert_eq!(1_599_955_200, Period::Day.start(SUNDAY));
+        assert_eq!(1_599_436_800, Period::Week.start(SUNDAY));
+        assert_eq!(1_599_436_800, Period::Week.start(1_599_436_800));
+        assert_eq!(1_598_918_400, Period::Month.start(SUNDAY));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn authors_run_link_test() -> Result<(), anyhow::Error> {
+        let app = state::unit().await?;
+        let (repository, repo) = git::fixture::repository(&app).await?;
+        let ann = ("Ann", "ann@example.com");
+        let bob = ("Bob", "bob@example.com");
+        repo.write("a.rs", "1\n2\n3\n");
+        repo.commit(ann, SUNDAY);
+        repo.write("a.rs", "1\n2\n");
+        repo.write("b.rs", "1\n");
+        repo.commit(ann, SUNDAY + 100);
+        repo.write("b.rs", "2\n");
+        repo.commit(bob, SUNDAY + DAY_SECONDS);
+        repo.write("a.rs", "1\n");
+        repo.commit(("Ann B", "ann@example.com"), SUNDAY + DAY_SECONDS);
+
+        let mut weekly = run(
+            &app.master_pool,
+            &repository,
+            "HEAD",
+            Period::Week,
+            &app.key,
+        )
+        .await?;
+        // Sunday is the end of one week, Monday the start of the next
+        assert_eq!(3, weekly.len());
+        assert_eq!(
+            weekly[0].period_start.timestamp(),
+            Period::Week.start(SUNDAY)
+        );
+        assert_eq!("Ann B", weekly[0].author.name.to_string());
+        assert_eq!("ann@example.com", weekly[0].author.email.to_string());
+        assert_eq!(
+            (2, 4, 1, 2),
+            (
+                weekly[0].commits,
+                weekly[0].added,
+                weekly[0].removed,
+                weekly[0].files
+            )
+        );
+        // within a period, authors are in no particular order
+        weekly[1..].sort_by_key(|v| v.author.name.to_string());
+        assert_eq!(weekly[0].author, weekly[1].author);
+        assert_eq!(
+            (1, 0, 1, 1),
+            (
+                weekly[1].commits,
+                weekly[1].added,
+                weekly[1].removed,
+                weekly[1].files
+            )
+        );
+        assert_eq!("Bob", weekly[2].author.name.to_string());
+        assert_eq!(None, weekly[2].author.user);
+
+        // identities are stored encrypted
+        let stored: Vec<(String, String)> =
+            sqlx::query_as("select name, email from authors where repository = ?")
+                .bind(repository.id.to_string())
+                .fetch_all(&app.master_pool)
+                .await?;
+        assert_eq!(2, stored.len());
+        assert!(stored
+            .iter()
+            .all(|(name, email)| !name.contains("Ann") && !email.contains('@')));
+
+        // monthly statistics are kept beside the weekly ones
+        let monthly = run(
+            &app.master_pool,
+            &repository,
+            "HEAD",
+            Period::Month,
+            &app.key,
+        )
+        .await?;
+        assert_eq!(2, monthly.len());
+        let from = chrono::DateTime::from_timestamp(SUNDAY, 0).unwrap_or_default();
+        let to = chrono::DateTime::<chrono::Utc>::MAX_UTC;
+        let mut later = stats(
+            &app.master_pool,
+            &repository.id,
+            Period::Week,
+            from,
+            to,
+            &app.key,
+        )
+        .await?;
+        later.sort_by_key(|v| v.author.name.to_string());
+        assert_eq!(weekly[1..].to_vec(), later);
+
+        // link to the user with bob's email, whatever its case
+        let password = safe::VarChar::new(&crypt::kdf(&crypt::rand_hex(), crypt::MIN_KDF_ROUNDS))?;
+        let mut user = User::create(
+            &app.master_pool,
+            &safe::VarChar::rand(),
+            &safe::VarChar::trusted(&bob.1.to_uppercase()),
+            &app.root_org.id,
+            &password,
+            &app.key,
+        )
+        .await?;
+        assert_eq!(1, link_users(&app.master_pool, &repository).await?);
+        let monthly = stats(
+            &app.master_pool,
+            &repository.id,
+            Period::Month,
+            chrono::DateTime::<chrono::Utc>::MIN_UTC,
+            to,
+            &app.key,
+        )
+        .await?;
+        let bob_stats = monthly.iter().find(|v| v.author.name.to_string() == "Bob");
+        assert_eq!(Some(user.id), bob_stats.and_then(|v| v.author.user));
+
+        let head = git::rev_parse(&repo.path, "HEAD").await?;
+        ownership::run(&app.master_pool, &repository, &head, &app.key).await?;
+
+        // erasing the user deletes the author identity and all it references
+        user.erase(&app.master_pool, &app.root_user.id).await?;
+        let monthly = stats(
+            &app.master_pool,
+            &repository.id,
+            Period::Month,
+            chrono::DateTime::<chrono::Utc>::MIN_UTC,
+            to,
+            &app.key,
+        )
+        .await?;
+        assert!(!monthly.is_empty());
+        assert!(monthly.iter().all(|v| v.author.email.to_string() == ann.1));
+        let owners = ownership::read(&app.master_pool, &repository.id, &head, &app.key).await?;
+        assert!(!owners.is_empty());
+        assert!(owners
+            .iter()
+            .flat_map(|v| v.shares.iter())
+            .all(|v| v.author.email.to_string() == ann.1));
+        let remaining: i64 =
+            sqlx::query_scalar("select count(*) from authors where email_digest = ?")
+                .bind(crypt::sha256_hex(bob.1))
+                .fetch_one(&app.master_pool)
+                .await?;
+        assert_eq!(0, remaining);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,301 @@
+//! breakdown groups the included files of an analysed commit by
+//! subproject and by top-level directory, so monorepo totals can be told
+//! apart
+use crate::grokloc::app::admin::subproject::{self, Subproject};
+use crate::grokloc::app::analysis::{sum_by, FileCount, LanguageTotals};
+use crate::grokloc::loc;
+use anyhow;
+use sqlx;
+use thiserror::Error;
+use uuid::Uuid;
+
+pub const SCHEMA_VERSION: i8 = 0;
+
+/// ROOT_DIRECTORY is the directory group of files at the root of the tree
+pub const ROOT_DIRECTORY: &str = ".";
+
+pub const DELETE_QUERY: &str = r#"
+delete from commit_groups where repository = ? and commit_id = ?
+"#;
+
+pub const INSERT_QUERY: &str = r#"
+insert into commit_groups
+(id,
+ repository,
+ commit_id,
+ breakdown,
+ name,
+ language,
+ files,
+ code,
+ comment,
+ blank,
+ schema_version)
+values
+(?,?,?,?,?,?,?,?,?,?,?)
+"#;
+
+pub const SELECT_QUERY: &str = r#"
+select name, language, files, code, comment, blank
+from commit_groups
+where repository = ? and commit_id = ? and breakdown = ?
+order by name, language
+"#;
+
+/// Err covers breakdown errors
+#[derive(Debug, Error, PartialEq)]
+pub enum Err {
+    #[error("unknown breakdown")]
+    UnknownBreakdown,
+}
+
+/// Breakdown is how files are grouped
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Breakdown {
+    /// by the subprojects whose patterns match the file
+    Subproject,
+    /// by the first component of the file path
+    Directory,
+}
+
+impl Breakdown {
+    /// translate a Breakdown to its database representation
+    pub fn to_int(self) -> i64 {
+        match self {
+            Breakdown::Subproject => 1,
+            Breakdown::Directory => 2,
+        }
+    }
+
+    /// translate a Breakdown from its database representation
+    #[allow(dead_code)]
+    pub fn from_int(i: i64) -> Result<Self, Err> {
+        match i {
+            1 => Ok(Breakdown::Subproject),
+            2 => Ok(Breakdown::Directory),
+            _ => Err(Err::UnknownBreakdown),
+        }
+    }
+}
+
+/// GroupTotals are the summed counts of the files of a language in a group
+#[derive(Clone, Debug, PartialEq)]
+pub struct GroupTotals {
+    pub name: String,
+    pub totals: LanguageTotals,
+}
+
+/// directory is the top-level directory of path
+pub fn directory(path: &str) -> &str {
+    match path.split_once('/') {
+        Some((directory, _)) => directory,
+        None => ROOT_DIRECTORY,
+    }
+}
+
+/// flatten turns sums keyed by (group, language) into GroupTotals
+fn flatten(sums: impl IntoIterator<Item = ((String, String), LanguageTotals)>) -> Vec<GroupTotals> {
+    sums.into_iter()
+        .map(|((name, _), totals)| GroupTotals { name, totals })
+        .collect()
+}
+
+/// by_directory sums the counts of included files per top-level directory
+/// and language, ordered by directory, then language
+pub fn by_directory(files: &[FileCount]) -> Vec<GroupTotals> {
+    flatten(sum_by(
+        files.iter().filter(|v| v.exclusion.is_none()),
+        |v| (directory(&v.path).to_string(), v.language.clone()),
+    ))
+}
+
+/// by_subproject sums the counts of included files per subproject and
+/// language, ordered by subproject, then language
+///
+/// a file matched by several subprojects counts toward each of them
+pub fn by_subproject(
+    files: &[FileCount],
+    subprojects: &[Subproject],
+) -> Result<Vec<GroupTotals>, anyhow::Error> {
+    let mut groups = Vec::new();
+    for subproject in subprojects {
+        let globs = subproject::globs(&subproject.patterns)?;
+        groups.extend(flatten(sum_by(
+            files
+                .iter()
+                .filter(|v| v.exclusion.is_none() && globs.is_match(&v.path)),
+            |v| (subproject.name.to_string(), v.language.clone()),
+        )));
+    }
+    Ok(groups)
+}
+
+/// store replaces the groups of commit of repository
+pub async fn store(
+    txn: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    repository: &Uuid,
+    commit: &str,
+    subprojects: &[GroupTotals],
+    directories: &[GroupTotals],
+) -> Result<(), anyhow::Error> {
+    sqlx::query(DELETE_QUERY)
+        .bind(repository.to_string())
+        .bind(commit)
+        .execute(&mut *txn)
+        .await?;
+    let rows = subprojects
+        .iter()
+        .map(|v| (Breakdown::Subproject, v))
+        .chain(directories.iter().map(|v| (Breakdown::Directory, v)));
+    for (breakdown, group) in rows {
+        sqlx::query(INSERT_QUERY)
+            .bind(Uuid::new_v4().to_string())
+            .bind(repository.to_string())
+            .bind(commit)
+            .bind(breakdown.to_int())
+            .bind(&group.name)
+            .bind(&group.totals.language)
+            .bind(group.totals.files)
+            .bind(group.totals.counts.code)
+            .bind(group.totals.counts.comment)
+            .bind(group.totals.counts.blank)
+            .bind(SCHEMA_VERSION)
+            .execute(&mut *txn)
+            .await?;
+    }
+    Ok(())
+}
+
+/// read selects the stored groups of an analysed commit of repository,
+/// ordered by group, then language
+#[allow(dead_code)]
+pub async fn read(
+    pool: &sqlx::SqlitePool,
+    repository: &Uuid,
+    commit: &str,
+    breakdown: Breakdown,
+) -> Result<Vec<GroupTotals>, anyhow::Error> {
+    let rows: Vec<(String, String, i64, i64, i64, i64)> = sqlx::query_as(SELECT_QUERY)
+        .bind(repository.to_string())
+        .bind(commit)
+        .bind(breakdown.to_int())
+        .fetch_all(pool)
+        .await?;
+    Ok(rows
+        .into_iter()
+        .map(
+            |(name, language, files, code, comment, blank)| GroupTotals {
+                name,
+                totals: LanguageTotals {
+                    language,
+                    files,
+                    counts: loc::Counts {
+                        code,
+                        comment,
+                        blank,
+                    },
+                },
+            },
+        )
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grokloc::app::analysis::Analysis;
+    use crate::grokloc::app::state;
+    use crate::grokloc::git;
+    use crate::grokloc::safe;
+
+    #[tokio::test]
+    async fn breakdown_test() -> Result<(), anyhow::Error> {
+        let app = state::unit().await?;
+        let (repository, repo) = git::fixture::repository(&app).await?;
+        for (name, pattern) in [("api", "services/api/**"), ("rust", "**/*.rs")] {
+            Subproject::create(
+                &app.master_pool,
+                &app.root_user.id,
+                &repository.id,
+                &safe::VarChar::trusted(name),
+                &[pattern.to_string()],
+            )
+            .await?;
+        }
+        repo.write("build.rs", "fn main() {}\n");
+        repo.write("services/api/main.rs", "fn main() {}\n\n");
+        repo.write("services/api/run.py", "print(1)\n");
+        repo.write("services/web/index.js", "a();\n");
+        repo.write("services/vendor/x.rs", "fn x() {}\n");
+        repo.commit(("A", "a@example.com"), 1_600_000_000);
+
+        let analysis = Analysis::run(&app.master_pool, &repository, "HEAD").await?;
+        let summary = |groups: &[GroupTotals]| -> Vec<(String, String, i64)> {
+            groups
+                .iter()
+                .map(|v| {
+                    (
+                        v.name.clone(),
+                        v.totals.language.clone(),
+                        v.totals.counts.lines(),
+                    )
+                })
+                .collect()
+        };
+        let expect = |rows: &[(&str, &str, i64)]| -> Vec<(String, String, i64)> {
+            rows.iter()
+                .map(|(name, language, lines)| (name.to_string(), language.to_string(), *lines))
+                .collect()
+        };
+
+        // vendored files are left out; files may be in several subprojects
+        assert_eq!(
+            expect(&[
+                ("api", "Python", 1),
+                ("api", "Rust", 2),
+                ("rust", "Rust", 3),
+            ]),
+            summary(&analysis.subprojects)
+        );
+        assert_eq!(
+            expect(&[
+                (".", "Rust", 1),
+                ("services", "JavaScript", 1),
+                ("services", "Python", 1),
+                ("services", "Rust", 2),
+            ]),
+            summary(&analysis.directories)
+        );
+
+        // stored beside the language totals of the commit
+        let commit = &analysis.commit;
+        assert_eq!(
+            analysis.subprojects,
+            read(
+                &app.master_pool,
+                &repository.id,
+                commit,
+                Breakdown::Subproject
+            )
+            .await?
+        );
+        assert_eq!(
+            analysis.directories,
+            read(
+                &app.master_pool,
+                &repository.id,
+                commit,
+                Breakdown::Directory
+            )
+            .await?
+        );
+
+        assert_eq!(
+            Breakdown::Directory,
+            Breakdown::from_int(Breakdown::Directory.to_int())?
+        );
+        assert_eq!(Err::UnknownBreakdown, Breakdown::from_int(0).unwrap_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,308 @@
+//! churn ranks the files of a repository by how often they changed during
+//! a time window, weighted by their current size, to find hotspots
+//!
+//! history is read with renames as a deletion and an addition, so the churn
+//! of a renamed file starts at its rename
+use crate::grokloc::app::admin::repository::Repository;
+use crate::grokloc::app::analysis::count_commit;
+use crate::grokloc::git;
+use anyhow;
+use sqlx;
+use sqlx::Row;
+use std::collections::HashMap;
+use std::path::Path;
+use uuid::Uuid;
+
+pub const SCHEMA_VERSION: i8 = 0;
+
+pub const DELETE_QUERY: &str = r#"
+delete from file_churn
+where repository = ? and window_start = ? and window_end = ?
+"#;
+
+pub const INSERT_QUERY: &str = r#"
+insert into file_churn
+(id,
+ repository,
+ window_start,
+ window_end,
+ commit_id,
+ path,
+ language,
+ commits,
+ added,
+ removed,
+ code,
+ score,
+ schema_version)
+values
+(?,?,?,?,?,?,?,?,?,?,?,?,?)
+"#;
+
+pub const SELECT_QUERY: &str = r#"
+select
+ commit_id,
+ path,
+ language,
+ commits,
+ added,
+ removed,
+ code,
+ score
+from file_churn
+where repository = ? and window_start = ? and window_end = ?
+order by score desc, path
+"#;
+
+/// Hotspot is the churn of a file during a window with its size at the
+/// end of the window
+#[derive(Clone, Debug, PartialEq)]
+pub struct Hotspot {
+    pub path: String,
+    pub language: String,
+    /// non-merge commits that changed the file
+    pub commits: i64,
+    pub added: i64,
+    pub removed: i64,
+    /// lines of code at the analysed commit
+    pub code: i64,
+    /// commits times lines of code
+    pub score: i64,
+}
+
+impl Hotspot {
+    /// churn is the lines changed
+    #[allow(dead_code)]
+    pub fn churn(&self) -> i64 {
+        self.added + self.removed
+    }
+}
+
+/// Churn is the ranked hotspots of a repository for a window
+#[derive(Clone, Debug, PartialEq)]
+pub struct Churn {
+    /// the commit whose tree gives the file sizes
+    pub commit: String,
+    pub from: chrono::DateTime<chrono::Utc>,
+    pub to: chrono::DateTime<chrono::Utc>,
+    /// ordered by score descending, then path
+    pub hotspots: Vec<Hotspot>,
+}
+
+/// run computes the churn of the non-merge history of rev in the local
+/// clone of repository for commits authored in [from, to), replacing any
+/// stored for the window
+///
+/// only included files present in the tree of rev are ranked; deleted,
+/// unrecognised and excluded files are left out
+#[allow(dead_code)]
+pub async fn run(
+    pool: &sqlx::SqlitePool,
+    repository: &Repository,
+    rev: &str,
+    from: chrono::DateTime<chrono::Utc>,
+    to: chrono::DateTime<chrono::Utc>,
+) -> Result<Churn, anyhow::Error> {
+    let repo = Path::new(&repository.path.to_string()).to_path_buf();
+    let commit = git::rev_parse(&repo, rev).await?;
+    let log = git::log_numstat(&repo, &commit).await?;
+
+    // commits, added, removed per path
+    let mut tallies: HashMap<&str, (i64, i64, i64)> = HashMap::new();
+    for log_commit in log
+        .iter()
+        .filter(|v| v.time >= from.timestamp() && v.time < to.timestamp())
+    {
+        for change in &log_commit.changes {
+            let tally = tallies.entry(change.path.as_str()).or_default();
+            tally.0 += 1;
+            tally.1 += change.added;
+            tally.2 += change.removed;
+        }
+    }
+
+    let (files, _, _) = count_commit(pool, repository, &commit).await?;
+    let mut hotspots: Vec<Hotspot> = files
+        .into_iter()
+        .filter(|v| v.exclusion.is_none())
+        .filter_map(|file| {
+            tallies
+                .get(file.path.as_str())
+                .map(|(commits, added, removed)| Hotspot {
+                    commits: *commits,
+                    added: *added,
+                    removed: *removed,
+                    code: file.counts.code,
+                    score: commits * file.counts.code,
+                    path: file.path,
+                    language: file.language,
+                })
+        })
+        .collect();
+    hotspots.sort_by(|a, b| b.score.cmp(&a.score).then_with(|| a.path.cmp(&b.path)));
+
+    let mut txn = pool.begin().await?;
+    sqlx::query(DELETE_QUERY)
+        .bind(repository.id.to_string())
+        .bind(from.timestamp())
+        .bind(to.timestamp())
+        .execute(&mut txn)
+        .await?;
+    for hotspot in &hotspots {
+        sqlx::query(INSERT_QUERY)
+            .bind(Uuid::new_v4().to_string())
+            .bind(repository.id.to_string())
+            .bind(from.timestamp())
+            .bind(to.timestamp())
+            .bind(&commit)
+            .bind(&hotspot.path)
+            .bind(&hotspot.language)
+            .bind(hotspot.commits)
+            .bind(hotspot.added)
+            .bind(hotspot.removed)
+            .bind(hotspot.code)
+            .bind(hotspot.score)
+            .bind(SCHEMA_VERSION)
+            .execute(&mut txn)
+            .await?;
+    }
+    txn.commit().await?;
+
+    Ok(Churn {
+        commit,
+        from,
+        to,
+        hotspots,
+    })
+}
+
+/// read selects the stored churn of repository for the window [from, to),
+/// if it was computed and had any hotspots
+#[allow(dead_code)]
+pub async fn read(
+    pool: &sqlx::SqlitePool,
+    repository: &Uuid,
+    from: chrono::DateTime<chrono::Utc>,
+    to: chrono::DateTime<chrono::Utc>,
+) -> Result<Option<Churn>, anyhow::Error> {
+    let rows = sqlx::query(SELECT_QUERY)
+        .bind(repository.to_string())
+        .bind(from.timestamp())
+        .bind(to.timestamp())
+        .fetch_all(pool)
+        .await?;
+    let commit = match rows.first() {
+        None => return Ok(None),
+        Some(row) => row.try_get::<String, _>("commit_id")?,
+    };
+    let mut hotspots = Vec::with_capacity(rows.len());
+    for row in rows {
+        hotspots.push(Hotspot {
+            path: row.try_get::<String, _>("path")?,
+            language: row.try_get::<String, _>("language")?,
+            commits: row.try_get::<i64, _>("commits")?,
+            added: row.try_get::<i64, _>("added")?,
+            removed: row.try_get::<i64, _>("removed")?,
+            code: row.try_get::<i64, _>("code")?,
+            score: row.try_get::<i64, _>("score")?,
+        });
+    }
+    Ok(Some(Churn {
+        commit,
+        from,
+        to,
+        hotspots,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grokloc::app::state;
+
+    #[tokio::test]
+    async fn churn_test() -> Result<(), anyhow::Error> {
+        let app = state::unit().await?;
+        let (repository, repo) = git::fixture::repository(&app).await?;
+        let author = ("A", "a@example.com");
+        let start = 1_600_000_000;
+        repo.write("a.rs", "fn a() {}\n");
+        repo.write("b.py", "print(1)\n");
+        repo.write("gone.rs", "fn g() {}\n");
+        repo.write("README", "x\n");
+        repo.commit(author, start);
+        repo.write("a.rs", "fn a() {}\nfn b() {}\n");
+        repo.write("README", "y\n");
+        repo.commit(author, start + 100);
+        repo.write("a.rs", "fn a() {}\n// c\nfn c() {}\n");
+        repo.write("b.py", "print(1)\nprint(2)\n");
+        repo.remove("gone.rs");
+        let head = repo.commit(author, start + 200);
+
+        let time = |v: i64| chrono::DateTime::from_timestamp(v, 0).unwrap_or_default();
+        let summary = |churn: &Churn| -> Vec<(String, i64, i64, i64, i64)> {
+            churn
+                .hotspots
+                .iter()
+                .map(|v| (v.path.clone(), v.commits, v.churn(), v.code, v.score))
+                .collect()
+        };
+
+        // deleted and unrecognised files are not ranked
+        let all = run(
+            &app.master_pool,
+            &repository,
+            "HEAD",
+            time(start),
+            time(start + 300),
+        )
+        .await?;
+        assert_eq!(head, all.commit);
+        assert_eq!(
+            vec![
+                ("a.rs".to_string(), 3, 5, 2, 6),
+                ("b.py".to_string(), 2, 2, 2, 4),
+            ],
+            summary(&all)
+        );
+
+        // the window end is exclusive
+        let late = run(
+            &app.master_pool,
+            &repository,
+            &head,
+            time(start + 50),
+            time(start + 200),
+        )
+        .await?;
+        assert_eq!(vec![("a.rs".to_string(), 1, 1, 2, 2)], summary(&late));
+
+        // windows are stored apart
+        assert_eq!(
+            Some(all.clone()),
+            read(
+                &app.master_pool,
+                &repository.id,
+                time(start),
+                time(start + 300)
+            )
+            .await?
+        );
+        assert_eq!(
+            Some(late),
+            read(
+                &app.master_pool,
+                &repository.id,
+                time(start + 50),
+                time(start + 200)
+            )
+            .await?
+        );
+        assert_eq!(
+            None,
+            read(&app.master_pool, &repository.id, time(0), time(start)).await?
+        );
+
+        Ok(())
+    }
+}
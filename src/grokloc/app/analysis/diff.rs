@@ -0,0 +1,405 @@
+//! diff compares two analysed commits, of the same or of different
+//! repositories, per language and per file
+//!
+//! only included files are compared (see exclude), so a file that becomes
+//! excluded reads as losing all of its lines
+use crate::grokloc::app::admin::repository::Repository;
+use crate::grokloc::app::analysis::{count_commit, totals, FileCount};
+use crate::grokloc::git;
+use crate::grokloc::loc;
+use anyhow;
+use sqlx;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+use thiserror::Error;
+
+pub const SELECT_ANALYSED_QUERY: &str = r#"
+select count(*) from commit_counts where repository = ? and commit_id = ?
+"#;
+
+/// Err covers diff errors
+#[derive(Debug, Error, PartialEq)]
+pub enum Err {
+    #[error("commit not analysed")]
+    NotAnalysed,
+}
+
+/// Status is how a file changed
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Status {
+    Added,
+    Deleted,
+    Modified,
+    Renamed,
+}
+
+/// FileDelta is the change in the counts of a file; counts of a side the
+/// file is absent from are zero
+#[derive(Clone, Debug, PartialEq)]
+pub struct FileDelta {
+    /// the new path, or the old path of a deleted file
+    pub path: String,
+    /// the old path of a renamed file
+    pub old_path: Option<String>,
+    pub status: Status,
+    pub language: String,
+    pub old: loc::Counts,
+    pub new: loc::Counts,
+}
+
+impl FileDelta {
+    /// delta is the new counts less the old
+    #[allow(dead_code)]
+    pub fn delta(&self) -> loc::Counts {
+        self.new - self.old
+    }
+}
+
+/// LanguageDelta is the change in the totals of a language
+#[derive(Clone, Debug, PartialEq)]
+pub struct LanguageDelta {
+    pub language: String,
+    pub files: i64,
+    pub counts: loc::Counts,
+}
+
+/// Diff is the comparison of an old commit with a new one
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diff {
+    pub old_commit: String,
+    pub new_commit: String,
+    /// languages that changed, ordered by language
+    pub languages: Vec<LanguageDelta>,
+    /// files that changed, ordered by path
+    pub files: Vec<FileDelta>,
+}
+
+/// path_changes compares the files of commits that share no history:
+/// files are matched by path, and an identical blob that moved is a rename
+pub fn path_changes(old: &[FileCount], new: &[FileCount]) -> Vec<git::Change> {
+    let old_blobs: HashMap<&str, &str> = old
+        .iter()
+        .map(|v| (v.path.as_str(), v.blob.as_str()))
+        .collect();
+    let new_blobs: HashMap<&str, &str> = new
+        .iter()
+        .map(|v| (v.path.as_str(), v.blob.as_str()))
+        .collect();
+
+    let mut changes = Vec::new();
+    let mut deleted: Vec<&FileCount> = Vec::new();
+    for file in old {
+        match new_blobs.get(file.path.as_str()) {
+            Some(blob) if *blob != file.blob => {
+                changes.push(git::Change::Modified(file.path.clone()))
+            }
+            Some(_) => (),
+            None => deleted.push(file),
+        }
+    }
+    // each blob lists its deleted files last first, so pop takes the first
+    let mut deleted_by_blob: HashMap<&str, Vec<&FileCount>> = HashMap::new();
+    for file in deleted.iter().rev() {
+        deleted_by_blob
+            .entry(file.blob.as_str())
+            .or_default()
+            .push(file);
+    }
+    let mut renamed: HashSet<&str> = HashSet::new();
+    for file in new
+        .iter()
+        .filter(|v| !old_blobs.contains_key(v.path.as_str()))
+    {
+        match deleted_by_blob
+            .get_mut(file.blob.as_str())
+            .and_then(|v| v.pop())
+        {
+            Some(old_file) => {
+                renamed.insert(old_file.path.as_str());
+                changes.push(git::Change::Renamed(
+                    old_file.path.clone(),
+                    file.path.clone(),
+                ));
+            }
+            None => changes.push(git::Change::Added(file.path.clone())),
+        }
+    }
+    changes.extend(
+        deleted
+            .into_iter()
+            .filter(|v| !renamed.contains(v.path.as_str()))
+            .map(|v| git::Change::Deleted(v.path.clone())),
+    );
+    changes
+}
+
+/// diff compares the commit old_rev of old with the commit new_rev of new;
+/// both commits must have been analysed
+///
+/// renames within a repository are found by git rename detection, and
+/// between repositories by identical content; language and file deltas are
+/// both of the files as classified with the current ignore list, so the
+/// language deltas sum the file deltas
+#[allow(dead_code)]
+pub async fn diff(
+    pool: &sqlx::SqlitePool,
+    old: &Repository,
+    old_rev: &str,
+    new: &Repository,
+    new_rev: &str,
+) -> Result<Diff, anyhow::Error> {
+    let old_repo = Path::new(&old.path.to_string()).to_path_buf();
+    let new_repo = Path::new(&new.path.to_string()).to_path_buf();
+    let old_commit = git::rev_parse(&old_repo, old_rev).await?;
+    let new_commit = git::rev_parse(&new_repo, new_rev).await?;
+    for (repository, commit) in [(old, &old_commit), (new, &new_commit)] {
+        let analysed: i64 = sqlx::query_scalar(SELECT_ANALYSED_QUERY)
+            .bind(repository.id.to_string())
+            .bind(commit)
+            .fetch_one(pool)
+            .await?;
+        if analysed == 0 {
+            return Err(Err::NotAnalysed.into());
+        }
+    }
+
+    let included = |files: Vec<FileCount>| -> Vec<FileCount> {
+        files
+            .into_iter()
+            .filter(|v| v.exclusion.is_none())
+            .collect()
+    };
+    let old_files = included(count_commit(pool, old, &old_commit).await?.0);
+    let new_files = included(count_commit(pool, new, &new_commit).await?.0);
+
+    let changes = match old.id == new.id {
+        true => git::diff_name_status(&new_repo, &old_commit, &new_commit).await?,
+        false => path_changes(&old_files, &new_files),
+    };
+    let old_by_path: HashMap<&str, &FileCount> =
+        old_files.iter().map(|v| (v.path.as_str(), v)).collect();
+    let new_by_path: HashMap<&str, &FileCount> =
+        new_files.iter().map(|v| (v.path.as_str(), v)).collect();
+    let mut files = Vec::new();
+    for change in changes {
+        let (status, old_path, new_path) = match change {
+            git::Change::Added(path) => (Status::Added, None, Some(path)),
+            git::Change::Deleted(path) => (Status::Deleted, Some(path), None),
+            git::Change::Modified(path) => (Status::Modified, Some(path.clone()), Some(path)),
+            git::Change::Renamed(from, to) => (Status::Renamed, Some(from), Some(to)),
+        };
+        let old_file = old_path.as_deref().and_then(|v| old_by_path.get(v));
+        let new_file = new_path.as_deref().and_then(|v| new_by_path.get(v));
+        // unrecognised and excluded files are not compared
+        let language = match new_file.or(old_file) {
+            None => continue,
+            Some(v) => v.language.clone(),
+        };
+        files.push(FileDelta {
+            old_path: match status {
+                Status::Renamed => old_path.clone(),
+                _ => None,
+            },
+            path: new_path.or(old_path).unwrap_or_default(),
+            status,
+            language,
+            old: old_file.map(|v| v.counts).unwrap_or_default(),
+            new: new_file.map(|v| v.counts).unwrap_or_default(),
+        });
+    }
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let mut languages: BTreeMap<String, LanguageDelta> = BTreeMap::new();
+    for (added, totals) in [(false, totals(&old_files)), (true, totals(&new_files))] {
+        for language_totals in totals {
+            let delta = languages
+                .entry(language_totals.language.clone())
+                .or_insert_with(|| LanguageDelta {
+                    language: language_totals.language.clone(),
+                    files: 0,
+                    counts: loc::Counts::default(),
+                });
+            if added {
+                delta.files += language_totals.files;
+                delta.counts += language_totals.counts;
+            } else {
+                delta.files -= language_totals.files;
+                delta.counts = delta.counts - language_totals.counts;
+            }
+        }
+    }
+
+    Ok(Diff {
+        old_commit,
+        new_commit,
+        languages: languages
+            .into_values()
+            .filter(|v| v.files != 0 || v.counts != loc::Counts::default())
+            .collect(),
+        files,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grokloc::app::admin::settings;
+    use crate::grokloc::app::analysis::Analysis;
+    use crate::grokloc::app::state;
+    use crate::grokloc::safe;
+
+    #[test]
+    fn path_changes_test() {
+        let file = |path: &str, blob: &str| FileCount {
+            path: path.to_string(),
+            blob: blob.to_string(),
+            language: "Rust".to_string(),
+            counts: loc::Counts::default(),
+            metrics: None,
+            exclusion: None,
+        };
+        let old = vec![
+            file("a.rs", "1"),
+            file("b.rs", "1"),
+            file("c.rs", "2"),
+            file("d.rs", "3"),
+        ];
+        let new = vec![file("c.rs", "4"), file("d.rs", "3"), file("e.rs", "1")];
+        // a copy of a blob renames the first of its deleted files
+        assert_eq!(
+            vec![
+                git::Change::Modified("c.rs".to_string()),
+                git::Change::Renamed("a.rs".to_string(), "e.rs".to_string()),
+                git::Change::Deleted("b.rs".to_string()),
+            ],
+            path_changes(&old, &new)
+        );
+    }
+
+    #[tokio::test]
+    async fn diff_test() -> Result<(), anyhow::Error> {
+        let app = state::unit().await?;
+        let author = ("A", "a@example.com");
+        let body = "fn a() {}\nfn b() {}\nfn c() {}\nfn d() {}\n";
+
+        let (old, old_repo) = git::fixture::repository(&app).await?;
+        old_repo.write("src/lib.rs", body);
+        old_repo.write("run.py", "print(1)\n");
+        old_repo.write("gone.py", "print(2)\n");
+        old_repo.write("README", "not counted\n");
+        let first = old_repo.commit(author, 1_600_000_000);
+        Analysis::run(&app.master_pool, &old, &first).await?;
+
+        old_repo.git(&["mv", "src/lib.rs", "src/core.rs"], &[]);
+        old_repo.write("src/core.rs", &format!("{}// e\n", body));
+        old_repo.write("run.py", "print(1)\n\nprint(3)\n");
+        old_repo.remove("gone.py");
+        old_repo.write("new.rs", "fn new() {}\n");
+        old_repo.write("README", "changed\n");
+        let second = old_repo.commit(author, 1_600_000_100);
+
+        // the new commit is not analysed yet
+        match diff(&app.master_pool, &old, &first, &old, &second).await {
+            Ok(_) => unreachable!(),
+            Err(e) => assert_eq!(Some(&Err::NotAnalysed), e.downcast_ref::<Err>()),
+        };
+        Analysis::run(&app.master_pool, &old, &second).await?;
+
+        // within a repository, git finds the edited rename
+        let report = diff(&app.master_pool, &old, &first, &old, "HEAD").await?;
+        assert_eq!(first, report.old_commit);
+        assert_eq!(second, report.new_commit);
+        let files: Vec<_> = report
+            .files
+            .iter()
+            .map(|v| {
+                (
+                    v.path.as_str(),
+                    v.old_path.as_deref(),
+                    v.status,
+                    v.delta().lines(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                ("gone.py", None, Status::Deleted, -1),
+                ("new.rs", None, Status::Added, 1),
+                ("run.py", None, Status::Modified, 2),
+                ("src/core.rs", Some("src/lib.rs"), Status::Renamed, 1),
+            ],
+            files
+        );
+        assert_eq!(
+            vec![
+                LanguageDelta {
+                    language: "Python".to_string(),
+                    files: -1,
+                    counts: loc::Counts {
+                        code: 0,
+                        comment: 0,
+                        blank: 1
+                    }
+                },
+                LanguageDelta {
+                    language: "Rust".to_string(),
+                    files: 1,
+                    counts: loc::Counts {
+                        code: 1,
+                        comment: 1,
+                        blank: 0
+                    }
+                },
+            ],
+            report.languages
+        );
+
+        // a later ignore list changes language and file deltas alike
+        settings::set(
+            &app.master_pool,
+            &app.root_user.id,
+            &app.root_org.id,
+            settings::Key::IgnorePatterns,
+            &settings::Value::Text(safe::VarChar::trusted("*.py")),
+        )
+        .await?;
+        let ignored = diff(&app.master_pool, &old, &first, &old, &second).await?;
+        assert_eq!(report.languages[1..].to_vec(), ignored.languages);
+        assert!(ignored.files.iter().all(|v| v.language == "Rust"));
+        assert_eq!(2, ignored.files.len());
+        let mut summed = loc::Counts::default();
+        for file in &ignored.files {
+            summed += file.delta();
+        }
+        assert_eq!(summed, ignored.languages[0].counts);
+        settings::set(
+            &app.master_pool,
+            &app.root_user.id,
+            &app.root_org.id,
+            settings::Key::IgnorePatterns,
+            &settings::Value::Text(safe::VarChar::trusted("")),
+        )
+        .await?;
+
+        // between repositories, identical content that moved is a rename
+        let (new, new_repo) = git::fixture::repository(&app).await?;
+        new_repo.write("lib/lib.rs", body);
+        new_repo.write("run.py", "print(1)\n");
+        new_repo.write("gone.py", "print(2)\n");
+        let third = new_repo.commit(author, 1_600_000_200);
+        Analysis::run(&app.master_pool, &new, &third).await?;
+        let report = diff(&app.master_pool, &old, &first, &new, &third).await?;
+        let files: Vec<_> = report
+            .files
+            .iter()
+            .map(|v| (v.path.as_str(), v.old_path.as_deref(), v.status))
+            .collect();
+        assert_eq!(
+            vec![("lib/lib.rs", Some("src/lib.rs"), Status::Renamed)],
+            files
+        );
+        assert!(report.languages.is_empty());
+
+        Ok(())
+    }
+}
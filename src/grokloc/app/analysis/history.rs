@@ -0,0 +1,261 @@
+//! history samples the first-parent history of a repository to chart how
+//! its line counts change over time
+use crate::grokloc::app::admin::repository::Repository;
+use crate::grokloc::app::analysis::{Analysis, LanguageTotals};
+use crate::grokloc::git;
+use crate::grokloc::loc;
+use anyhow;
+use sqlx;
+use std::path::Path;
+use uuid::Uuid;
+
+pub const SCHEMA_VERSION: i8 = 0;
+
+pub const SELECT_POINT_QUERY: &str = r#"
+select count(*) from history_points where repository = ? and commit_id = ?
+"#;
+
+pub const INSERT_POINT_QUERY: &str = r#"
+insert into history_points
+(id,
+ repository,
+ commit_id,
+ commit_time,
+ schema_version)
+values
+(?,?,?,?,?)
+on conflict (repository, commit_id) do nothing
+"#;
+
+pub const SELECT_SERIES_QUERY: &str = r#"
+select
+ p.commit_id,
+ p.commit_time,
+ c.language,
+ c.files,
+ c.code,
+ c.comment,
+ c.blank
+from history_points p
+left join commit_counts c
+ on c.repository = p.repository and c.commit_id = p.commit_id and c.exclusion = 0
+where p.repository = ?
+and p.commit_time >= ?
+and p.commit_time < ?
+order by p.commit_time, p.commit_id, c.language
+"#;
+
+const DAY_SECONDS: i64 = 86400;
+
+/// Sampling selects which commits of the history become points
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[allow(dead_code)]
+pub enum Sampling {
+    /// every commit
+    Every,
+    /// the last commit of each UTC day
+    Daily,
+    /// the last commit of each week, starting Monday UTC
+    Weekly,
+}
+
+impl Sampling {
+    /// bucket is the period containing time; commits sharing a bucket
+    /// are represented by the last of them
+    fn bucket(self, index: usize, time: i64) -> i64 {
+        match self {
+            Sampling::Every => index as i64,
+            Sampling::Daily => time.div_euclid(DAY_SECONDS),
+            // the epoch was a Thursday
+            Sampling::Weekly => (time.div_euclid(DAY_SECONDS) + 3).div_euclid(7),
+        }
+    }
+}
+
+/// Point is the per-language totals of a sampled commit
+#[derive(Clone, Debug, PartialEq)]
+pub struct Point {
+    pub commit: String,
+    pub time: chrono::DateTime<chrono::Utc>,
+    pub totals: Vec<LanguageTotals>,
+}
+
+/// sample selects from commits, oldest first, those that are points under sampling
+pub fn sample(commits: &[(String, i64)], sampling: Sampling) -> Vec<(String, i64)> {
+    let mut samples: Vec<(i64, (String, i64))> = Vec::new();
+    for (index, commit) in commits.iter().enumerate() {
+        let bucket = sampling.bucket(index, commit.1);
+        match samples.last_mut() {
+            Some(last) if last.0 == bucket => last.1 = commit.clone(),
+            _ => samples.push((bucket, commit.clone())),
+        }
+    }
+    samples.into_iter().map(|v| v.1).collect()
+}
+
+/// run samples the first-parent history of rev in the local clone of
+/// repository, analysing and storing a point for each sampled commit
+///
+/// commits already stored as points are not analysed again; returns the
+/// sampled points, oldest first
+#[allow(dead_code)]
+pub async fn run(
+    pool: &sqlx::SqlitePool,
+    repository: &Repository,
+    rev: &str,
+    sampling: Sampling,
+) -> Result<Vec<Point>, anyhow::Error> {
+    let repo = Path::new(&repository.path.to_string()).to_path_buf();
+    let commit = git::rev_parse(&repo, rev).await?;
+    let commits = git::first_parent(&repo, &commit).await?;
+
+    let mut points = Vec::new();
+    for (commit, time) in sample(&commits, sampling) {
+        let stored: i64 = sqlx::query_scalar(SELECT_POINT_QUERY)
+            .bind(repository.id.to_string())
+            .bind(&commit)
+            .fetch_one(pool)
+            .await?;
+        let totals = match stored {
+            0 => {
+                let analysis = Analysis::run(pool, repository, &commit).await?;
+                sqlx::query(INSERT_POINT_QUERY)
+                    .bind(Uuid::new_v4().to_string())
+                    .bind(repository.id.to_string())
+                    .bind(&commit)
+                    .bind(time)
+                    .bind(SCHEMA_VERSION)
+                    .execute(pool)
+                    .await?;
+                analysis.totals
+            }
+            _ => Analysis::read(pool, &repository.id, &commit).await?,
+        };
+        points.push(Point {
+            commit,
+            time: chrono::DateTime::from_timestamp(time, 0).unwrap_or_default(),
+            totals,
+        });
+    }
+    Ok(points)
+}
+
+/// series selects the stored points of repository with commit times in
+/// [from, to), oldest first
+///
+/// points from every sampling run are included
+#[allow(dead_code)]
+pub async fn series(
+    pool: &sqlx::SqlitePool,
+    repository: &Uuid,
+    from: chrono::DateTime<chrono::Utc>,
+    to: chrono::DateTime<chrono::Utc>,
+) -> Result<Vec<Point>, anyhow::Error> {
+    type Row = (
+        String,
+        i64,
+        Option<String>,
+        Option<i64>,
+        Option<i64>,
+        Option<i64>,
+        Option<i64>,
+    );
+    let rows: Vec<Row> = sqlx::query_as(SELECT_SERIES_QUERY)
+        .bind(repository.to_string())
+        .bind(from.timestamp())
+        .bind(to.timestamp())
+        .fetch_all(pool)
+        .await?;
+
+    let mut points: Vec<Point> = Vec::new();
+    for (commit, time, language, files, code, comment, blank) in rows {
+        if points.last().map(|v| &v.commit) != Some(&commit) {
+            points.push(Point {
+                commit,
+                time: chrono::DateTime::from_timestamp(time, 0).unwrap_or_default(),
+                totals: Vec::new(),
+            });
+        }
+        if let (Some(language), Some(point)) = (language, points.last_mut()) {
+            point.totals.push(LanguageTotals {
+                language,
+                files: files.unwrap_or_default(),
+                counts: loc::Counts {
+                    code: code.unwrap_or_default(),
+                    comment: comment.unwrap_or_default(),
+                    blank: blank.unwrap_or_default(),
+                },
+            });
+        }
+    }
+    Ok(points)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grokloc::app::state;
+
+    // Sunday 2020-09-13 12:26:40 UTC
+    const SUNDAY: i64 = 1_600_000_000;
+
+    #[test]
+    fn history_sample_test() {
+        let times = [
+            SUNDAY,
+            SUNDAY + 100,
+            SUNDAY + DAY_SECONDS,
+            SUNDAY + 2 * DAY_SECONDS,
+            SUNDAY + 8 * DAY_SECONDS,
+        ];
+        let commits: Vec<(String, i64)> = times
+            .iter()
+            .enumerate()
+            .map(|(i, t)| (i.to_string(), *t))
+            .collect();
+        let ids = |sampling| {
+            sample(&commits, sampling)
+                .into_iter()
+                .map(|v| v.0)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(vec!["0", "1", "2", "3", "4"], ids(Sampling::Every));
+        assert_eq!(vec!["1", "2", "3", "4"], ids(Sampling::Daily));
+        assert_eq!(vec!["1", "3", "4"], ids(Sampling::Weekly));
+        assert!(sample(&[], Sampling::Daily).is_empty());
+    }
+
+    #[tokio::test]
+    async fn history_run_series_test() -> Result<(), anyhow::Error> {
+        let app = state::unit().await?;
+        let (repository, repo) = git::fixture::repository(&app).await?;
+        let author = ("A", "a@example.com");
+        let mut source = String::new();
+        for day in 0..3 {
+            for _ in 0..2 {
+                source.push_str("let x = 1;\n");
+                repo.write("a.rs", &source);
+                repo.commit(author, SUNDAY + day * DAY_SECONDS + source.len() as i64);
+            }
+        }
+
+        let points = run(&app.master_pool, &repository, "HEAD", Sampling::Daily).await?;
+        assert_eq!(3, points.len());
+        let code: Vec<i64> = points.iter().map(|v| v.totals[0].counts.code).collect();
+        assert_eq!(vec![2, 4, 6], code);
+
+        // every commit adds to the stored points
+        let points = run(&app.master_pool, &repository, "HEAD", Sampling::Every).await?;
+        assert_eq!(6, points.len());
+
+        let from = chrono::DateTime::from_timestamp(SUNDAY + DAY_SECONDS, 0).unwrap_or_default();
+        let to = chrono::DateTime::from_timestamp(SUNDAY + 2 * DAY_SECONDS, 0).unwrap_or_default();
+        let series_points = series(&app.master_pool, &repository.id, from, to).await?;
+        assert_eq!(points[2..4].to_vec(), series_points);
+        assert!(series(&app.master_pool, &Uuid::new_v4(), from, to)
+            .await?
+            .is_empty());
+
+        Ok(())
+    }
+}
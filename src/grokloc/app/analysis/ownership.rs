@@ -0,0 +1,302 @@
+//! ownership attributes the surviving lines of each directory of a commit
+//! to their authors with git blame, to find code only one person knows
+//!
+//! the bus factor of a directory is the fewest authors that together last
+//! changed at least half of its lines; authors are stored as in authors,
+//! which also names them: an author first seen here is named as in its
+//! newest blamed line, until authors::run renames it
+use crate::grokloc::app::admin::repository::Repository;
+use crate::grokloc::app::analysis::authors::{self, Author};
+use crate::grokloc::app::analysis::breakdown::ROOT_DIRECTORY;
+use crate::grokloc::app::analysis::count_commit;
+use crate::grokloc::git;
+use crate::grokloc::safe;
+use anyhow;
+use sqlx;
+use sqlx::Row;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use uuid::Uuid;
+
+pub const SCHEMA_VERSION: i8 = 0;
+
+pub const DELETE_QUERY: &str = r#"
+delete from directory_owners where repository = ? and commit_id = ?
+"#;
+
+pub const INSERT_QUERY: &str = r#"
+insert into directory_owners
+(id,
+ repository,
+ commit_id,
+ directory,
+ author,
+ lines,
+ schema_version)
+values
+(?,?,?,?,?,?,?)
+"#;
+
+pub const SELECT_QUERY: &str = r#"
+select
+ d.directory,
+ d.lines,
+ a.id,
+ a.name,
+ a.email,
+ a.email_digest,
+ a.user
+from directory_owners d
+join authors a on a.id = d.author
+where d.repository = ? and d.commit_id = ?
+order by d.directory
+"#;
+
+/// Share is the lines of a directory last changed by an author
+#[derive(Clone, Debug, PartialEq)]
+pub struct Share {
+    pub author: Author,
+    pub lines: i64,
+}
+
+/// Ownership is the shares of the lines of a directory and all of its
+/// subdirectories
+#[derive(Clone, Debug, PartialEq)]
+pub struct Ownership {
+    /// the path of the directory, or ROOT_DIRECTORY
+    pub directory: String,
+    pub lines: i64,
+    /// ordered by lines descending, then email
+    pub shares: Vec<Share>,
+    pub bus_factor: i64,
+}
+
+/// directories lists the directories containing path, root first
+pub fn directories(path: &str) -> Vec<&str> {
+    let mut directories = vec![ROOT_DIRECTORY];
+    directories.extend(
+        path.match_indices('/')
+            .map(|(i, _)| &path[..i])
+            .filter(|v| !v.is_empty()),
+    );
+    directories
+}
+
+/// bus_factor is the fewest of the shares, largest first, covering at
+/// least half of the lines
+pub fn bus_factor(shares: &[Share]) -> i64 {
+    let mut lines: Vec<i64> = shares.iter().map(|v| v.lines).collect();
+    lines.sort_unstable_by(|a, b| b.cmp(a));
+    let total: i64 = lines.iter().sum();
+    let mut covered = 0;
+    for (i, v) in lines.iter().enumerate() {
+        covered += v;
+        if covered * 2 >= total {
+            return i as i64 + 1;
+        }
+    }
+    0
+}
+
+/// at_risk lists the directories known to a single author
+#[allow(dead_code)]
+pub fn at_risk(ownership: &[Ownership]) -> Vec<&Ownership> {
+    ownership.iter().filter(|v| v.bus_factor == 1).collect()
+}
+
+/// run blames the included files of the commit rev of the local clone of
+/// repository, replacing any ownership stored for the commit, and returns
+/// the ownership of each directory (see read)
+///
+/// unrecognised and excluded files are not blamed; each file is blamed
+/// with its own git process
+#[allow(dead_code)]
+pub async fn run(
+    pool: &sqlx::SqlitePool,
+    repository: &Repository,
+    rev: &str,
+    key: &str,
+) -> Result<Vec<Ownership>, anyhow::Error> {
+    let repo = Path::new(&repository.path.to_string()).to_path_buf();
+    let commit = git::rev_parse(&repo, rev).await?;
+    let (files, _, _) = count_commit(pool, repository, &commit).await?;
+
+    // the name of the newest blamed line of each email
+    let mut names: HashMap<String, (i64, String)> = HashMap::new();
+    let mut lines: BTreeMap<(String, String), i64> = BTreeMap::new();
+    for file in files.iter().filter(|v| v.exclusion.is_none()) {
+        for blamed in git::blame(&repo, &commit, &file.path).await? {
+            let email = safe::email_normal(&blamed.author_email);
+            for directory in directories(&file.path) {
+                *lines
+                    .entry((directory.to_string(), email.clone()))
+                    .or_default() += blamed.lines;
+            }
+            let name = names
+                .entry(email)
+                .or_insert((blamed.author_time, blamed.author_name.clone()));
+            if blamed.author_time > name.0 {
+                *name = (blamed.author_time, blamed.author_name);
+            }
+        }
+    }
+
+    let mut txn = pool.begin().await?;
+    sqlx::query(DELETE_QUERY)
+        .bind(repository.id.to_string())
+        .bind(&commit)
+        .execute(&mut txn)
+        .await?;
+    let mut ids: HashMap<&str, String> = HashMap::new();
+    for (email, (_, name)) in &names {
+        let id = authors::insert(&mut txn, &repository.id, name, email, key).await?;
+        ids.insert(email, id);
+    }
+    for ((directory, email), count) in &lines {
+        sqlx::query(INSERT_QUERY)
+            .bind(Uuid::new_v4().to_string())
+            .bind(repository.id.to_string())
+            .bind(&commit)
+            .bind(directory)
+            .bind(&ids[email.as_str()])
+            .bind(count)
+            .bind(SCHEMA_VERSION)
+            .execute(&mut txn)
+            .await?;
+    }
+    txn.commit().await?;
+
+    read(pool, &repository.id, &commit, key).await
+}
+
+/// read selects and decrypts the stored ownership of commit of repository,
+/// ordered by directory
+#[allow(dead_code)]
+pub async fn read(
+    pool: &sqlx::SqlitePool,
+    repository: &Uuid,
+    commit: &str,
+    key: &str,
+) -> Result<Vec<Ownership>, anyhow::Error> {
+    let rows = sqlx::query(SELECT_QUERY)
+        .bind(repository.to_string())
+        .bind(commit)
+        .fetch_all(pool)
+        .await?;
+
+    let mut by_directory: BTreeMap<String, Vec<Share>> = BTreeMap::new();
+    for row in rows {
+        by_directory
+            .entry(row.try_get::<String, _>("directory")?)
+            .or_default()
+            .push(Share {
+                author: authors::decrypt(&row, key)?,
+                lines: row.try_get::<i64, _>("lines")?,
+            });
+    }
+    Ok(by_directory
+        .into_iter()
+        .map(|(directory, mut shares)| {
+            shares.sort_by(|a, b| {
+                b.lines
+                    .cmp(&a.lines)
+                    .then_with(|| a.author.email.to_string().cmp(&b.author.email.to_string()))
+            });
+            Ownership {
+                directory,
+                lines: shares.iter().map(|v| v.lines).sum(),
+                bus_factor: bus_factor(&shares),
+                shares,
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grokloc::app::state;
+
+    #[test]
+    fn directories_test() {
+        assert_eq!(vec!["."], directories("a.rs"));
+        assert_eq!(vec![".", "a", "a/b"], directories("a/b/c.rs"));
+    }
+
+    #[tokio::test]
+    async fn ownership_test() -> Result<(), anyhow::Error> {
+        let app = state::unit().await?;
+        let (repository, repo) = git::fixture::repository(&app).await?;
+        repo.write("core/a.rs", "fn a() {}\nfn b() {}\nfn c() {}\nfn d() {}\n");
+        repo.write("main.rs", "fn main() {}\n");
+        repo.write("web/x.js", "x();\n");
+        repo.commit(("Ann", "ann@example.com"), 1_600_000_000);
+        repo.write("core/a.rs", "fn a() {}\nfn b() {}\nfn c() {}\nfn e() {}\n");
+        repo.write("web/b.js", "b();\nb();\n");
+        repo.commit(("Bob", "bob@example.com"), 1_600_000_100);
+        repo.write("web/c.js", "c();\nc();\n");
+        repo.write("vendor/v.rs", "fn v() {}\nfn v() {}\nfn v() {}\n");
+        repo.write("README", "not counted\n");
+        repo.commit(("Cat", "cat@example.com"), 1_600_000_200);
+        repo.write("README", "still not counted\n");
+        let head = repo.commit(("Ann Lee", "ann@example.com"), 1_600_000_300);
+
+        let ownership = run(&app.master_pool, &repository, "HEAD", &app.key).await?;
+        let summary: Vec<_> = ownership
+            .iter()
+            .map(|v| {
+                (
+                    v.directory.as_str(),
+                    v.lines,
+                    v.bus_factor,
+                    v.shares
+                        .iter()
+                        .map(|v| (v.author.name.to_string(), v.lines))
+                        .collect(),
+                )
+            })
+            .collect();
+        let shares = |v: &[(&str, i64)]| -> Vec<(String, i64)> {
+            v.iter()
+                .map(|(name, lines)| (name.to_string(), *lines))
+                .collect()
+        };
+        // vendored and unrecognised files are not attributed
+        assert_eq!(
+            vec![
+                (".", 10, 1, shares(&[("Ann", 5), ("Bob", 3), ("Cat", 2)])),
+                ("core", 4, 1, shares(&[("Ann", 3), ("Bob", 1)])),
+                ("web", 5, 2, shares(&[("Bob", 2), ("Cat", 2), ("Ann", 1)])),
+            ],
+            summary
+        );
+        assert_eq!(
+            "ann@example.com",
+            ownership[0].shares[0].author.email.to_string()
+        );
+        let risky: Vec<&str> = at_risk(&ownership)
+            .iter()
+            .map(|v| v.directory.as_str())
+            .collect();
+        assert_eq!(vec![".", "core"], risky);
+
+        assert_eq!(
+            ownership,
+            read(&app.master_pool, &repository.id, &head, &app.key).await?
+        );
+
+        // names given by authors::run, from the newest commit, are kept
+        authors::run(
+            &app.master_pool,
+            &repository,
+            "HEAD",
+            authors::Period::Month,
+            &app.key,
+        )
+        .await?;
+        let ownership = run(&app.master_pool, &repository, "HEAD", &app.key).await?;
+        assert_eq!("Ann Lee", ownership[0].shares[0].author.name.to_string());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,104 @@
+//! audit records changes to model rows in the audit table
+use anyhow;
+use sqlx;
+use uuid::Uuid;
+
+pub const SCHEMA_VERSION: i8 = 0;
+
+/// audit codes; grouped by hundreds per source table
+pub const USER_INSERT: i64 = 100;
+pub const USER_EMAIL: i64 = 101;
+pub const USER_API_SECRET: i64 = 102;
+pub const USER_ROLE: i64 = 103;
+pub const USER_ERASE: i64 = 104;
+pub const USER_ACTIVATE: i64 = 105;
+
+pub const ORG_OWNER: i64 = 200;
+pub const ORG_NAME: i64 = 201;
+pub const ORG_SETTING: i64 = 202;
+pub const ORG_STATUS: i64 = 203;
+pub const ORG_IMPORT: i64 = 204;
+
+pub const REPOSITORY_INSERT: i64 = 300;
+
+pub const INVITATION_INSERT: i64 = 400;
+pub const INVITATION_REVOKE: i64 = 401;
+pub const INVITATION_ACCEPT: i64 = 402;
+
+pub const TEAM_INSERT: i64 = 500;
+pub const TEAM_MEMBER: i64 = 501;
+pub const TEAM_GRANT: i64 = 502;
+pub const TEAM_DELETE: i64 = 503;
+
+pub const LANGUAGE_INSERT: i64 = 600;
+pub const LANGUAGE_UPDATE: i64 = 601;
+pub const LANGUAGE_DELETE: i64 = 602;
+
+pub const SUBPROJECT_INSERT: i64 = 700;
+pub const SUBPROJECT_UPDATE: i64 = 701;
+pub const SUBPROJECT_DELETE: i64 = 702;
+
+pub const INSERT_QUERY: &str = r#"
+insert into audit
+(id,
+ code,
+ source,
+ source_id,
+ schema_version)
+values
+(?,?,?,?,?)
+"#;
+
+/// insert adds an audit row for source_id in the source table, returning the audit id
+///
+/// assumed to be called within the transaction that performed the audited change
+pub async fn insert(
+    txn: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    code: i64,
+    source: &str,
+    source_id: &Uuid,
+) -> Result<Uuid, anyhow::Error> {
+    let id = Uuid::new_v4();
+    sqlx::query(INSERT_QUERY)
+        .bind(id.to_string())
+        .bind(code)
+        .bind(source)
+        .bind(source_id.to_string())
+        .bind(SCHEMA_VERSION)
+        .execute(txn)
+        .await?;
+    Ok(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grokloc::app::schema;
+
+    #[tokio::test]
+    async fn audit_insert_test() -> Result<(), anyhow::Error> {
+        // create the db
+        let pool: sqlx::SqlitePool = sqlx::sqlite::SqlitePoolOptions::new()
+            .connect("sqlite::memory:")
+            .await?;
+        sqlx::query(schema::APP_CREATE_SCHEMA_SQLITE)
+            .execute(&pool)
+            .await?;
+
+        let source_id = Uuid::new_v4();
+        let mut txn = pool.begin().await?;
+        let id = insert(&mut txn, USER_INSERT, schema::USERS_TABLENAME, &source_id).await?;
+        txn.commit().await?;
+
+        let (code, source, source_id_read): (i64, String, String) =
+            sqlx::query_as("select code, source, source_id from audit where id = ?")
+                .bind(id.to_string())
+                .fetch_one(&pool)
+                .await?;
+        assert_eq!(USER_INSERT, code);
+        assert_eq!(schema::USERS_TABLENAME, source);
+        assert_eq!(source_id.to_string(), source_id_read);
+
+        Ok(())
+    }
+}
@@ -1,13 +1,21 @@
 //! models contains cross-model definitions
 use chrono;
-use std::{default, fmt};
+use std::{default, fmt, str};
 use thiserror::Error;
+use uuid::Uuid;
+
+/// MAX_LIST_LIMIT caps the page size of any listing
+pub const MAX_LIST_LIMIT: i64 = 1000;
 
 /// Err covers various generic model errors
 #[derive(Debug, Error, PartialEq)]
 pub enum Err {
     #[error("unknown status")]
     UnknownStatus,
+    #[error("malformed cursor")]
+    BadCursor,
+    #[error("rows are only deleted by erasure and never change after")]
+    Deleted,
 }
 
 /// Status describes model status
@@ -17,6 +25,8 @@ pub enum Status {
     Unconfirmed,
     Active,
     Inactive,
+    /// an erased row retained as a tombstone for referential integrity
+    Deleted,
 }
 
 impl fmt::Display for Status {
@@ -33,6 +43,7 @@ impl Status {
             Status::Unconfirmed => 1,
             Status::Active => 2,
             Status::Inactive => 3,
+            Status::Deleted => 4,
         }
     }
 
@@ -43,6 +54,7 @@ impl Status {
             1 => Ok(Status::Unconfirmed),
             2 => Ok(Status::Active),
             3 => Ok(Status::Inactive),
+            4 => Ok(Status::Deleted),
             _ => Err(Err::UnknownStatus),
         }
     }
@@ -61,14 +73,8 @@ pub struct Meta {
 impl default::Default for Meta {
     fn default() -> Self {
         Meta {
-            ctime: chrono::DateTime::from_utc(
-                chrono::NaiveDateTime::from_timestamp(0, 0),
-                chrono::Utc,
-            ),
-            mtime: chrono::DateTime::from_utc(
-                chrono::NaiveDateTime::from_timestamp(0, 0),
-                chrono::Utc,
-            ),
+            ctime: chrono::DateTime::UNIX_EPOCH,
+            mtime: chrono::DateTime::UNIX_EPOCH,
             schema_version: 0,
             status: Status::Unconfirmed,
         }
@@ -79,16 +85,97 @@ impl Meta {
     #[allow(dead_code)]
     pub fn from_db(ctime: i64, mtime: i64, schema_version: i8, status: i64) -> Result<Meta, Err> {
         Ok(Meta {
-            ctime: chrono::DateTime::from_utc(
-                chrono::NaiveDateTime::from_timestamp(ctime, 0),
-                chrono::Utc,
-            ),
-            mtime: chrono::DateTime::from_utc(
-                chrono::NaiveDateTime::from_timestamp(mtime, 0),
-                chrono::Utc,
-            ),
+            ctime: chrono::DateTime::from_timestamp(ctime, 0).unwrap_or_default(),
+            mtime: chrono::DateTime::from_timestamp(mtime, 0).unwrap_or_default(),
             schema_version,
             status: Status::from_int(status)?,
         })
     }
 }
+
+/// Cursor marks a position in a listing ordered by (ctime, id)
+///
+/// listings return rows strictly after the cursor, so rows inserted
+/// concurrently with paging do not shift or repeat the pages that follow;
+/// the string form is opaque to callers and can be passed through tooling
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cursor {
+    pub ctime: i64,
+    pub id: Uuid,
+}
+
+impl Cursor {
+    /// bind_values returns the (ctime, id) pair to bind into a listing
+    /// query, where None starts from the beginning
+    pub fn bind_values(cursor: Option<&Cursor>) -> (i64, String) {
+        match cursor {
+            Some(c) => (c.ctime, c.id.to_string()),
+            None => (i64::MIN, String::new()),
+        }
+    }
+}
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}", self.ctime, self.id)
+    }
+}
+
+impl str::FromStr for Cursor {
+    type Err = Err;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (ctime, id) = s.split_once('.').ok_or(Err::BadCursor)?;
+        Ok(Cursor {
+            ctime: ctime.parse::<i64>().map_err(|_| Err::BadCursor)?,
+            id: Uuid::try_parse(id).map_err(|_| Err::BadCursor)?,
+        })
+    }
+}
+
+/// limit_ok clamps a requested page size to [1, MAX_LIST_LIMIT]
+pub fn limit_ok(limit: i64) -> i64 {
+    limit.clamp(1, MAX_LIST_LIMIT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_int_test() -> Result<(), Err> {
+        for status in [
+            Status::Unconfirmed,
+            Status::Active,
+            Status::Inactive,
+            Status::Deleted,
+        ] {
+            assert_eq!(status, Status::from_int(status.to_int())?);
+        }
+        assert_eq!(Err::UnknownStatus, Status::from_int(0).unwrap_err());
+        Ok(())
+    }
+
+    #[test]
+    fn cursor_string_test() -> Result<(), Err> {
+        let cursor = Cursor {
+            ctime: 1_660_000_000,
+            id: Uuid::new_v4(),
+        };
+        assert_eq!(cursor, cursor.to_string().parse::<Cursor>()?);
+        assert_eq!(Err::BadCursor, "".parse::<Cursor>().unwrap_err());
+        assert_eq!(Err::BadCursor, "x.y".parse::<Cursor>().unwrap_err());
+        assert_eq!(
+            Err::BadCursor,
+            format!("1.{}", "z").parse::<Cursor>().unwrap_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn limit_ok_test() {
+        assert_eq!(1, limit_ok(0));
+        assert_eq!(10, limit_ok(10));
+        assert_eq!(MAX_LIST_LIMIT, limit_ok(MAX_LIST_LIMIT + 1));
+    }
+}
@@ -0,0 +1,98 @@
+//! outbox queues notifications for delivery outside of the db transaction
+//! that produced them
+use crate::grokloc::crypt;
+use crate::grokloc::safe;
+use anyhow;
+use sqlx;
+use uuid::Uuid;
+
+pub const SCHEMA_VERSION: i8 = 0;
+
+/// notification codes
+pub const EMAIL_CHANGED: i64 = 100;
+
+pub const INSERT_QUERY: &str = r#"
+insert into outbox
+(id,
+ code,
+ recipient,
+ recipient_digest,
+ user,
+ schema_version)
+values
+(?,?,?,?,?,?)
+"#;
+
+/// insert queues a notification of type code for recipient about user,
+/// returning the outbox id
+///
+/// the recipient is PII, so it is encrypted with key and salt derived from its digest;
+/// user keeps the notification reachable once the recipient is no longer its email
+pub async fn insert(
+    txn: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    code: i64,
+    recipient: &safe::VarChar,
+    user: &Uuid,
+    key: &str,
+) -> Result<Uuid, anyhow::Error> {
+    let id = Uuid::new_v4();
+    let recipient_digest = crypt::sha256_hex(&recipient.to_string());
+    let iv = crypt::iv(&recipient_digest);
+    sqlx::query(INSERT_QUERY)
+        .bind(id.to_string())
+        .bind(code)
+        .bind(crypt::encrypt(key, &iv, &recipient.to_string())?)
+        .bind(&recipient_digest)
+        .bind(user.to_string())
+        .bind(SCHEMA_VERSION)
+        .execute(txn)
+        .await?;
+    Ok(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grokloc::app::schema;
+
+    #[tokio::test]
+    async fn outbox_insert_test() -> Result<(), anyhow::Error> {
+        // create the db
+        let pool: sqlx::SqlitePool = sqlx::sqlite::SqlitePoolOptions::new()
+            .connect("sqlite::memory:")
+            .await?;
+        sqlx::query(schema::APP_CREATE_SCHEMA_SQLITE)
+            .execute(&pool)
+            .await?;
+
+        let key = crypt::rand_key();
+        let recipient = safe::VarChar::rand();
+        let user = Uuid::new_v4();
+        let mut txn = pool.begin().await?;
+        let id = insert(&mut txn, EMAIL_CHANGED, &recipient, &user, &key).await?;
+        txn.commit().await?;
+
+        let (code, encrypted_recipient, recipient_digest, user_id, delivered): (
+            i64,
+            String,
+            String,
+            String,
+            i64,
+        ) = sqlx::query_as(
+            "select code, recipient, recipient_digest, user, delivered from outbox where id = ?",
+        )
+        .bind(id.to_string())
+        .fetch_one(&pool)
+        .await?;
+        assert_eq!(EMAIL_CHANGED, code);
+        assert_eq!(user.to_string(), user_id);
+        assert_eq!(crypt::sha256_hex(&recipient.to_string()), recipient_digest);
+        assert_eq!(
+            recipient.to_string(),
+            crypt::decrypt(&key, &crypt::iv(&recipient_digest), &encrypted_recipient)?
+        );
+        assert_eq!(0, delivered);
+
+        Ok(())
+    }
+}
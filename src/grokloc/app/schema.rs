@@ -6,6 +6,24 @@ pub const ORGS_TABLENAME: &str = "orgs";
 #[allow(dead_code)]
 pub const USERS_TABLENAME: &str = "users";
 
+#[allow(dead_code)]
+pub const REPOSITORIES_TABLENAME: &str = "repositories";
+
+#[allow(dead_code)]
+pub const TEAMS_TABLENAME: &str = "teams";
+
+#[allow(dead_code)]
+pub const OUTBOX_TABLENAME: &str = "outbox";
+
+#[allow(dead_code)]
+pub const INVITATIONS_TABLENAME: &str = "invitations";
+
+#[allow(dead_code)]
+pub const LANGUAGES_TABLENAME: &str = "languages";
+
+#[allow(dead_code)]
+pub const SUBPROJECTS_TABLENAME: &str = "subprojects";
+
 pub static APP_CREATE_SCHEMA_SQLITE: &str = r#"
 create table if not exists users (
        api_secret text unique not null,
@@ -17,6 +35,7 @@ create table if not exists users (
        email_digest text not null,
        org text not null,
        password text not null,
+       role integer not null default 3,
        schema_version integer not null default 0,
        status integer not null,
        ctime integer,
@@ -25,6 +44,10 @@ create table if not exists users (
 -- STMT
 create unique index if not exists users_email_org on users (email_digest, org);
 -- STMT
+create unique index if not exists users_org_owner on users (org) where role = 1;
+-- STMT
+create index if not exists users_org_ctime on users (org, ctime, id);
+-- STMT
 create trigger if not exists users_ctime_trigger after insert on users
 begin
         update users set
@@ -39,6 +62,58 @@ begin
         where id = new.id;
 end;
 -- STMT
+create table if not exists api_secret_grace (
+       id text unique not null,
+       user text not null,
+       api_secret_digest text unique not null,
+       expires integer not null,
+       schema_version integer not null default 0,
+       ctime integer,
+       mtime integer,
+       primary key (id));
+-- STMT
+create index if not exists api_secret_grace_user on api_secret_grace (user);
+-- STMT
+create trigger if not exists api_secret_grace_ctime_trigger after insert on api_secret_grace
+begin
+        update api_secret_grace set
+        ctime = strftime('%s','now'),
+        mtime = strftime('%s','now')
+        where id = new.id;
+end;
+-- STMT
+create trigger if not exists api_secret_grace_mtime_trigger after update on api_secret_grace
+begin
+        update api_secret_grace set mtime = strftime('%s','now')
+        where id = new.id;
+end;
+-- STMT
+create table if not exists user_tokens (
+       id text unique not null,
+       user text not null,
+       token_digest text unique not null,
+       expires integer not null,
+       schema_version integer not null default 0,
+       ctime integer,
+       mtime integer,
+       primary key (id));
+-- STMT
+create index if not exists user_tokens_user on user_tokens (user);
+-- STMT
+create trigger if not exists user_tokens_ctime_trigger after insert on user_tokens
+begin
+        update user_tokens set
+        ctime = strftime('%s','now'),
+        mtime = strftime('%s','now')
+        where id = new.id;
+end;
+-- STMT
+create trigger if not exists user_tokens_mtime_trigger after update on user_tokens
+begin
+        update user_tokens set mtime = strftime('%s','now')
+        where id = new.id;
+end;
+-- STMT
 create table if not exists orgs (
        id text unique not null,
        name text unique not null,
@@ -63,6 +138,91 @@ begin
         where id = new.id;
 end;
 -- STMT
+create table if not exists status_cascade (
+       id text unique not null,
+       org text not null,
+       source text not null,
+       source_id text not null,
+       prior_status integer not null,
+       schema_version integer not null default 0,
+       ctime integer,
+       mtime integer,
+       primary key (id));
+-- STMT
+create unique index if not exists status_cascade_org_source on status_cascade (org, source, source_id);
+-- STMT
+create trigger if not exists status_cascade_ctime_trigger after insert on status_cascade
+begin
+        update status_cascade set
+        ctime = strftime('%s','now'),
+        mtime = strftime('%s','now')
+        where id = new.id;
+end;
+-- STMT
+create trigger if not exists status_cascade_mtime_trigger after update on status_cascade
+begin
+        update status_cascade set mtime = strftime('%s','now')
+        where id = new.id;
+end;
+-- STMT
+create table if not exists invitations (
+       id text unique not null,
+       org text not null,
+       email text not null,
+       email_digest text not null,
+       role integer not null,
+       inviter text not null,
+       token_digest text unique not null,
+       expires integer not null,
+       state integer not null,
+       schema_version integer not null default 0,
+       ctime integer,
+       mtime integer,
+       primary key (id));
+-- STMT
+create index if not exists invitations_org_ctime on invitations (org, ctime, id);
+-- STMT
+create trigger if not exists invitations_ctime_trigger after insert on invitations
+begin
+        update invitations set
+        ctime = strftime('%s','now'),
+        mtime = strftime('%s','now')
+        where id = new.id;
+end;
+-- STMT
+create trigger if not exists invitations_mtime_trigger after update on invitations
+begin
+        update invitations set mtime = strftime('%s','now')
+        where id = new.id;
+end;
+-- STMT
+create table if not exists org_settings (
+       id text unique not null,
+       org text not null,
+       name text not null,
+       kind integer not null,
+       value text not null,
+       schema_version integer not null default 0,
+       ctime integer,
+       mtime integer,
+       primary key (id));
+-- STMT
+create unique index if not exists org_settings_org_name on org_settings (org, name);
+-- STMT
+create trigger if not exists org_settings_ctime_trigger after insert on org_settings
+begin
+        update org_settings set
+        ctime = strftime('%s','now'),
+        mtime = strftime('%s','now')
+        where id = new.id;
+end;
+-- STMT
+create trigger if not exists org_settings_mtime_trigger after update on org_settings
+begin
+        update org_settings set mtime = strftime('%s','now')
+        where id = new.id;
+end;
+-- STMT
 create table if not exists repositories (
        id text unique not null,
        name text not null,
@@ -91,6 +251,433 @@ begin
         where id = new.id;
 end;
 -- STMT
+create table if not exists teams (
+       id text unique not null,
+       name text not null,
+       org text not null,
+       schema_version integer not null default 0,
+       ctime integer,
+       mtime integer,
+       primary key (id));
+-- STMT
+create unique index if not exists teams_name_org on teams (name, org);
+-- STMT
+create trigger if not exists teams_ctime_trigger after insert on teams
+begin
+        update teams set
+        ctime = strftime('%s','now'),
+        mtime = strftime('%s','now')
+        where id = new.id;
+end;
+-- STMT
+create trigger if not exists teams_mtime_trigger after update on teams
+begin
+        update teams set mtime = strftime('%s','now')
+        where id = new.id;
+end;
+-- STMT
+create table if not exists team_members (
+       id text unique not null,
+       team text not null,
+       user text not null,
+       schema_version integer not null default 0,
+       ctime integer,
+       mtime integer,
+       primary key (id));
+-- STMT
+create unique index if not exists team_members_team_user on team_members (team, user);
+-- STMT
+create index if not exists team_members_user on team_members (user);
+-- STMT
+create trigger if not exists team_members_ctime_trigger after insert on team_members
+begin
+        update team_members set
+        ctime = strftime('%s','now'),
+        mtime = strftime('%s','now')
+        where id = new.id;
+end;
+-- STMT
+create trigger if not exists team_members_mtime_trigger after update on team_members
+begin
+        update team_members set mtime = strftime('%s','now')
+        where id = new.id;
+end;
+-- STMT
+create table if not exists repository_grants (
+       id text unique not null,
+       repository text not null,
+       team text not null,
+       access integer not null,
+       schema_version integer not null default 0,
+       ctime integer,
+       mtime integer,
+       primary key (id));
+-- STMT
+create unique index if not exists repository_grants_repository_team on repository_grants (repository, team);
+-- STMT
+create trigger if not exists repository_grants_ctime_trigger after insert on repository_grants
+begin
+        update repository_grants set
+        ctime = strftime('%s','now'),
+        mtime = strftime('%s','now')
+        where id = new.id;
+end;
+-- STMT
+create trigger if not exists repository_grants_mtime_trigger after update on repository_grants
+begin
+        update repository_grants set mtime = strftime('%s','now')
+        where id = new.id;
+end;
+-- STMT
+create table if not exists blob_counts (
+       id text unique not null,
+       blob text not null,
+       definition text not null,
+       code integer not null,
+       comment integer not null,
+       blank integer not null,
+       functions integer not null default 0,
+       complexity integer not null default 0,
+       generated integer not null default 0,
+       schema_version integer not null default 0,
+       ctime integer,
+       mtime integer,
+       primary key (id));
+-- STMT
+create unique index if not exists blob_counts_blob_definition on blob_counts (blob, definition);
+-- STMT
+create trigger if not exists blob_counts_ctime_trigger after insert on blob_counts
+begin
+        update blob_counts set
+        ctime = strftime('%s','now'),
+        mtime = strftime('%s','now')
+        where id = new.id;
+end;
+-- STMT
+create trigger if not exists blob_counts_mtime_trigger after update on blob_counts
+begin
+        update blob_counts set mtime = strftime('%s','now')
+        where id = new.id;
+end;
+-- STMT
+create table if not exists blob_interpreters (
+       id text unique not null,
+       blob text not null,
+       interpreter text,
+       schema_version integer not null default 0,
+       ctime integer,
+       mtime integer,
+       primary key (id));
+-- STMT
+create unique index if not exists blob_interpreters_blob on blob_interpreters (blob);
+-- STMT
+create trigger if not exists blob_interpreters_ctime_trigger after insert on blob_interpreters
+begin
+        update blob_interpreters set
+        ctime = strftime('%s','now'),
+        mtime = strftime('%s','now')
+        where id = new.id;
+end;
+-- STMT
+create trigger if not exists blob_interpreters_mtime_trigger after update on blob_interpreters
+begin
+        update blob_interpreters set mtime = strftime('%s','now')
+        where id = new.id;
+end;
+-- STMT
+create table if not exists commit_counts (
+       id text unique not null,
+       repository text not null,
+       commit_id text not null,
+       language text not null,
+       exclusion integer not null default 0,
+       files integer not null,
+       code integer not null,
+       comment integer not null,
+       blank integer not null,
+       schema_version integer not null default 0,
+       ctime integer,
+       mtime integer,
+       primary key (id));
+-- STMT
+create unique index if not exists commit_counts_repository_commit_exclusion_language on commit_counts (repository, commit_id, exclusion, language);
+-- STMT
+create trigger if not exists commit_counts_ctime_trigger after insert on commit_counts
+begin
+        update commit_counts set
+        ctime = strftime('%s','now'),
+        mtime = strftime('%s','now')
+        where id = new.id;
+end;
+-- STMT
+create trigger if not exists commit_counts_mtime_trigger after update on commit_counts
+begin
+        update commit_counts set mtime = strftime('%s','now')
+        where id = new.id;
+end;
+-- STMT
+create table if not exists history_points (
+       id text unique not null,
+       repository text not null,
+       commit_id text not null,
+       commit_time integer not null,
+       schema_version integer not null default 0,
+       ctime integer,
+       mtime integer,
+       primary key (id));
+-- STMT
+create unique index if not exists history_points_repository_commit on history_points (repository, commit_id);
+-- STMT
+create index if not exists history_points_repository_time on history_points (repository, commit_time);
+-- STMT
+create trigger if not exists history_points_ctime_trigger after insert on history_points
+begin
+        update history_points set
+        ctime = strftime('%s','now'),
+        mtime = strftime('%s','now')
+        where id = new.id;
+end;
+-- STMT
+create trigger if not exists history_points_mtime_trigger after update on history_points
+begin
+        update history_points set mtime = strftime('%s','now')
+        where id = new.id;
+end;
+-- STMT
+create table if not exists authors (
+       id text unique not null,
+       repository text not null,
+       name text not null,
+       name_digest text not null,
+       email text not null,
+       email_digest text not null,
+       user text,
+       schema_version integer not null default 0,
+       ctime integer,
+       mtime integer,
+       primary key (id));
+-- STMT
+create unique index if not exists authors_repository_email on authors (repository, email_digest);
+-- STMT
+create trigger if not exists authors_ctime_trigger after insert on authors
+begin
+        update authors set
+        ctime = strftime('%s','now'),
+        mtime = strftime('%s','now')
+        where id = new.id;
+end;
+-- STMT
+create trigger if not exists authors_mtime_trigger after update on authors
+begin
+        update authors set mtime = strftime('%s','now')
+        where id = new.id;
+end;
+-- STMT
+create table if not exists author_stats (
+       id text unique not null,
+       author text not null,
+       period integer not null,
+       period_start integer not null,
+       commits integer not null,
+       added integer not null,
+       removed integer not null,
+       files integer not null,
+       schema_version integer not null default 0,
+       ctime integer,
+       mtime integer,
+       primary key (id));
+-- STMT
+create unique index if not exists author_stats_author_period on author_stats (author, period, period_start);
+-- STMT
+create trigger if not exists author_stats_ctime_trigger after insert on author_stats
+begin
+        update author_stats set
+        ctime = strftime('%s','now'),
+        mtime = strftime('%s','now')
+        where id = new.id;
+end;
+-- STMT
+create trigger if not exists author_stats_mtime_trigger after update on author_stats
+begin
+        update author_stats set mtime = strftime('%s','now')
+        where id = new.id;
+end;
+-- STMT
+create table if not exists languages (
+       id text unique not null,
+       org text not null,
+       name text not null,
+       definition text not null,
+       schema_version integer not null default 0,
+       ctime integer,
+       mtime integer,
+       primary key (id));
+-- STMT
+create unique index if not exists languages_org_name on languages (org, name);
+-- STMT
+create trigger if not exists languages_ctime_trigger after insert on languages
+begin
+        update languages set
+        ctime = strftime('%s','now'),
+        mtime = strftime('%s','now')
+        where id = new.id;
+end;
+-- STMT
+create trigger if not exists languages_mtime_trigger after update on languages
+begin
+        update languages set mtime = strftime('%s','now')
+        where id = new.id;
+end;
+-- STMT
+create table if not exists subprojects (
+       id text unique not null,
+       repository text not null,
+       name text not null,
+       patterns text not null,
+       schema_version integer not null default 0,
+       ctime integer,
+       mtime integer,
+       primary key (id));
+-- STMT
+create unique index if not exists subprojects_repository_name on subprojects (repository, name);
+-- STMT
+create trigger if not exists subprojects_ctime_trigger after insert on subprojects
+begin
+        update subprojects set
+        ctime = strftime('%s','now'),
+        mtime = strftime('%s','now')
+        where id = new.id;
+end;
+-- STMT
+create trigger if not exists subprojects_mtime_trigger after update on subprojects
+begin
+        update subprojects set mtime = strftime('%s','now')
+        where id = new.id;
+end;
+-- STMT
+create table if not exists commit_groups (
+       id text unique not null,
+       repository text not null,
+       commit_id text not null,
+       breakdown integer not null,
+       name text not null,
+       language text not null,
+       files integer not null,
+       code integer not null,
+       comment integer not null,
+       blank integer not null,
+       schema_version integer not null default 0,
+       ctime integer,
+       mtime integer,
+       primary key (id));
+-- STMT
+create unique index if not exists commit_groups_repository_commit_breakdown_name_language on commit_groups (repository, commit_id, breakdown, name, language);
+-- STMT
+create trigger if not exists commit_groups_ctime_trigger after insert on commit_groups
+begin
+        update commit_groups set
+        ctime = strftime('%s','now'),
+        mtime = strftime('%s','now')
+        where id = new.id;
+end;
+-- STMT
+create trigger if not exists commit_groups_mtime_trigger after update on commit_groups
+begin
+        update commit_groups set mtime = strftime('%s','now')
+        where id = new.id;
+end;
+-- STMT
+create table if not exists file_churn (
+       id text unique not null,
+       repository text not null,
+       window_start integer not null,
+       window_end integer not null,
+       commit_id text not null,
+       path text not null,
+       language text not null,
+       commits integer not null,
+       added integer not null,
+       removed integer not null,
+       code integer not null,
+       score integer not null,
+       schema_version integer not null default 0,
+       ctime integer,
+       mtime integer,
+       primary key (id));
+-- STMT
+create unique index if not exists file_churn_repository_window_path on file_churn (repository, window_start, window_end, path);
+-- STMT
+create trigger if not exists file_churn_ctime_trigger after insert on file_churn
+begin
+        update file_churn set
+        ctime = strftime('%s','now'),
+        mtime = strftime('%s','now')
+        where id = new.id;
+end;
+-- STMT
+create trigger if not exists file_churn_mtime_trigger after update on file_churn
+begin
+        update file_churn set mtime = strftime('%s','now')
+        where id = new.id;
+end;
+-- STMT
+create table if not exists directory_owners (
+       id text unique not null,
+       repository text not null,
+       commit_id text not null,
+       directory text not null,
+       author text not null,
+       lines integer not null,
+       schema_version integer not null default 0,
+       ctime integer,
+       mtime integer,
+       primary key (id));
+-- STMT
+create unique index if not exists directory_owners_repository_commit_directory_author on directory_owners (repository, commit_id, directory, author);
+-- STMT
+create trigger if not exists directory_owners_ctime_trigger after insert on directory_owners
+begin
+        update directory_owners set
+        ctime = strftime('%s','now'),
+        mtime = strftime('%s','now')
+        where id = new.id;
+end;
+-- STMT
+create trigger if not exists directory_owners_mtime_trigger after update on directory_owners
+begin
+        update directory_owners set mtime = strftime('%s','now')
+        where id = new.id;
+end;
+-- STMT
+create table if not exists commit_complexity (
+       id text unique not null,
+       repository text not null,
+       commit_id text not null,
+       language text not null,
+       files integer not null,
+       functions integer not null,
+       complexity integer not null,
+       schema_version integer not null default 0,
+       ctime integer,
+       mtime integer,
+       primary key (id));
+-- STMT
+create unique index if not exists commit_complexity_repository_commit_language on commit_complexity (repository, commit_id, language);
+-- STMT
+create trigger if not exists commit_complexity_ctime_trigger after insert on commit_complexity
+begin
+        update commit_complexity set
+        ctime = strftime('%s','now'),
+        mtime = strftime('%s','now')
+        where id = new.id;
+end;
+-- STMT
+create trigger if not exists commit_complexity_mtime_trigger after update on commit_complexity
+begin
+        update commit_complexity set mtime = strftime('%s','now')
+        where id = new.id;
+end;
+-- STMT
 create table if not exists audit (
       id text unique not null,
       code integer not null,
@@ -114,6 +701,32 @@ create trigger if not exists audit_mtime_trigger after update on audit
       update audit set mtime = strftime('%s','now')
       where id = new.id;
 end;
+-- STMT
+create table if not exists outbox (
+      id text unique not null,
+      code integer not null,
+      recipient text not null,
+      recipient_digest text not null,
+      user text not null,
+      delivered integer not null default 0,
+      schema_version integer not null default 0,
+      ctime integer,
+      mtime integer,
+      primary key (id));
+-- STMT
+create trigger if not exists outbox_ctime_trigger after insert on outbox
+      begin
+      update outbox set
+      ctime = strftime('%s','now'),
+      mtime = strftime('%s','now')
+      where id = new.id;
+end;
+-- STMT
+create trigger if not exists outbox_mtime_trigger after update on outbox
+      begin
+      update outbox set mtime = strftime('%s','now')
+      where id = new.id;
+end;
 "#;
 
 #[cfg(test)]
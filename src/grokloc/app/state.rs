@@ -9,6 +9,7 @@ use anyhow;
 use sqlx;
 
 /// App is the central state access mechanism
+#[allow(dead_code)]
 pub struct App {
     pub level: env::Level,
     pub master_pool: sqlx::SqlitePool,
@@ -0,0 +1,346 @@
+//! complexity counts the functions of source files and estimates their
+//! cyclomatic complexity from a lightweight tokenization of their code
+//!
+//! the complexity of a file is the sum over its functions of one plus their
+//! decision points, taken as its functions plus all of its decision points;
+//! nesting and scopes are not parsed, so results are estimates
+use crate::grokloc::loc;
+use std::ops::AddAssign;
+
+/// Metrics are the functions and estimated cyclomatic complexity of code
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Metrics {
+    pub functions: i64,
+    pub complexity: i64,
+}
+
+impl AddAssign for Metrics {
+    fn add_assign(&mut self, other: Self) {
+        self.functions += other.functions;
+        self.complexity += other.complexity;
+    }
+}
+
+/// Family groups languages whose functions and decisions look alike
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Family {
+    Rust,
+    Go,
+    Python,
+    Script,
+    CLike,
+}
+
+/// OPERATORS are the tokens of more than one character, longest first
+const OPERATORS: &[&str] = &["&&", "||", "??", "?.", "?:", "=>", "->", "::"];
+
+/// CONTROL are keywords followed by parentheses and a block that are not
+/// function definitions
+const CONTROL: &[&str] = &[
+    "if",
+    "for",
+    "while",
+    "switch",
+    "catch",
+    "return",
+    "sizeof",
+    "synchronized",
+    "new",
+    "function",
+    "else",
+    "do",
+    "try",
+];
+
+/// family finds the family of a built-in language
+fn family(name: &str) -> Option<Family> {
+    match name {
+        "Rust" => Some(Family::Rust),
+        "Go" => Some(Family::Go),
+        "Python" => Some(Family::Python),
+        "JavaScript" | "TypeScript" => Some(Family::Script),
+        "Java" | "C" | "C++" => Some(Family::CLike),
+        _ => None,
+    }
+}
+
+/// is_supported reports a language whose complexity is estimated
+pub fn is_supported(name: &str) -> bool {
+    family(name).is_some()
+}
+
+/// tokens splits code into words, numbers, string delimiters and
+/// punctuation, skipping whitespace
+fn tokens(code: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut rest = code.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let len = if c.is_alphanumeric() || c == '_' {
+            rest.find(|v: char| !v.is_alphanumeric() && v != '_')
+                .unwrap_or(rest.len())
+        } else {
+            match OPERATORS.iter().find(|v| rest.starts_with(*v)) {
+                Some(v) => v.len(),
+                None => c.len_utf8(),
+            }
+        };
+        tokens.push(&rest[..len]);
+        rest = rest[len..].trim_start();
+    }
+    tokens
+}
+
+/// is_word reports an identifier, keyword or number
+fn is_word(token: &str) -> bool {
+    token.starts_with(|c: char| c.is_alphanumeric() || c == '_')
+}
+
+/// is_value reports a token that can end an operand, so that a following
+/// &&, || or ? is a binary operator rather than a closure, a reference or
+/// a wildcard
+fn is_value(token: Option<&&str>) -> bool {
+    match token {
+        Some(v) => {
+            (is_word(v) && !matches!(*v, "return" | "move"))
+                || matches!(*v, ")" | "]" | "\"" | "'" | "`")
+        }
+        None => false,
+    }
+}
+
+/// closes finds the index of the token closing the bracket at tokens[open]
+fn closes(tokens: &[&str], open: usize) -> Option<usize> {
+    let (start, end) = match tokens[open] {
+        "(" => ("(", ")"),
+        "{" => ("{", "}"),
+        _ => return None,
+    };
+    let mut depth = 0;
+    for (i, token) in tokens.iter().enumerate().skip(open) {
+        if *token == start {
+            depth += 1;
+        } else if *token == end {
+            depth -= 1;
+            if depth == 0 {
+                return Some(i);
+            }
+        }
+    }
+    None
+}
+
+/// is_definition reports a name followed by parameters and then a block,
+/// allowing qualifiers such as const, throws or a return type between
+fn is_definition(tokens: &[&str], name: usize) -> bool {
+    if !is_word(tokens[name]) || CONTROL.contains(&tokens[name]) {
+        return false;
+    }
+    if name > 0 && matches!(tokens[name - 1], "new" | "function" | "." | "?.") {
+        return false;
+    }
+    if tokens.get(name + 1) != Some(&"(") {
+        return false;
+    }
+    let mut i = match closes(tokens, name + 1) {
+        Some(v) => v + 1,
+        None => return false,
+    };
+    while let Some(token) = tokens.get(i) {
+        match *token {
+            "{" => return true,
+            "," | "." | "::" | "->" | "&" | "*" | "<" | ">" => (),
+            v if is_word(v) && !CONTROL.contains(&v) => (),
+            _ => return false,
+        }
+        i += 1;
+    }
+    false
+}
+
+/// functions counts function definitions in tokens of family
+fn functions(tokens: &[&str], family: Family) -> i64 {
+    let mut functions = 0;
+    for (i, token) in tokens.iter().enumerate() {
+        let next = tokens.get(i + 1).copied();
+        let defines = match family {
+            Family::Rust => *token == "fn" && next.is_some_and(is_word),
+            Family::Python => *token == "def",
+            // a function or a method with a receiver; literals are skipped
+            Family::Go => {
+                *token == "func"
+                    && match next {
+                        Some("(") => closes(tokens, i + 1)
+                            .and_then(|v| tokens.get(v + 1..v + 3))
+                            .is_some_and(|v| is_word(v[0]) && v[1] == "("),
+                        Some(v) => is_word(v),
+                        None => false,
+                    }
+            }
+            Family::Script => *token == "function" || *token == "=>" || is_definition(tokens, i),
+            Family::CLike => is_definition(tokens, i),
+        };
+        if defines {
+            functions += 1;
+        }
+    }
+    functions
+}
+
+/// decisions counts the decision points in tokens of family
+fn decisions(tokens: &[&str], family: Family) -> i64 {
+    let mut decisions = 0;
+    let mut arms = 0;
+    let mut matches = 0;
+    for (i, token) in tokens.iter().enumerate() {
+        let previous = match i {
+            0 => None,
+            _ => tokens.get(i - 1),
+        };
+        let decides = match (family, *token) {
+            // impl X for Y and for<'a> are not loops
+            (Family::Rust, "for") => !previous.is_some_and(|v| is_word(v) || *v == ">"),
+            (_, "if" | "for" | "while") => true,
+            (Family::Python, "elif" | "except" | "and" | "or") => true,
+            (Family::Python, _) => false,
+            (Family::Rust, "=>") => {
+                arms += 1;
+                false
+            }
+            (Family::Rust, "match") => {
+                matches += 1;
+                false
+            }
+            (Family::Go | Family::Script | Family::CLike, "case") => true,
+            (Family::Script | Family::CLike, "catch") => true,
+            (Family::Script, "??") => true,
+            (Family::Script | Family::CLike, "?") => is_value(previous),
+            (_, "&&" | "||") => is_value(previous),
+            _ => false,
+        };
+        if decides {
+            decisions += 1;
+        }
+    }
+    // a match of n arms decides n - 1 times
+    decisions + (arms - matches).max(0)
+}
+
+/// measure estimates the metrics of content in language, or None if the
+/// language is not supported or content is binary
+pub fn measure(language: &loc::Language, content: &[u8]) -> Option<Metrics> {
+    let family = family(&language.name)?;
+    let code = loc::code(language, content)?;
+    let tokens = tokens(&code);
+    let functions = functions(&tokens, family);
+    Some(Metrics {
+        functions,
+        complexity: functions + decisions(&tokens, family),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metrics(path: &str, source: &str) -> Option<Metrics> {
+        let languages = loc::Languages::builtin();
+        measure(languages.detect(path).unwrap(), source.as_bytes())
+    }
+
+    fn expect(functions: i64, complexity: i64) -> Option<Metrics> {
+        Some(Metrics {
+            functions,
+            complexity,
+        })
+    }
+
+    #[test]
+    fn tokens_test() {
+        assert_eq!(
+            vec!["a", "&&", "b", "?.", "c", "(", "\"", ")", "=>", "1"],
+            tokens(" a&&b?.c(\" )=>1")
+        );
+    }
+
+    #[test]
+    fn measure_test() {
+        let rust = "// fn commented() { if x {} }\n\
+                    fn a(x: Option<i64>) -> i64 {\n\
+                    \x20   let f = || x.is_some();\n\
+                    \x20   if f() && x > Some(1) { return 1; }\n\
+                    \x20   match x { Some(1) => 1, Some(_) => 2, None => 3 }\n\
+                    }\n\
+                    fn b(s: &str) -> bool { s == \"if\" || s.is_empty() }\n\
+                    type F = fn(i64) -> i64;\n\
+                    impl X for Y {}\n\
+                    impl<'a> Z<'a> for &'a W where for<'b> &'b W: X {}\n\
+                    fn c() { for v in 0..2 {} 'l: for _ in 0..2 { break 'l; } }\n";
+        // a: 1 + if + && + two more arms; b: 1 + ||; c: 1 + two loops
+        assert_eq!(expect(3, 10), metrics("x.rs", rust));
+
+        let go = "func (s *S) Get(k string) (int, error) {\n\
+                  \x20   for _, v := range s.v { if v == k { return 1, nil } }\n\
+                  \x20   switch k { case \"a\": return 2, nil; case \"b\", \"c\": return 3, nil }\n\
+                  \x20   f := func() bool { return k == \"\" || s == nil }\n\
+                  \x20   return 0, nil\n\
+                  }\n\
+                  func main() {}\n";
+        // Get: 1 + for + if + two cases + ||; main: 1
+        assert_eq!(expect(2, 7), metrics("x.go", go));
+
+        let python = "def a(x):\n\
+                      \x20   '''if this, for that'''\n\
+                      \x20   if x and x > 1:\n\
+                      \x20       return [v for v in x if v]\n\
+                      \x20   elif x:  # if\n\
+                      \x20       pass\n\
+                      \x20   try:\n\
+                      \x20       pass\n\
+                      \x20   except ValueError:\n\
+                      \x20       pass\n\
+                      \n\
+                      def b():\n\
+                      \x20   return 1\n";
+        // a: 1 + if + and + for + if + elif + except; b: 1
+        assert_eq!(expect(2, 8), metrics("x.py", python));
+
+        let script = "function a(x) { return x ? 1 : 2; }\n\
+                      const b = (y) => y ?? 0;\n\
+                      class C {\n\
+                      \x20 m(z) { if (z) { g(z); } }\n\
+                      \x20 get(k?: string) { return k?.length || 0; }\n\
+                      }\n";
+        // four functions; ?, ??, if, ||
+        assert_eq!(expect(4, 8), metrics("x.ts", script));
+
+        let java = "class A {\n\
+                    \x20 List<? extends B> a(int x) throws IOException {\n\
+                    \x20   try { return x > 0 ? f(x) : null; } catch (E e) { return null; }\n\
+                    \x20 }\n\
+                    \x20 void b() { switch (y) { case 1: break; default: while (z) {} } }\n\
+                    \x20 Runnable r = new Runnable() { public void run() {} };\n\
+                    }\n";
+        // a: 1 + ? + catch; b: 1 + case + while; run: 1
+        assert_eq!(expect(3, 7), metrics("A.java", java));
+
+        let cpp = "int A::f(const char *s) const {\n\
+                   \x20 for (int i = 0; s[i] && i < 3; i++) {}\n\
+                   \x20 return 0;\n\
+                   }\n\
+                   int g(int);\n";
+        // f: 1 + for + &&; g is a declaration
+        assert_eq!(expect(1, 3), metrics("a.cpp", cpp));
+
+        let c = "int q(char c) { return c == '\"'; }\n\
+                 // if (c) {}\n\
+                 /* while (c) {} */\n";
+        // a quote in a character literal opens no string
+        assert_eq!(expect(1, 1), metrics("q.c", c));
+
+        // unsupported languages and binary content are not measured
+        assert_eq!(None, metrics("x.sql", "select 1;"));
+        assert_eq!(None, metrics("x.rs", "fn a() {}\0"));
+        assert!(is_supported("C++"));
+        assert!(!is_supported("Ruby"));
+    }
+}
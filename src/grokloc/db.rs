@@ -11,6 +11,14 @@ pub enum Err {
     OrgViolation,
     #[error("user constraint violation")]
     UserViolation,
+    #[error("repository constraint violation")]
+    RepositoryViolation,
+    #[error("team constraint violation")]
+    TeamViolation,
+    #[error("language constraint violation")]
+    LanguageViolation,
+    #[error("subproject constraint violation")]
+    SubprojectViolation,
     #[error("bad row values")]
     BadRowValues,
 }
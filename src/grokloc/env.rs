@@ -6,17 +6,12 @@ use thiserror::Error;
 pub const GROKLOC_ENV_KEY: &str = "GROKLOC_ENV";
 
 /// Level describes the run level
-#[derive(Copy, Clone, Debug, PartialEq)]
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
 pub enum Level {
+    #[default]
     Unit,
 }
 
-impl Default for Level {
-    fn default() -> Self {
-        Level::Unit
-    }
-}
-
 impl fmt::Display for Level {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{:?}", self)
@@ -0,0 +1,204 @@
+//! exclude decides which files of a tree are left out of line counts
+//!
+//! excluded files are still counted, so their lines can be reported apart
+//! from the included totals
+use anyhow;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use thiserror::Error;
+
+/// IGNORE_FILE is the file at the root of a repository listing, in
+/// gitignore syntax, the paths to exclude
+pub const IGNORE_FILE: &str = ".groklocignore";
+
+/// VENDORED_DIRS are directory names that hold third party code
+pub const VENDORED_DIRS: &[&str] = &["bower_components", "node_modules", "third_party", "vendor"];
+
+/// LOCKFILES are file names of generated dependency lock files
+pub const LOCKFILES: &[&str] = &[
+    "Cargo.lock",
+    "Gemfile.lock",
+    "composer.lock",
+    "go.sum",
+    "package-lock.json",
+    "pnpm-lock.yaml",
+    "poetry.lock",
+    "yarn.lock",
+];
+
+/// GENERATED_MARKERS are header markers of generated files
+pub const GENERATED_MARKERS: &[&str] = &[
+    "@generated",
+    "auto-generated",
+    "autogenerated",
+    "code generated",
+    "do not edit",
+];
+
+/// GENERATED_PROBE_LINES is how many leading lines are searched for
+/// generated markers
+pub const GENERATED_PROBE_LINES: usize = 5;
+
+/// Err covers exclusion errors
+#[derive(Debug, Error, PartialEq)]
+pub enum Err {
+    #[error("unknown exclusion")]
+    UnknownExclusion,
+    #[error("bad ignore pattern")]
+    Pattern,
+}
+
+/// Exclusion is why a file is left out of line counts
+#[derive(Copy, Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Exclusion {
+    /// matched by the org ignore list or the repository ignore file
+    Ignored,
+    /// under a vendored directory
+    Vendored,
+    /// a lock file, or marked as generated in its header
+    Generated,
+}
+
+impl Exclusion {
+    /// translate an Exclusion to its database representation
+    pub fn to_int(self) -> i64 {
+        match self {
+            Exclusion::Ignored => 1,
+            Exclusion::Vendored => 2,
+            Exclusion::Generated => 3,
+        }
+    }
+
+    /// translate an Exclusion from its database representation
+    pub fn from_int(i: i64) -> Result<Self, Err> {
+        match i {
+            1 => Ok(Exclusion::Ignored),
+            2 => Ok(Exclusion::Vendored),
+            3 => Ok(Exclusion::Generated),
+            _ => Err(Err::UnknownExclusion),
+        }
+    }
+}
+
+/// Ignore matches paths against ignore lists in gitignore syntax
+#[derive(Clone, Debug)]
+pub struct Ignore(Gitignore);
+
+impl Ignore {
+    /// new builds a matcher from lists of patterns, one per line; later
+    /// lists take precedence, so they can re-include with !
+    pub fn new(lists: &[&str]) -> Result<Self, anyhow::Error> {
+        let mut builder = GitignoreBuilder::new("");
+        for list in lists {
+            for line in list.lines() {
+                builder.add_line(None, line).map_err(|_| Err::Pattern)?;
+            }
+        }
+        Ok(Ignore(builder.build().map_err(|_| Err::Pattern)?))
+    }
+
+    /// ignored reports whether the file at path, or a directory containing
+    /// it, is matched
+    pub fn ignored(&self, path: &str) -> bool {
+        self.0.matched_path_or_any_parents(path, false).is_ignore()
+    }
+}
+
+/// patterns_ok reports whether every line of list is a valid pattern
+pub fn patterns_ok(list: &str) -> bool {
+    Ignore::new(&[list]).is_ok()
+}
+
+/// is_vendored reports a path under a vendored directory
+pub fn is_vendored(path: &str) -> bool {
+    let mut dirs = path.split('/');
+    dirs.next_back();
+    dirs.any(|v| VENDORED_DIRS.contains(&v))
+}
+
+/// is_lockfile reports a path naming a lock file
+pub fn is_lockfile(path: &str) -> bool {
+    LOCKFILES.contains(&path.rsplit('/').next().unwrap_or(path))
+}
+
+/// is_generated reports content with a generated marker in its leading lines
+pub fn is_generated(content: &[u8]) -> bool {
+    String::from_utf8_lossy(content)
+        .lines()
+        .take(GENERATED_PROBE_LINES)
+        .any(|line| {
+            let line = line.to_lowercase();
+            GENERATED_MARKERS.iter().any(|v| line.contains(v))
+        })
+}
+
+/// classify finds the exclusion of the file at path, given whether its
+/// content is generated; ignore lists take precedence over heuristics
+pub fn classify(ignore: &Ignore, path: &str, generated: bool) -> Option<Exclusion> {
+    if ignore.ignored(path) {
+        Some(Exclusion::Ignored)
+    } else if is_vendored(path) {
+        Some(Exclusion::Vendored)
+    } else if generated || is_lockfile(path) {
+        Some(Exclusion::Generated)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exclude_ignore_test() -> Result<(), anyhow::Error> {
+        let ignore = Ignore::new(&["build/\n*.pb.go\n", "# repo\n!keep.pb.go\n/gen\n"])?;
+        assert!(ignore.ignored("build/a.rs"));
+        assert!(ignore.ignored("src/build/a.rs"));
+        assert!(ignore.ignored("api/x.pb.go"));
+        assert!(!ignore.ignored("api/keep.pb.go"));
+        assert!(ignore.ignored("gen/a.rs"));
+        assert!(!ignore.ignored("src/gen/a.rs"));
+        assert!(!ignore.ignored("src/main.rs"));
+        assert!(!Ignore::new(&[])?.ignored("a.rs"));
+
+        assert!(patterns_ok(""));
+        assert!(patterns_ok("target/\n# comment\n"));
+        assert!(!patterns_ok("a{b\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn exclude_classify_test() -> Result<(), anyhow::Error> {
+        assert!(is_vendored("vendor/x/a.go"));
+        assert!(is_vendored("web/node_modules/a/index.js"));
+        assert!(!is_vendored("vendor"));
+        assert!(!is_vendored("src/vendors/a.go"));
+        assert!(is_lockfile("web/package-lock.json"));
+        assert!(!is_lockfile("package.json"));
+        assert!(is_generated(b"// Code generated by protoc. DO NOT EDIT.\n"));
+        assert!(is_generated(b"\n\n# @generated\n"));
+        assert!(!is_generated(b"1\n2\n3\n4\n5\n// DO NOT EDIT\n"));
+
+        let ignore = Ignore::new(&["vendor/"])?;
+        assert_eq!(
+            Some(Exclusion::Ignored),
+            classify(&ignore, "vendor/a.go", false)
+        );
+        assert_eq!(
+            Some(Exclusion::Vendored),
+            classify(&ignore, "x/third_party/a.go", true)
+        );
+        assert_eq!(Some(Exclusion::Generated), classify(&ignore, "a.go", true));
+        assert_eq!(None, classify(&ignore, "a.go", false));
+
+        for exclusion in [
+            Exclusion::Ignored,
+            Exclusion::Vendored,
+            Exclusion::Generated,
+        ] {
+            assert_eq!(exclusion, Exclusion::from_int(exclusion.to_int())?);
+        }
+        assert_eq!(Err::UnknownExclusion, Exclusion::from_int(0).unwrap_err());
+        Ok(())
+    }
+}
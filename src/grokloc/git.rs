@@ -0,0 +1,593 @@
+//! git provides read access to local repository clones through the git cli
+use anyhow;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::process::Stdio;
+use thiserror::Error;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
+use tokio::process::Command;
+
+/// Err covers git command errors
+#[derive(Clone, Debug, Error, PartialEq)]
+pub enum Err {
+    #[error("git command failed: {0}")]
+    Command(String),
+    #[error("unexpected git output")]
+    Output,
+}
+
+/// TreeEntry is a file (blob) in the tree of a commit
+#[derive(Clone, Debug, PartialEq)]
+pub struct TreeEntry {
+    pub path: String,
+    pub blob: String,
+}
+
+/// FileChange is the lines added to and removed from a file by a commit;
+/// binary changes add and remove no lines
+#[derive(Clone, Debug, PartialEq)]
+pub struct FileChange {
+    pub path: String,
+    pub added: i64,
+    pub removed: i64,
+}
+
+/// LogCommit is a non-merge commit with its author and file changes
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogCommit {
+    pub commit: String,
+    /// author time in seconds since the epoch
+    pub time: i64,
+    pub author_name: String,
+    pub author_email: String,
+    pub changes: Vec<FileChange>,
+}
+
+/// run executes git with args in repo, returning stdout
+async fn run(repo: &Path, args: &[&str]) -> Result<Vec<u8>, anyhow::Error> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .await?;
+    if !output.status.success() {
+        return Err(
+            Err::Command(String::from_utf8_lossy(&output.stderr).trim().to_string()).into(),
+        );
+    }
+    Ok(output.stdout)
+}
+
+/// rev_parse resolves rev to a full commit id
+///
+/// rev is never interpreted as an option
+pub async fn rev_parse(repo: &Path, rev: &str) -> Result<String, anyhow::Error> {
+    let stdout = run(
+        repo,
+        &[
+            "rev-parse",
+            "--verify",
+            "--end-of-options",
+            &format!("{}^{{commit}}", rev),
+        ],
+    )
+    .await?;
+    Ok(String::from_utf8(stdout)?.trim().to_string())
+}
+
+/// ls_tree lists the regular files of commit, recursively
+///
+/// symlinks and submodules are not files and are not listed
+pub async fn ls_tree(repo: &Path, commit: &str) -> Result<Vec<TreeEntry>, anyhow::Error> {
+    let stdout = run(repo, &["ls-tree", "-r", "-z", "--full-tree", commit]).await?;
+    let mut entries = Vec::new();
+    for record in stdout.split(|v| *v == 0).filter(|v| !v.is_empty()) {
+        // <mode> SP <type> SP <object> TAB <path>
+        let record = String::from_utf8_lossy(record);
+        let (meta, path) = record.split_once('\t').ok_or(Err::Output)?;
+        let mut fields = meta.split(' ');
+        let (mode, kind, blob) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(mode), Some(kind), Some(blob)) => (mode, kind, blob),
+            _ => return Err(Err::Output.into()),
+        };
+        if kind == "blob" && mode != "120000" {
+            entries.push(TreeEntry {
+                path: path.to_string(),
+                blob: blob.to_string(),
+            });
+        }
+    }
+    Ok(entries)
+}
+
+/// first_parent lists the commits reachable from commit by first parents,
+/// oldest first, with their committer times in seconds since the epoch
+pub async fn first_parent(repo: &Path, commit: &str) -> Result<Vec<(String, i64)>, anyhow::Error> {
+    let stdout = run(
+        repo,
+        &[
+            "rev-list",
+            "--first-parent",
+            "--reverse",
+            "--timestamp",
+            commit,
+        ],
+    )
+    .await?;
+    let mut commits = Vec::new();
+    for line in String::from_utf8(stdout)?.lines() {
+        // <timestamp> SP <commit>
+        let (time, id) = line.split_once(' ').ok_or(Err::Output)?;
+        commits.push((id.to_string(), time.parse()?));
+    }
+    Ok(commits)
+}
+
+/// log_numstat lists the non-merge commits reachable from commit, newest
+/// first, with the lines changed per file; renames are changes to two files
+pub async fn log_numstat(repo: &Path, commit: &str) -> Result<Vec<LogCommit>, anyhow::Error> {
+    let stdout = run(
+        repo,
+        &[
+            "-c",
+            "core.quotePath=false",
+            "log",
+            "--no-merges",
+            "--no-renames",
+            "--numstat",
+            "--format=%x00%H%x09%at%x09%an%x09%ae",
+            commit,
+        ],
+    )
+    .await?;
+    let stdout = String::from_utf8_lossy(&stdout);
+    let mut commits = Vec::new();
+    for record in stdout.split('\0').filter(|v| !v.is_empty()) {
+        let mut lines = record.lines();
+        // <commit> TAB <time> TAB <name> TAB <email>
+        let header: Vec<&str> = lines.next().ok_or(Err::Output)?.splitn(4, '\t').collect();
+        let (id, time, name, email) = match header[..] {
+            [id, time, name, email] => (id, time, name, email),
+            _ => return Err(Err::Output.into()),
+        };
+        let mut changes = Vec::new();
+        for line in lines.filter(|v| !v.is_empty()) {
+            // <added> TAB <removed> TAB <path>, with - for binary files
+            let fields: Vec<&str> = line.splitn(3, '\t').collect();
+            let (added, removed, path) = match fields[..] {
+                [added, removed, path] => (added, removed, path),
+                _ => return Err(Err::Output.into()),
+            };
+            changes.push(FileChange {
+                path: path.to_string(),
+                added: added.parse().unwrap_or(0),
+                removed: removed.parse().unwrap_or(0),
+            });
+        }
+        commits.push(LogCommit {
+            commit: id.to_string(),
+            time: time.parse()?,
+            author_name: name.to_string(),
+            author_email: email.to_string(),
+            changes,
+        });
+    }
+    Ok(commits)
+}
+
+/// Change is how a file differs between two commits
+#[derive(Clone, Debug, PartialEq)]
+pub enum Change {
+    Added(String),
+    Deleted(String),
+    Modified(String),
+    /// from the old path to the new path
+    Renamed(String, String),
+}
+
+/// diff_name_status lists the files changed from commit old to commit new,
+/// detecting renames of similar files
+pub async fn diff_name_status(
+    repo: &Path,
+    old: &str,
+    new: &str,
+) -> Result<Vec<Change>, anyhow::Error> {
+    let stdout = run(
+        repo,
+        &[
+            "diff",
+            "--no-ext-diff",
+            "--name-status",
+            "-z",
+            "-M",
+            "--end-of-options",
+            old,
+            new,
+        ],
+    )
+    .await?;
+    let stdout = String::from_utf8_lossy(&stdout);
+    // <status> NUL <path> NUL, with a second path for renames and copies
+    let mut fields = stdout.split('\0').filter(|v| !v.is_empty());
+    let mut changes = Vec::new();
+    while let Some(status) = fields.next() {
+        let path = fields.next().ok_or(Err::Output)?.to_string();
+        let change = match status.chars().next() {
+            Some('A') => Change::Added(path),
+            Some('D') => Change::Deleted(path),
+            Some('M') | Some('T') => Change::Modified(path),
+            Some('R') => Change::Renamed(path, fields.next().ok_or(Err::Output)?.to_string()),
+            Some('C') => Change::Added(fields.next().ok_or(Err::Output)?.to_string()),
+            _ => return Err(Err::Output.into()),
+        };
+        changes.push(change);
+    }
+    Ok(changes)
+}
+
+/// BlameLines is the lines of a file last changed by an author
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlameLines {
+    pub author_name: String,
+    pub author_email: String,
+    /// the newest author time of the lines, in seconds since the epoch
+    pub author_time: i64,
+    pub lines: i64,
+}
+
+/// blame attributes each line of path in the tree of commit to the author
+/// of the commit that last changed it, summed per author email and ordered
+/// by email; the name is that of the newest line of the author
+pub async fn blame(
+    repo: &Path,
+    commit: &str,
+    path: &str,
+) -> Result<Vec<BlameLines>, anyhow::Error> {
+    let stdout = run(repo, &["blame", "--line-porcelain", commit, "--", path]).await?;
+    let stdout = String::from_utf8_lossy(&stdout);
+    let mut by_email: BTreeMap<String, BlameLines> = BTreeMap::new();
+    let (mut name, mut email) = ("", "");
+    // each line is a header, author fields, then TAB <content>
+    for line in stdout.lines() {
+        if line.starts_with('\t') {
+            continue;
+        }
+        if let Some(v) = line.strip_prefix("author ") {
+            name = v;
+        } else if let Some(v) = line.strip_prefix("author-mail ") {
+            email = v.trim_start_matches('<').trim_end_matches('>');
+        } else if let Some(v) = line.strip_prefix("author-time ") {
+            let time: i64 = v.parse()?;
+            let blamed = by_email
+                .entry(email.to_string())
+                .or_insert_with(|| BlameLines {
+                    author_name: name.to_string(),
+                    author_email: email.to_string(),
+                    author_time: time,
+                    lines: 0,
+                });
+            blamed.lines += 1;
+            if time > blamed.author_time {
+                blamed.author_name = name.to_string();
+                blamed.author_time = time;
+            }
+        }
+    }
+    Ok(by_email.into_values().collect())
+}
+
+/// cat_blobs reads the content of each blob, in order, with one git process
+///
+/// every content is held in memory at once, so callers pass blobs in
+/// batches (see analysis::BLOB_BATCH_SIZE)
+pub async fn cat_blobs(repo: &Path, blobs: &[String]) -> Result<Vec<Vec<u8>>, anyhow::Error> {
+    if blobs.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut child = Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .args(["cat-file", "--batch"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // write requests while the output is read, so neither pipe can fill
+    let mut stdin = child.stdin.take().ok_or(Err::Output)?;
+    let requests = blobs.iter().fold(String::new(), |acc, v| acc + v + "\n");
+    let writer = tokio::spawn(async move { stdin.write_all(requests.as_bytes()).await });
+    let output = child.wait_with_output().await?;
+    writer.await??;
+    if !output.status.success() {
+        return Err(
+            Err::Command(String::from_utf8_lossy(&output.stderr).trim().to_string()).into(),
+        );
+    }
+
+    // each response is <object> SP <type> SP <size> LF <content> LF
+    let mut contents = Vec::with_capacity(blobs.len());
+    let mut rest = &output.stdout[..];
+    for _ in blobs {
+        let header_end = rest.iter().position(|v| *v == b'\n').ok_or(Err::Output)?;
+        let header = String::from_utf8_lossy(&rest[..header_end]).to_string();
+        let size: usize = match header.split(' ').collect::<Vec<_>>()[..] {
+            [_, "blob", size] => size.parse()?,
+            _ => return Err(Err::Output.into()),
+        };
+        let start = header_end + 1;
+        if rest.len() < start + size + 1 {
+            return Err(Err::Output.into());
+        }
+        contents.push(rest[start..start + size].to_vec());
+        rest = &rest[start + size + 1..];
+    }
+    Ok(contents)
+}
+
+/// FIRST_LINE_MAX is the most bytes of a first line kept by first_lines
+pub const FIRST_LINE_MAX: u64 = 256;
+
+/// first_lines reads the first line of each blob, in order, with one git
+/// process; the rest of each blob is streamed past without being kept, and
+/// lines are cut at FIRST_LINE_MAX bytes
+pub async fn first_lines(repo: &Path, blobs: &[String]) -> Result<Vec<Vec<u8>>, anyhow::Error> {
+    if blobs.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut child = Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .args(["cat-file", "--batch"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    let mut stdin = child.stdin.take().ok_or(Err::Output)?;
+    let requests = blobs.iter().fold(String::new(), |acc, v| acc + v + "\n");
+    let writer = tokio::spawn(async move { stdin.write_all(requests.as_bytes()).await });
+
+    // each response is <object> SP <type> SP <size> LF <content> LF
+    let mut stdout = tokio::io::BufReader::new(child.stdout.take().ok_or(Err::Output)?);
+    let mut lines = Vec::with_capacity(blobs.len());
+    for _ in blobs {
+        let mut header = String::new();
+        stdout.read_line(&mut header).await?;
+        let size: u64 = match header.trim_end().split(' ').collect::<Vec<_>>()[..] {
+            [_, "blob", size] => size.parse()?,
+            _ => return Err(Err::Output.into()),
+        };
+        let mut content = (&mut stdout).take(size);
+        let mut line = Vec::new();
+        (&mut content)
+            .take(FIRST_LINE_MAX)
+            .read_until(b'\n', &mut line)
+            .await?;
+        tokio::io::copy(&mut content, &mut tokio::io::sink()).await?;
+        if stdout.read_u8().await? != b'\n' {
+            return Err(Err::Output.into());
+        }
+        if line.last() == Some(&b'\n') {
+            line.pop();
+        }
+        lines.push(line);
+    }
+
+    let output = child.wait_with_output().await?;
+    writer.await??;
+    if !output.status.success() {
+        return Err(
+            Err::Command(String::from_utf8_lossy(&output.stderr).trim().to_string()).into(),
+        );
+    }
+    Ok(lines)
+}
+
+/// fixture builds throwaway repositories for tests
+#[cfg(test)]
+pub mod fixture {
+    use crate::grokloc::app::admin::repository::Repository;
+    use crate::grokloc::app::state;
+    use crate::grokloc::safe;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::process::Command;
+
+    /// repository creates a repository in the root org of app, with an empty
+    /// repository at its clone path
+    pub async fn repository(app: &state::App) -> Result<(Repository, Repo), anyhow::Error> {
+        let repository = Repository::create(
+            &app.master_pool,
+            &app.root_user.id,
+            &safe::VarChar::rand(),
+            &app.root_org.id,
+            &safe::VarChar::rand(),
+            &app.repo_base,
+        )
+        .await?;
+        let repo = Repo::init(Path::new(&repository.path.to_string()));
+        Ok((repository, repo))
+    }
+
+    /// Repo is a repository at path, removed on drop
+    pub struct Repo {
+        pub path: PathBuf,
+    }
+
+    impl Repo {
+        /// init creates an empty repository at path
+        pub fn init(path: &Path) -> Self {
+            fs::create_dir_all(path).unwrap();
+            let repo = Repo {
+                path: path.to_path_buf(),
+            };
+            repo.git(&["init", "-q", "-b", "main"], &[]);
+            repo
+        }
+
+        /// write sets the content of the file at path, creating directories
+        pub fn write(&self, path: &str, content: &str) {
+            let path = self.path.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+
+        /// remove deletes the file at path
+        pub fn remove(&self, path: &str) {
+            fs::remove_file(self.path.join(path)).unwrap();
+        }
+
+        /// commit records every change as author at time (seconds since the
+        /// epoch), returning the commit id
+        pub fn commit(&self, author: (&str, &str), time: i64) -> String {
+            let date = format!("{} +0000", time);
+            let env = [
+                ("GIT_AUTHOR_NAME", author.0),
+                ("GIT_AUTHOR_EMAIL", author.1),
+                ("GIT_AUTHOR_DATE", date.as_str()),
+                ("GIT_COMMITTER_NAME", author.0),
+                ("GIT_COMMITTER_EMAIL", author.1),
+                ("GIT_COMMITTER_DATE", date.as_str()),
+            ];
+            self.git(&["add", "-A"], &env);
+            self.git(&["commit", "-q", "--allow-empty", "-m", "change"], &env);
+            self.git(&["rev-parse", "HEAD"], &env).trim().to_string()
+        }
+
+        /// git runs a git command in the repository, returning stdout
+        pub fn git(&self, args: &[&str], env: &[(&str, &str)]) -> String {
+            let output = Command::new("git")
+                .arg("-C")
+                .arg(&self.path)
+                .args(["-c", "commit.gpgsign=false"])
+                .args(args)
+                .envs(env.iter().copied())
+                .output()
+                .unwrap();
+            assert!(
+                output.status.success(),
+                "{}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+            String::from_utf8(output.stdout).unwrap()
+        }
+    }
+
+    impl Drop for Repo {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grokloc::crypt;
+
+    #[tokio::test]
+    async fn git_tree_blobs_test() -> Result<(), anyhow::Error> {
+        let repo = fixture::Repo::init(&std::env::temp_dir().join(crypt::rand_hex()));
+        repo.write("a.rs", "fn main() {}\n");
+        repo.write("dir/b.txt", "");
+        repo.write("dir/c d.txt", "with\nspace\n");
+        let commit = repo.commit(("A", "a@example.com"), 1_600_000_000);
+
+        assert_eq!(commit, rev_parse(&repo.path, "HEAD").await?);
+        assert_eq!(commit, rev_parse(&repo.path, "main").await?);
+        assert!(rev_parse(&repo.path, "--help").await.is_err());
+        assert!(rev_parse(&repo.path, "missing").await.is_err());
+
+        let second = repo.commit(("A", "a@example.com"), 1_600_000_100);
+        assert_eq!(
+            vec![(commit.clone(), 1_600_000_000), (second, 1_600_000_100)],
+            first_parent(&repo.path, "HEAD").await?
+        );
+
+        let log = log_numstat(&repo.path, "HEAD").await?;
+        assert_eq!(2, log.len());
+        assert!(log[0].changes.is_empty());
+        assert_eq!("A", log[1].author_name);
+        assert_eq!("a@example.com", log[1].author_email);
+        assert_eq!(1_600_000_000, log[1].time);
+        assert_eq!(
+            vec![
+                FileChange {
+                    path: "a.rs".to_string(),
+                    added: 1,
+                    removed: 0
+                },
+                // an empty file is changed without adding lines
+                FileChange {
+                    path: "dir/b.txt".to_string(),
+                    added: 0,
+                    removed: 0
+                },
+                FileChange {
+                    path: "dir/c d.txt".to_string(),
+                    added: 2,
+                    removed: 0
+                },
+            ],
+            log[1].changes
+        );
+
+        repo.write("dir/c d.txt", "with\nspace\nmore\n");
+        repo.git(&["mv", "a.rs", "b.rs"], &[]);
+        repo.remove("dir/b.txt");
+        repo.write("new.rs", "fn new() {}\n");
+        let third = repo.commit(("A", "a@example.com"), 1_600_000_200);
+        assert_eq!(
+            vec![
+                Change::Renamed("a.rs".to_string(), "b.rs".to_string()),
+                Change::Deleted("dir/b.txt".to_string()),
+                Change::Modified("dir/c d.txt".to_string()),
+                Change::Added("new.rs".to_string()),
+            ],
+            diff_name_status(&repo.path, &commit, &third).await?
+        );
+        assert!(diff_name_status(&repo.path, &commit, &commit)
+            .await?
+            .is_empty());
+
+        repo.write("new.rs", "fn new() {}\nfn b() {}\n");
+        let fourth = repo.commit(("B", "b@example.com"), 1_600_000_300);
+        assert_eq!(
+            vec![
+                BlameLines {
+                    author_name: "A".to_string(),
+                    author_email: "a@example.com".to_string(),
+                    author_time: 1_600_000_200,
+                    lines: 1
+                },
+                BlameLines {
+                    author_name: "B".to_string(),
+                    author_email: "b@example.com".to_string(),
+                    author_time: 1_600_000_300,
+                    lines: 1
+                },
+            ],
+            blame(&repo.path, &fourth, "new.rs").await?
+        );
+        assert!(blame(&repo.path, &fourth, "missing.rs").await.is_err());
+
+        let entries = ls_tree(&repo.path, &commit).await?;
+        let paths: Vec<_> = entries.iter().map(|v| v.path.as_str()).collect();
+        assert_eq!(vec!["a.rs", "dir/b.txt", "dir/c d.txt"], paths);
+
+        let blobs: Vec<String> = entries.iter().map(|v| v.blob.clone()).collect();
+        let contents = cat_blobs(&repo.path, &blobs).await?;
+        assert_eq!(b"fn main() {}\n".to_vec(), contents[0]);
+        assert!(contents[1].is_empty());
+        assert_eq!(b"with\nspace\n".to_vec(), contents[2]);
+        assert_eq!(
+            vec![b"fn main() {}".to_vec(), Vec::new(), b"with".to_vec()],
+            first_lines(&repo.path, &blobs).await?
+        );
+
+        Ok(())
+    }
+}
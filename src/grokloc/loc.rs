@@ -0,0 +1,915 @@
+//! loc classifies source lines as code, comment or blank
+use crate::grokloc::crypt;
+use crate::grokloc::safe;
+use serde::{Deserialize, Serialize};
+use std::ops::{AddAssign, Sub};
+use thiserror::Error;
+
+/// BINARY_PROBE_LEN is how much of a file is searched for NUL bytes
+/// to decide that it is binary
+pub const BINARY_PROBE_LEN: usize = 8000;
+
+/// NAME_MAX is the longest permitted language name
+pub const NAME_MAX: usize = 64;
+
+/// Err covers language definition errors
+#[derive(Debug, Error, PartialEq)]
+pub enum Err {
+    #[error("bad language name")]
+    Name,
+    #[error("language name is built in")]
+    BuiltinName,
+    #[error("language matches no files")]
+    NoMatch,
+    #[error("bad file match")]
+    Match,
+    #[error("bad comment or string marker")]
+    Marker,
+}
+
+/// Language describes how to recognise a source language, and how its
+/// comments and strings are delimited
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Language {
+    pub name: String,
+    /// without the leading dot, matched case insensitively
+    pub extensions: Vec<String>,
+    pub filenames: Vec<String>,
+    /// interpreter names, matched for files without an extension
+    pub shebangs: Vec<String>,
+    pub line_comments: Vec<String>,
+    /// (start, end) pairs
+    pub block_comments: Vec<(String, String)>,
+    /// block comments may contain block comments
+    pub nested_comments: bool,
+    /// strings open and close with the same delimiter; \ escapes
+    pub string_delimiters: Vec<String>,
+    /// ' opens a character literal if it closes one character or escape
+    /// later; any other ', such as that of a Rust lifetime, is code
+    #[serde(default)]
+    pub char_literals: bool,
+}
+
+impl Language {
+    /// validate checks that the definition can match files and that every
+    /// marker is usable
+    pub fn validate(&self) -> Result<(), Err> {
+        if !safe::string_ok(&self.name) || self.name.len() > NAME_MAX {
+            return Err(Err::Name);
+        }
+        if self.extensions.is_empty() && self.filenames.is_empty() && self.shebangs.is_empty() {
+            return Err(Err::NoMatch);
+        }
+        let bad_match = |v: &String, reserved: &[char]| {
+            v.is_empty() || v.contains(|c: char| c.is_whitespace() || reserved.contains(&c))
+        };
+        if self.extensions.iter().any(|v| bad_match(v, &['.', '/']))
+            || self.filenames.iter().any(|v| bad_match(v, &['/']))
+            || self.shebangs.iter().any(|v| bad_match(v, &['/']))
+        {
+            return Err(Err::Match);
+        }
+        let bad_marker = |v: &String| v.is_empty() || v.contains(char::is_whitespace);
+        if self.line_comments.iter().any(bad_marker)
+            || self
+                .block_comments
+                .iter()
+                .any(|(start, end)| bad_marker(start) || bad_marker(end))
+            || self.string_delimiters.iter().any(bad_marker)
+        {
+            return Err(Err::Marker);
+        }
+        Ok(())
+    }
+
+    /// digest identifies the definition, so counts cached under one
+    /// definition are not reused when it changes
+    pub fn digest(&self) -> String {
+        crypt::sha256_hex(&serde_json::to_string(self).unwrap_or_default())
+    }
+}
+
+/// Spec is the static form of a built-in Language
+struct Spec {
+    name: &'static str,
+    extensions: &'static [&'static str],
+    filenames: &'static [&'static str],
+    shebangs: &'static [&'static str],
+    line_comments: &'static [&'static str],
+    block_comments: &'static [(&'static str, &'static str)],
+    nested_comments: bool,
+    string_delimiters: &'static [&'static str],
+    char_literals: bool,
+}
+
+impl From<&Spec> for Language {
+    fn from(spec: &Spec) -> Self {
+        let strings = |v: &[&str]| v.iter().map(|v| v.to_string()).collect();
+        Language {
+            name: spec.name.to_string(),
+            extensions: strings(spec.extensions),
+            filenames: strings(spec.filenames),
+            shebangs: strings(spec.shebangs),
+            line_comments: strings(spec.line_comments),
+            block_comments: spec
+                .block_comments
+                .iter()
+                .map(|(start, end)| (start.to_string(), end.to_string()))
+                .collect(),
+            nested_comments: spec.nested_comments,
+            string_delimiters: strings(spec.string_delimiters),
+            char_literals: spec.char_literals,
+        }
+    }
+}
+
+const C_LINE: &[&str] = &["//"];
+const C_BLOCK: &[(&str, &str)] = &[("/*", "*/")];
+const HASH_LINE: &[&str] = &["#"];
+const NONE: &[&str] = &[];
+const NONE_BLOCK: &[(&str, &str)] = &[];
+const DOUBLE_QUOTE: &[&str] = &["\""];
+const QUOTES: &[&str] = &["\"", "'"];
+const JS_QUOTES: &[&str] = &["\"", "'", "`"];
+const TRIPLE_QUOTES: &[&str] = &["\"\"\"", "'''", "\"", "'"];
+
+/// BUILTIN is the table of languages recognised in every org
+const BUILTIN: &[Spec] = &[
+    Spec {
+        name: "C",
+        extensions: &["c", "h"],
+        filenames: NONE,
+        shebangs: NONE,
+        line_comments: C_LINE,
+        block_comments: C_BLOCK,
+        nested_comments: false,
+        string_delimiters: DOUBLE_QUOTE,
+        char_literals: true,
+    },
+    Spec {
+        name: "C++",
+        extensions: &["cc", "cpp", "cxx", "hh", "hpp", "hxx"],
+        filenames: NONE,
+        shebangs: NONE,
+        line_comments: C_LINE,
+        block_comments: C_BLOCK,
+        nested_comments: false,
+        string_delimiters: DOUBLE_QUOTE,
+        char_literals: true,
+    },
+    Spec {
+        name: "CSS",
+        extensions: &["css"],
+        filenames: NONE,
+        shebangs: NONE,
+        line_comments: NONE,
+        block_comments: C_BLOCK,
+        nested_comments: false,
+        string_delimiters: QUOTES,
+        char_literals: false,
+    },
+    Spec {
+        name: "Go",
+        extensions: &["go"],
+        filenames: NONE,
+        shebangs: NONE,
+        line_comments: C_LINE,
+        block_comments: C_BLOCK,
+        nested_comments: false,
+        string_delimiters: &["\"", "`"],
+        char_literals: true,
+    },
+    Spec {
+        name: "HTML",
+        extensions: &["htm", "html"],
+        filenames: NONE,
+        shebangs: NONE,
+        line_comments: NONE,
+        block_comments: &[("<!--", "-->")],
+        nested_comments: false,
+        string_delimiters: NONE,
+        char_literals: false,
+    },
+    Spec {
+        name: "Java",
+        extensions: &["java"],
+        filenames: NONE,
+        shebangs: NONE,
+        line_comments: C_LINE,
+        block_comments: C_BLOCK,
+        nested_comments: false,
+        string_delimiters: DOUBLE_QUOTE,
+        char_literals: true,
+    },
+    Spec {
+        name: "JavaScript",
+        extensions: &["cjs", "js", "jsx", "mjs"],
+        filenames: NONE,
+        shebangs: &["node"],
+        line_comments: C_LINE,
+        block_comments: C_BLOCK,
+        nested_comments: false,
+        string_delimiters: JS_QUOTES,
+        char_literals: false,
+    },
+    Spec {
+        name: "JSON",
+        extensions: &["json"],
+        filenames: NONE,
+        shebangs: NONE,
+        line_comments: NONE,
+        block_comments: NONE_BLOCK,
+        nested_comments: false,
+        string_delimiters: DOUBLE_QUOTE,
+        char_literals: false,
+    },
+    Spec {
+        name: "Makefile",
+        extensions: &["mk"],
+        filenames: &["Makefile", "makefile", "GNUmakefile"],
+        shebangs: NONE,
+        line_comments: HASH_LINE,
+        block_comments: NONE_BLOCK,
+        nested_comments: false,
+        string_delimiters: NONE,
+        char_literals: false,
+    },
+    Spec {
+        name: "Markdown",
+        extensions: &["md"],
+        filenames: NONE,
+        shebangs: NONE,
+        line_comments: NONE,
+        block_comments: NONE_BLOCK,
+        nested_comments: false,
+        string_delimiters: NONE,
+        char_literals: false,
+    },
+    Spec {
+        name: "Python",
+        extensions: &["py"],
+        filenames: NONE,
+        shebangs: &["python", "python2", "python3"],
+        line_comments: HASH_LINE,
+        block_comments: NONE_BLOCK,
+        nested_comments: false,
+        string_delimiters: TRIPLE_QUOTES,
+        char_literals: false,
+    },
+    Spec {
+        name: "Ruby",
+        extensions: &["rb"],
+        filenames: &["Gemfile", "Rakefile"],
+        shebangs: &["ruby"],
+        line_comments: HASH_LINE,
+        block_comments: &[("=begin", "=end")],
+        nested_comments: false,
+        string_delimiters: QUOTES,
+        char_literals: false,
+    },
+    Spec {
+        name: "Rust",
+        extensions: &["rs"],
+        filenames: NONE,
+        shebangs: NONE,
+        line_comments: C_LINE,
+        block_comments: C_BLOCK,
+        nested_comments: true,
+        string_delimiters: DOUBLE_QUOTE,
+        char_literals: true,
+    },
+    Spec {
+        name: "Shell",
+        extensions: &["bash", "sh", "zsh"],
+        filenames: NONE,
+        shebangs: &["bash", "sh", "zsh"],
+        line_comments: HASH_LINE,
+        block_comments: NONE_BLOCK,
+        nested_comments: false,
+        string_delimiters: QUOTES,
+        char_literals: false,
+    },
+    Spec {
+        name: "SQL",
+        extensions: &["sql"],
+        filenames: NONE,
+        shebangs: NONE,
+        line_comments: &["--"],
+        block_comments: C_BLOCK,
+        nested_comments: false,
+        string_delimiters: &["'"],
+        char_literals: false,
+    },
+    Spec {
+        name: "TOML",
+        extensions: &["toml"],
+        filenames: NONE,
+        shebangs: NONE,
+        line_comments: HASH_LINE,
+        block_comments: NONE_BLOCK,
+        nested_comments: false,
+        string_delimiters: TRIPLE_QUOTES,
+        char_literals: false,
+    },
+    Spec {
+        name: "TypeScript",
+        extensions: &["ts", "tsx"],
+        filenames: NONE,
+        shebangs: NONE,
+        line_comments: C_LINE,
+        block_comments: C_BLOCK,
+        nested_comments: false,
+        string_delimiters: JS_QUOTES,
+        char_literals: false,
+    },
+    Spec {
+        name: "YAML",
+        extensions: &["yaml", "yml"],
+        filenames: NONE,
+        shebangs: NONE,
+        line_comments: HASH_LINE,
+        block_comments: NONE_BLOCK,
+        nested_comments: false,
+        string_delimiters: NONE,
+        char_literals: false,
+    },
+];
+
+/// is_builtin reports a built-in language name
+pub fn is_builtin(name: &str) -> bool {
+    BUILTIN.iter().any(|v| v.name == name)
+}
+
+/// shebang finds the interpreter named by the #! line starting content,
+/// looking through env
+pub fn shebang(content: &[u8]) -> Option<String> {
+    let first = content.split(|v| *v == b'\n').next()?;
+    let line = String::from_utf8_lossy(first.strip_prefix(b"#!")?).to_string();
+    let mut words = line.split_whitespace();
+    let mut interpreter = words.next()?.rsplit('/').next()?;
+    if interpreter == "env" {
+        interpreter = words.find(|v| !v.starts_with('-') && !v.contains('='))?;
+    }
+    Some(interpreter.to_string())
+}
+
+/// has_extension reports a file name with an extension; only files without
+/// one are recognised by shebang
+pub fn has_extension(path: &str) -> bool {
+    let filename = path.rsplit('/').next().unwrap_or(path);
+    matches!(filename.rsplit_once('.'), Some((stem, _)) if !stem.is_empty())
+}
+
+/// Languages is the set of languages recognised in an org: its custom
+/// definitions, which take precedence, then the built-in table
+#[derive(Clone, Debug)]
+pub struct Languages(Vec<Language>);
+
+impl Languages {
+    /// builtin is the set of built-in languages alone
+    #[allow(dead_code)]
+    pub fn builtin() -> Self {
+        Self::with_custom(Vec::new())
+    }
+
+    /// with_custom is the set of custom languages, then built-in languages
+    pub fn with_custom(custom: Vec<Language>) -> Self {
+        let mut languages = custom;
+        languages.extend(BUILTIN.iter().map(Language::from));
+        Languages(languages)
+    }
+
+    /// detect finds the language of the file at path by file name, then
+    /// extension
+    pub fn detect(&self, path: &str) -> Option<&Language> {
+        let filename = path.rsplit('/').next().unwrap_or(path);
+        if let Some(language) = self
+            .0
+            .iter()
+            .find(|v| v.filenames.iter().any(|v| v == filename))
+        {
+            return Some(language);
+        }
+        if !has_extension(filename) {
+            return None;
+        }
+        let (_, extension) = filename.rsplit_once('.')?;
+        self.0.iter().find(|v| {
+            v.extensions
+                .iter()
+                .any(|v| v.eq_ignore_ascii_case(extension))
+        })
+    }
+
+    /// detect_shebang finds the language of content by its #! line
+    #[allow(dead_code)]
+    pub fn detect_shebang(&self, content: &[u8]) -> Option<&Language> {
+        self.detect_interpreter(&shebang(content)?)
+    }
+
+    /// detect_interpreter finds the language run by interpreter (see shebang)
+    pub fn detect_interpreter(&self, interpreter: &str) -> Option<&Language> {
+        self.0
+            .iter()
+            .find(|v| v.shebangs.iter().any(|v| v == interpreter))
+    }
+}
+
+/// Counts are line counts; a line with both code and a comment is code
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Counts {
+    pub code: i64,
+    pub comment: i64,
+    pub blank: i64,
+}
+
+impl Counts {
+    /// lines is the total line count
+    #[allow(dead_code)]
+    pub fn lines(&self) -> i64 {
+        self.code + self.comment + self.blank
+    }
+}
+
+impl AddAssign for Counts {
+    fn add_assign(&mut self, other: Self) {
+        self.code += other.code;
+        self.comment += other.comment;
+        self.blank += other.blank;
+    }
+}
+
+impl Sub for Counts {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        Counts {
+            code: self.code - other.code,
+            comment: self.comment - other.comment,
+            blank: self.blank - other.blank,
+        }
+    }
+}
+
+/// is_binary reports content with a NUL byte near its start
+pub fn is_binary(content: &[u8]) -> bool {
+    content[..content.len().min(BINARY_PROBE_LEN)].contains(&0)
+}
+
+/// State is what an unterminated construct carries over to the next line
+#[derive(Copy, Clone)]
+enum State<'a> {
+    Code,
+    /// a block comment with (start, end) markers, nested depth deep
+    Block(&'a str, &'a str, usize),
+    /// a string opened by the delimiter
+    Str(&'a str),
+}
+
+/// Token is a construct that starts in code
+enum Token<'a> {
+    Line,
+    Char,
+    Block(&'a str, &'a str),
+    Str(&'a str),
+}
+
+/// string_end finds the closing delimiter in rest, skipping escapes
+fn string_end(rest: &str, delimiter: &str) -> Option<usize> {
+    let mut chars = rest.char_indices();
+    while let Some((i, c)) = chars.next() {
+        if c == '\\' {
+            chars.next();
+        } else if rest[i..].starts_with(delimiter) {
+            return Some(i);
+        }
+    }
+    None
+}
+
+/// CHAR_ESCAPE_MAX is the most characters after \ in a character literal,
+/// as in '\u{10FFFF}'
+const CHAR_ESCAPE_MAX: usize = 9;
+
+/// char_literal finds the length of the character literal starting rest,
+/// or None if the ' at its start does not open one
+fn char_literal(rest: &str) -> Option<usize> {
+    let mut chars = rest.strip_prefix('\'')?.char_indices();
+    let close = match chars.next()? {
+        (_, '\'') => return None,
+        (_, '\\') => {
+            chars.next()?;
+            chars.take(CHAR_ESCAPE_MAX).find(|v| v.1 == '\'')?.0
+        }
+        _ => chars.next().filter(|v| v.1 == '\'')?.0,
+    };
+    Some(close + 2)
+}
+
+/// next_token finds the first construct starting in rest, preferring the
+/// longest marker where several start together
+fn next_token<'a>(language: &'a Language, rest: &str) -> Option<(usize, usize, Token<'a>)> {
+    let line = language
+        .line_comments
+        .iter()
+        .map(|v| (v.as_str(), Token::Line));
+    let block = language
+        .block_comments
+        .iter()
+        .map(|(start, end)| (start.as_str(), Token::Block(start, end)));
+    let string = language
+        .string_delimiters
+        .iter()
+        .map(|v| (v.as_str(), Token::Str(v)));
+    let char_literal = language
+        .char_literals
+        .then_some(("'", Token::Char))
+        .into_iter();
+    line.chain(block)
+        .chain(string)
+        .chain(char_literal)
+        .filter_map(|(marker, token)| rest.find(marker).map(|i| (i, marker.len(), token)))
+        .min_by_key(|v| (v.0, std::cmp::Reverse(v.1)))
+}
+
+/// scan classifies each line of text in language, passing on_code each
+/// fragment of code outside comments and the end of each line
+///
+/// a string is passed as its opening delimiter, without its content
+fn scan(language: &Language, text: &str, mut on_code: impl FnMut(&str)) -> Counts {
+    let mut counts = Counts::default();
+    let mut state = State::Code;
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() {
+            counts.blank += 1;
+            continue;
+        }
+        let (mut code, mut comment) = (false, false);
+        let mut rest = line;
+        while !rest.is_empty() {
+            match state {
+                State::Block(start, end, depth) => {
+                    comment = true;
+                    let next_start = match language.nested_comments {
+                        true => rest.find(start),
+                        false => None,
+                    };
+                    match (next_start, rest.find(end)) {
+                        (Some(i), next_end) if next_end.is_none_or(|v| i < v) => {
+                            rest = &rest[i + start.len()..];
+                            state = State::Block(start, end, depth + 1);
+                        }
+                        (_, Some(i)) => {
+                            rest = &rest[i + end.len()..];
+                            state = match depth {
+                                1 => State::Code,
+                                _ => State::Block(start, end, depth - 1),
+                            };
+                        }
+                        _ => rest = "",
+                    }
+                }
+                State::Str(delimiter) => {
+                    code = true;
+                    match string_end(rest, delimiter) {
+                        Some(i) => {
+                            rest = &rest[i + delimiter.len()..];
+                            state = State::Code;
+                        }
+                        None => rest = "",
+                    }
+                }
+                State::Code => match next_token(language, rest) {
+                    None => {
+                        code = true;
+                        on_code(rest);
+                        rest = "";
+                    }
+                    Some((i, len, token)) => {
+                        code |= !rest[..i].trim().is_empty();
+                        match token {
+                            Token::Line => {
+                                on_code(&rest[..i]);
+                                comment = true;
+                                rest = "";
+                            }
+                            Token::Block(start, end) => {
+                                on_code(&rest[..i]);
+                                comment = true;
+                                rest = &rest[i + len..];
+                                state = State::Block(start, end, 1);
+                            }
+                            Token::Char => {
+                                // passed like a string, as its opening quote
+                                on_code(&rest[..i + len]);
+                                code = true;
+                                rest = &rest[i + char_literal(&rest[i..]).unwrap_or(len)..];
+                            }
+                            Token::Str(delimiter) => {
+                                on_code(&rest[..i + len]);
+                                code = true;
+                                rest = &rest[i + len..];
+                                state = State::Str(delimiter);
+                            }
+                        }
+                    }
+                },
+            }
+        }
+        on_code("\n");
+        if code {
+            counts.code += 1;
+        } else if comment {
+            counts.comment += 1;
+        }
+    }
+    counts
+}
+
+/// count classifies each line of content in language, or None if binary
+///
+/// lines within strings are code, and comment markers within strings are
+/// not comments
+pub fn count(language: &Language, content: &[u8]) -> Option<Counts> {
+    if is_binary(content) {
+        return None;
+    }
+    Some(scan(language, &String::from_utf8_lossy(content), |_| ()))
+}
+
+/// code is the code of content in language with comments removed and
+/// strings emptied, or None if binary; each line that is not blank ends
+/// with a line break
+pub fn code(language: &Language, content: &[u8]) -> Option<String> {
+    if is_binary(content) {
+        return None;
+    }
+    let mut code = String::new();
+    scan(language, &String::from_utf8_lossy(content), |v| {
+        // fragments either side of a comment are kept apart
+        if code.ends_with(|c: char| !c.is_whitespace()) && !v.starts_with(char::is_whitespace) {
+            code.push(' ');
+        }
+        code.push_str(v);
+    });
+    Some(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn language(name: &str) -> Language {
+        Language {
+            name: name.to_string(),
+            extensions: vec!["dsl".to_string()],
+            line_comments: vec![";".to_string()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn detect_test() {
+        let languages = Languages::builtin();
+        let detect = |path| languages.detect(path).map(|v| v.name.as_str());
+        assert_eq!(Some("Rust"), detect("src/main.rs"));
+        assert_eq!(Some("C++"), detect("a/b/x.HPP"));
+        assert_eq!(Some("Makefile"), detect("build/Makefile"));
+        assert_eq!(None, detect(".rs"));
+        assert_eq!(None, detect("README"));
+        assert_eq!(None, detect("image.png"));
+        assert_eq!(None, detect("x.dsl"));
+
+        let shebang = |content: &[u8]| languages.detect_shebang(content).map(|v| v.name.as_str());
+        assert_eq!(
+            Some("Python"),
+            shebang(b"#!/usr/bin/env python3\nprint(1)\n")
+        );
+        assert_eq!(Some("Shell"), shebang(b"#!/bin/bash -e\n"));
+        assert_eq!(Some("JavaScript"), shebang(b"#!/usr/bin/env -S node\n"));
+        assert_eq!(None, shebang(b"#!/usr/bin/perl\n"));
+        assert_eq!(None, shebang(b"# not a shebang\n"));
+        assert!(has_extension("a/x.sh"));
+        assert!(!has_extension("a.d/run"));
+        assert!(!has_extension(".profile"));
+
+        // custom definitions take precedence
+        let mut custom = language("Dsl");
+        custom.extensions.push("H".to_string());
+        let languages = Languages::with_custom(vec![custom]);
+        assert_eq!(
+            Some("Dsl"),
+            languages.detect("x.dsl").map(|v| v.name.as_str())
+        );
+        assert_eq!(
+            Some("Dsl"),
+            languages.detect("x.h").map(|v| v.name.as_str())
+        );
+    }
+
+    #[test]
+    fn validate_test() {
+        for language in Languages::builtin().0 {
+            assert_eq!(Ok(()), language.validate(), "{}", language.name);
+            assert!(is_builtin(&language.name));
+        }
+        assert!(!is_builtin("Dsl"));
+        assert_eq!(Ok(()), language("Dsl").validate());
+
+        let invalid = [
+            (Err::Name, Language { ..language("") }),
+            (
+                Err::Name,
+                Language {
+                    ..language("<dsl>")
+                },
+            ),
+            (
+                Err::NoMatch,
+                Language {
+                    extensions: vec![],
+                    ..language("Dsl")
+                },
+            ),
+            (
+                Err::Match,
+                Language {
+                    extensions: vec![".dsl".to_string()],
+                    ..language("Dsl")
+                },
+            ),
+            (
+                Err::Match,
+                Language {
+                    shebangs: vec!["/bin/dsl".to_string()],
+                    ..language("Dsl")
+                },
+            ),
+            (
+                Err::Marker,
+                Language {
+                    block_comments: vec![("(*".to_string(), "".to_string())],
+                    ..language("Dsl")
+                },
+            ),
+            (
+                Err::Marker,
+                Language {
+                    string_delimiters: vec![" ".to_string()],
+                    ..language("Dsl")
+                },
+            ),
+        ];
+        for (err, language) in invalid {
+            assert_eq!(Err(err), language.validate());
+        }
+
+        // a changed definition has a different digest
+        let mut changed = language("Dsl");
+        assert_eq!(changed.digest(), language("Dsl").digest());
+        changed.nested_comments = true;
+        assert_ne!(changed.digest(), language("Dsl").digest());
+    }
+
+    #[test]
+    fn count_test() {
+        let languages = Languages::builtin();
+        let rust = languages.detect("x.rs").unwrap();
+        let source = "// header\n\
+                      \n\
+                      fn main() { // trailing\n\
+                      \x20   /* block\n\
+                      \x20      still block */ let x = 1;\n\
+                      \x20   /* one line */\n\
+                      }\n";
+        assert_eq!(
+            Some(Counts {
+                code: 3,
+                comment: 3,
+                blank: 1
+            }),
+            count(rust, source.as_bytes())
+        );
+
+        let python = languages.detect("x.py").unwrap();
+        let counts = count(python, b"# c\nx = 1\n\n  # c\n").unwrap();
+        assert_eq!(
+            Counts {
+                code: 1,
+                comment: 2,
+                blank: 1
+            },
+            counts
+        );
+        assert_eq!(4, counts.lines());
+
+        assert_eq!(None, count(rust, b"fn\0main"));
+        assert_eq!(Some(Counts::default()), count(rust, b""));
+    }
+
+    #[test]
+    fn count_strings_nested_test() {
+        let languages = Languages::builtin();
+
+        // comment markers in strings, including escaped quotes
+        let rust = languages.detect("x.rs").unwrap();
+        let source = "let url = \"http://x\";\n\
+                      let q = \"\\\" /* \";\n\
+                      let s = \"multi\n\
+                      // still string\n\
+                      \";\n";
+        assert_eq!(
+            Some(Counts {
+                code: 5,
+                comment: 0,
+                blank: 0
+            }),
+            count(rust, source.as_bytes())
+        );
+
+        // nested block comments close at the outer end
+        let source = "/* outer /* inner */\n\
+                      still outer */\n\
+                      fn f() {}\n";
+        assert_eq!(
+            Some(Counts {
+                code: 1,
+                comment: 2,
+                blank: 0
+            }),
+            count(rust, source.as_bytes())
+        );
+        let c = languages.detect("x.c").unwrap();
+        let source = "/* outer /* inner */\n\
+                      int x; */\n";
+        assert_eq!(
+            Some(Counts {
+                code: 1,
+                comment: 1,
+                blank: 0
+            }),
+            count(c, source.as_bytes())
+        );
+
+        // character literals, even of quotes, are not strings, and a
+        // lifetime opens nothing
+        let source = "let q = '\"';\n\
+                      // one\n\
+                      let e = ['\\'', '\\u{1F600}', 'x'];\n\
+                      /* two */\n\
+                      fn f<'a>(x: &'a str) -> &'a str { x }\n\
+                      // three\n";
+        assert_eq!(
+            Some(Counts {
+                code: 3,
+                comment: 3,
+                blank: 0
+            }),
+            count(rust, source.as_bytes())
+        );
+        let java = languages.detect("A.java").unwrap();
+        let source = "char q = '\"';\n\
+                      // one\n\
+                      /* two */\n";
+        assert_eq!(
+            Some(Counts {
+                code: 1,
+                comment: 2,
+                blank: 0
+            }),
+            count(java, source.as_bytes())
+        );
+
+        // the longest delimiter wins, so docstrings span lines
+        let python = languages.detect("x.py").unwrap();
+        let source = "\"\"\"doc\n\
+                      # not a comment\n\
+                      \"\"\"\n\
+                      x = '#'\n";
+        assert_eq!(
+            Some(Counts {
+                code: 4,
+                comment: 0,
+                blank: 0
+            }),
+            count(python, source.as_bytes())
+        );
+    }
+
+    #[test]
+    fn code_test() {
+        let languages = Languages::builtin();
+        let rust = languages.detect("x.rs").unwrap();
+        let source = "// if\n\
+                      fn a() { /* if */ f(\"if {\") }\n\
+                      \n\
+                      /* if\n\
+                      if */ x\n";
+        assert_eq!(
+            Some("\nfn a() {  f(\" ) }\n\n x\n".to_string()),
+            code(rust, source.as_bytes())
+        );
+        assert_eq!(None, code(rust, b"\0"));
+    }
+}
@@ -6,6 +6,9 @@ use thiserror::Error;
 
 pub const STR_MAX: usize = 8192;
 
+/// EMAIL_MAX is the longest permitted email address (RFC 5321)
+pub const EMAIL_MAX: usize = 254;
+
 /// Err abstracts over safe-value error types
 #[derive(Copy, Clone, Debug, Error, PartialEq)]
 pub enum Err {
@@ -33,6 +36,18 @@ pub fn string_ok(s: &str) -> bool {
         || s.is_empty())
 }
 
+/// email_ok makes sure strings are safe and shaped like a single email address
+pub fn email_ok(s: &str) -> bool {
+    let re_email = Regex::new(r"^[^@\s]+@[^@\s]+\.[^@\s.]+$").unwrap();
+    string_ok(s) && s.len() <= EMAIL_MAX && re_email.is_match(s)
+}
+
+/// email_normal is the one form in which emails are compared, digested and
+/// stored, so that addresses differing only in case are the same address
+pub fn email_normal(s: &str) -> String {
+    s.trim().to_lowercase()
+}
+
 /// VarChar is a string container that proves that the value is safe for db storage
 #[derive(Clone, Debug, PartialEq)]
 pub struct VarChar(String);
@@ -79,6 +94,29 @@ mod tests {
         assert!(!string_ok("hello create table"));
     }
 
+    #[test]
+    fn email_ok_test() {
+        assert!(email_ok("user@example.com"));
+        assert!(email_ok("first.last+tag@mail.example.co"));
+        assert!(!email_ok(""));
+        assert!(!email_ok("user"));
+        assert!(!email_ok("user@example"));
+        assert!(!email_ok("user@@example.com"));
+        assert!(!email_ok("us er@example.com"));
+        assert!(!email_ok("user@example.com."));
+        assert!(!email_ok("us'er@example.com"));
+        assert!(!email_ok(&format!("{}@example.com", "a".repeat(EMAIL_MAX))));
+    }
+
+    #[test]
+    fn email_normal_test() {
+        assert_eq!("user@example.com", email_normal("User@Example.COM"));
+        assert_eq!(
+            email_normal("user@example.com"),
+            email_normal(" USER@example.com")
+        );
+    }
+
     #[test]
     fn varchar_ok_test() -> Result<(), Err> {
         assert_eq!(VarChar::new("ok")?.to_string(), "ok");